@@ -5,6 +5,10 @@ use std::cell::RefCell;
 use std::collections::VecDeque;
 use std::rc::Rc;
 
+use pixel_demolition_common::client_message::ClientMessage;
+use pixel_demolition_common::proto::Proto;
+use pixel_demolition_common::server_message::ServerMessage;
+
 
 #[wasm_bindgen]
 extern "C" {
@@ -48,7 +52,17 @@ impl Connection {
 
                 let message = js_sys::Uint8Array::new(&message);
                 let message: Vec<u8> = message.to_vec();
-                message_queue.borrow_mut().push_back(message);
+
+                // The server sends each tick as a bundle, queue what is inside it one by one
+                if Proto::get_type(&message) != Ok(Proto::TCT_BUNDLE) {
+                    message_queue.borrow_mut().push_back(message);
+                    return;
+                }
+
+                match Proto::unbundle(&message) {
+                    Ok(messages) => message_queue.borrow_mut().extend(messages),
+                    Err(err) => log(&err.to_string()),
+                }
             });
 
             ws.set_onmessage(Some(callback.as_ref().unchecked_ref()));
@@ -56,27 +70,48 @@ impl Connection {
         }
 
 
-        while ws.ready_state() != WebSocket::OPEN {
+        // Give up if the server refuses the connection, callers check is_closed()
+        while ws.ready_state() != WebSocket::OPEN && ws.ready_state() != WebSocket::CLOSED {
             let _ = JsFuture::from(Self::sleep()).await;
         }
 
-        return Connection {
+        let connection = Connection {
             ws,
             message_queue,
+        };
+
+        // The server won't listen to anything else until it knows both sides speak the same
+        // protocol
+        if !connection.is_closed() {
+            connection.send(ClientMessage::Hello { protocol_version: Proto::PROTOCOL_VERSION });
         }
+
+        return connection;
     }
 
-    pub fn send(&self, message:Vec<u8>) {
-        self.ws.send_with_u8_array(&message).expect("Unable to send websocket message");
+    pub fn send(&self, message: ClientMessage) {
+        self.ws.send_with_u8_array(&message.encode()).expect("Unable to send websocket message");
     }
 
-    pub fn next_message(&self) -> Option<Vec<u8>> {
+    // Skips over anything that can't be decoded
+    pub fn next_message(&self) -> Option<ServerMessage> {
         let mut message_queue = self.message_queue.borrow_mut();
-        let message = message_queue.pop_front().clone();
-        return message;
+
+        while let Some(message) = message_queue.pop_front() {
+            match ServerMessage::decode(&message) {
+                Ok(message) => return Some(message),
+                Err(err) => log(&err.to_string()),
+            }
+        }
+
+        return None;
     }
 
     pub fn disconnect(&self) {
         let _ = self.ws.close();
     }
+
+    pub fn is_closed(&self) -> bool {
+        return self.ws.ready_state() == WebSocket::CLOSED;
+    }
 }
@@ -1,14 +1,20 @@
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures;
 use js_sys::Date;
+use std::collections::{HashMap, VecDeque};
 use std::str;
 
+use pixel_demolition_common::match_settings::MatchSettings;
 use pixel_demolition_common::player::Player;
 use pixel_demolition_common::projectile::Projectile;
 use pixel_demolition_common::weapon::Weapon;
 use pixel_demolition_common::proto::Proto;
+use pixel_demolition_common::client_message::ClientMessage;
+use pixel_demolition_common::server_message::ServerMessage;
+use pixel_demolition_common::replay::Replay;
 use pixel_demolition_common::vel_system::VelSystem;
-use pixel_demolition_common::collision_system::CollisionSystem;
+use pixel_demolition_common::movement_system::MovementSystem;
+use pixel_demolition_common::player_input::PlayerInput;
 
 use crate::input::Input;
 use crate::graphics::Graphics;
@@ -18,6 +24,7 @@ use crate::states::States;
 use crate::selected::Selected;
 use crate::audio::{Audio, sounds::Sounds};
 use crate::interp_system::InterpSystem;
+use crate::replay_player::ReplayPlayer;
 
 #[wasm_bindgen]
 extern "C" {
@@ -35,21 +42,52 @@ pub struct Engine {
     state:States,
     state_changed:bool,
     players:Vec<Player>,
-    ground_weapons: Vec<Weapon>,
-    projectiles: Vec<Projectile>,
+    // Keyed by the ids the server gives them
+    ground_weapons: HashMap<u32, Weapon>,
+    projectiles: HashMap<u32, Projectile>,
     player_i: Option<usize>,
     selected: Selected,
     last_time: f64,
     name: String,
     code: String,
     jump_debounce: bool,
-    next_angle_update: f32,
+    // Sent to the server but not acked yet, replayed on top of every ack
+    pending_inputs: VecDeque<PlayerInput>,
     // x, y, radius, frames left to live
     flashes: Vec<Flash>,
+    // Shown in the code box the next time the unmatched screen is drawn
+    unmatched_notice: Option<String>,
+    // In ms, set once the server announces it is shutting down
+    shutdown_time_left: Option<f32>,
+    // (code, host name, player count) for each open lobby
+    lobbies: Vec<(String, String, usize)>,
+    next_lobbies_refresh: f32,
+    // Handed out by the server when joining, used to get back into the match after a disconnect
+    resume_token: Option<[u8; Proto::RESUME_TOKEN_LEN]>,
+    next_reconnect: f32,
+    // Sent by the server whenever the lobby changes
+    host_i: usize,
+    lobby_locked: bool,
+    // Rules for the current match, picked by the host in the lobby
+    settings: MatchSettings,
+    // Spectators have no player, the camera follows follow_i instead
+    spectating: bool,
+    follow_i: usize,
+    // Set from the page's ?replay= parameter, the replay is loaded on the first frame
+    replay_name: Option<String>,
+    replay: Option<ReplayPlayer>,
 }
 
-// Send new angles 5/sec
-const ANGLE_UPDATE_PERIOD: f32 = 200.0;
+// Ask the server for open lobbies every 2 seconds
+const LOBBIES_REFRESH_PERIOD: f32 = 2000.0;
+
+// Try to reconnect every second after losing the connection
+const RECONNECT_PERIOD: f32 = 1000.0;
+
+// The page is still running an older or newer client than the server
+const VERSION_MISMATCH_NOTICE: &str = "Game updated, please reload";
+
+const MATCH_ERROR_NOTICE: &str = "Match ended by a server error";
 
 #[wasm_bindgen]
 impl Engine {
@@ -63,6 +101,18 @@ impl Engine {
         let graphics = Graphics::new(&window);
         let input = Input::new();
 
+        let search = window.location().search().unwrap_or_default();
+        let replay_name = search
+            .trim_start_matches('?')
+            .split('&')
+            .find_map(|param| param.strip_prefix("replay="))
+            .map(|name| name.to_string());
+
+        let state = match replay_name {
+            Some(_) => States::Replay,
+            None => States::Unmatched,
+        };
+
         return Engine {
             window,
             graphics,
@@ -70,18 +120,31 @@ impl Engine {
             connection: None,
             audio: None,
             players: Vec::new(),
-            ground_weapons: Vec::new(),
-            projectiles: Vec::new(),
+            ground_weapons: HashMap::new(),
+            projectiles: HashMap::new(),
             player_i: None,
-            state: States::Unmatched,
+            state,
             state_changed: true,
             selected: Selected::Name,
             last_time,
             name: String::new(),
             code: String::new(),
             jump_debounce: false,
-            next_angle_update: 0.0,
+            pending_inputs: VecDeque::new(),
             flashes: Vec::new(),
+            unmatched_notice: None,
+            shutdown_time_left: None,
+            lobbies: Vec::new(),
+            next_lobbies_refresh: 0.0,
+            resume_token: None,
+            next_reconnect: 0.0,
+            host_i: 0,
+            lobby_locked: false,
+            settings: MatchSettings::new(),
+            spectating: false,
+            follow_i: 0,
+            replay_name,
+            replay: None,
         };
     }
 
@@ -95,14 +158,20 @@ impl Engine {
             States::Unmatched => {
                 self.unmatched().await;
             }
+            States::Browser => {
+                self.browser(time_elapsed).await;
+            },
             States::Lobby => {
-                self.lobby();
+                self.lobby(time_elapsed).await;
             },
             States::Game => {
                 self.game(time_elapsed).await;
             },
             States::GameOver => {
                 self.game_over();
+            },
+            States::Replay => {
+                self.replay(time_elapsed).await;
             }
         }
     }
@@ -112,6 +181,10 @@ impl Engine {
             self.graphics.update_canvas(States::Unmatched);
             self.graphics.first_render_unmatched();
             self.state_changed=false;
+
+            if let Some(notice) = self.unmatched_notice.take() {
+                self.graphics.update_render_unmatched_name_code(&self.name, &notice);
+            }
         }
 
         let (_, mouse_coord_y) = self.input.mouse_coordinates();
@@ -119,22 +192,21 @@ impl Engine {
 
         if self.connection.is_some() {
             while let Some(message) = self.connection.as_mut().unwrap().next_message() {
-                let message_type = Proto::get_type(&message);
-
-                if message_type.is_err() {
-                    continue;
-                }
-
-                match message_type.unwrap() {
-                    Proto::TCT_JOIN_EXISTING_RESULT => {
-                        let join_existing_result = Proto::parse_tct_join_existing_result(&message);
-
-                        if join_existing_result.is_err() {
-                            continue;
-                        }
-
-                        match join_existing_result.unwrap() {
+                match message {
+                    ServerMessage::JoinExistingResult { status, resume_token } => {
+                        match status {
                             Proto::JOIN_EXISTING_RESULT_SUCCESS => {
+                                self.resume_token = resume_token;
+                                self.spectating = false;
+                                self.state_changed = true;
+                                self.state = States::Lobby;
+                                break;
+                            },
+                            Proto::JOIN_EXISTING_RESULT_SPECTATING => {
+                                self.resume_token = None;
+                                self.player_i = None;
+                                self.spectating = true;
+                                self.follow_i = 0;
                                 self.state_changed = true;
                                 self.state = States::Lobby;
                                 break;
@@ -146,6 +218,20 @@ impl Engine {
                                 self.connection.as_mut().unwrap().disconnect();
                                 self.connection = None;
                             },
+                            Proto::JOIN_EXISTING_RESULT_FULL => {
+                                self.graphics.update_render_unmatched_name_code(
+                                    &self.name, &String::from("Game full")
+                                );
+                                self.connection.as_mut().unwrap().disconnect();
+                                self.connection = None;
+                            },
+                            Proto::JOIN_EXISTING_RESULT_LOCKED => {
+                                self.graphics.update_render_unmatched_name_code(
+                                    &self.name, &String::from("Lobby locked")
+                                );
+                                self.connection.as_mut().unwrap().disconnect();
+                                self.connection = None;
+                            },
                             Proto::JOIN_EXISTING_RESULT_SERVER_ERROR | _ => {
                                self.graphics.update_render_unmatched_name_code(
                                     &self.name, &String::from("Server error")
@@ -155,31 +241,30 @@ impl Engine {
                             }
                         }
                     },
-                    Proto::TCT_CREATE_NEW_RESULT => {
-                        let create_new_result = Proto::parse_tct_create_new_result(&message);
-
-                        if create_new_result.is_err() {
-                            continue;
-                        }
-
-                        let (status, code) = create_new_result.unwrap();
-
+                    ServerMessage::CreateNewResult { status, code, resume_token } => {
                         match status {
                             Proto::CREATE_NEW_RESULT_SUCCESS => {
                                 self.code = code;
+                                self.resume_token = resume_token;
+                                self.spectating = false;
                                 self.state_changed = true;
                                 self.state = States::Lobby;
                                 break;
                             }
+                            // Usually every match slot is taken, so another try may work
                             Proto::CREATE_NEW_RESULT_SERVER_ERROR | _ => {
                                self.graphics.update_render_unmatched_name_code(
-                                    &self.name, &String::from("Invalid code")
+                                    &self.name, &String::from("Server busy, try again")
                                 );
                                 self.connection.as_mut().unwrap().disconnect();
                                 self.connection = None;
                             }
                         }
-                    }
+                    },
+                    ServerMessage::VersionMismatch { .. } => {
+                        self.leave_match(String::from(VERSION_MISMATCH_NOTICE));
+                        return;
+                    },
                     _ => ()
                 }
             }
@@ -194,14 +279,37 @@ impl Engine {
             },
             y if y > self.graphics.height_divided*6 && y < self.graphics.height_divided*7 => {
                 if mouse_clicked {
-                    let join_existing_message = Proto::tst_join_existing(&self.code, &self.name);
+                    let join_existing_message = ClientMessage::JoinExisting {
+                        code: self.code.clone(),
+                        name: self.name.clone(),
+                    };
                     self.connection = Some(Connection::new(&self.window).await);
                     self.connection.as_mut().unwrap().send(join_existing_message);
                 }
             },
+            y if y > self.graphics.height_divided*7 && y < self.graphics.height_divided*8 => {
+                if mouse_clicked {
+                    self.state = States::Browser;
+                    self.state_changed = true;
+                    return;
+                }
+            },
             y if y > self.graphics.height_divided*8 && y < self.graphics.height_divided*9 => {
                 if mouse_clicked {
-                    let create_new_message = Proto::tst_create_new(&self.name);
+                    let create_new_message = ClientMessage::CreateNew {
+                        public: true,
+                        name: self.name.clone(),
+                    };
+                    self.connection = Some(Connection::new(&self.window).await);
+                    self.connection.as_mut().unwrap().send(create_new_message);
+                }
+            },
+            y if y > self.graphics.height_divided*9 && y < self.graphics.height_divided*10 => {
+                if mouse_clicked {
+                    let create_new_message = ClientMessage::CreateNew {
+                        public: false,
+                        name: self.name.clone(),
+                    };
                     self.connection = Some(Connection::new(&self.window).await);
                     self.connection.as_mut().unwrap().send(create_new_message);
                 }
@@ -211,6 +319,16 @@ impl Engine {
                     self.audio = Some(Audio::new(&self.window).await);
                     self.graphics.update_render_unmatched_audio_enabled();
                 }
+            },
+            y if y > self.graphics.height_divided*11 && y < self.graphics.height_divided*12 => {
+                if mouse_clicked {
+                    let spectate_message = ClientMessage::SpectateExisting {
+                        code: self.code.clone(),
+                        name: self.name.clone(),
+                    };
+                    self.connection = Some(Connection::new(&self.window).await);
+                    self.connection.as_mut().unwrap().send(spectate_message);
+                }
             }
             _ => ()
         }
@@ -237,74 +355,222 @@ impl Engine {
         }
     }
 
-    pub fn lobby(&mut self) {
+    pub async fn browser(&mut self, time_elapsed: f32) {
+        if self.state_changed {
+            self.graphics.update_canvas(States::Browser);
+            self.lobbies.clear();
+            self.graphics.render_browser(&self.lobbies, &String::from("Loading..."));
+            self.next_lobbies_refresh = 0.0;
+            self.state_changed = false;
+
+            if self.connection.is_none() {
+                self.connection = Some(Connection::new(&self.window).await);
+            }
+        }
+
         let (_, mouse_coord_y) = self.input.mouse_coordinates();
         let mouse_clicked = self.input.mouse_clicked();
 
+        self.next_lobbies_refresh -= time_elapsed;
+        if self.next_lobbies_refresh < 0.0 {
+            self.connection.as_mut().unwrap().send(ClientMessage::ListLobbies);
+
+            self.next_lobbies_refresh = LOBBIES_REFRESH_PERIOD;
+        }
+
+        while let Some(message) = self.connection.as_mut().unwrap().next_message() {
+            match message {
+                ServerMessage::LobbyList { lobbies } => {
+                    self.lobbies = lobbies;
+                    self.lobbies.truncate(Graphics::BROWSER_ROWS as usize);
+
+                    let notice = match self.lobbies.len() {
+                        0 => String::from("No open games"),
+                        _ => String::new(),
+                    };
+
+                    self.graphics.render_browser(&self.lobbies, &notice);
+                },
+                ServerMessage::JoinExistingResult { status, resume_token } => {
+                    match status {
+                        Proto::JOIN_EXISTING_RESULT_SUCCESS => {
+                            self.resume_token = resume_token;
+                            self.state_changed = true;
+                            self.state = States::Lobby;
+                            return;
+                        },
+                        _ => {
+                            self.graphics.render_browser(
+                                &self.lobbies,
+                                &String::from("Unable to join game"),
+                            );
+                        }
+                    }
+                },
+                ServerMessage::VersionMismatch { .. } => {
+                    self.leave_match(String::from(VERSION_MISMATCH_NOTICE));
+                    return;
+                },
+                _ => ()
+            }
+        }
+
+        if !mouse_clicked {
+            return;
+        }
+
+        let row = mouse_coord_y / self.graphics.height_divided;
+
+        if row >= 2 && row < 2 + Graphics::BROWSER_ROWS {
+            let lobby_i = (row - 2) as usize;
+
+            if lobby_i < self.lobbies.len() {
+                self.code = self.lobbies[lobby_i].0.clone();
+
+                let join_existing_message = ClientMessage::JoinExisting {
+                    code: self.code.clone(),
+                    name: self.name.clone(),
+                };
+                self.connection.as_mut().unwrap().send(join_existing_message);
+            }
+        } else if row == 11 {
+            self.connection.as_mut().unwrap().disconnect();
+            self.connection = None;
+
+            self.state = States::Unmatched;
+            self.state_changed = true;
+        }
+    }
+
+    pub async fn lobby(&mut self, time_elapsed: f32) {
+        let (mouse_coord_x, mouse_coord_y) = self.input.mouse_coordinates();
+        let mouse_clicked = self.input.mouse_clicked();
+
         if self.state_changed {
-            self.graphics.render_lobby(&self.code, &self.players);
+            self.render_lobby();
             self.state_changed=false;
         }
 
+        if !self.reconnect(time_elapsed).await {
+            return;
+        }
+
         if mouse_coord_y > self.graphics.height_divided*8
             && mouse_coord_y < self.graphics.height_divided*9
             && mouse_clicked 
         {
-            let toggle_ready_message = Proto::tst_toggle_ready();
-            self.connection.as_mut().unwrap().send(toggle_ready_message);
+            self.connection.as_mut().unwrap().send(ClientMessage::ToggleReady);
         }
 
-        while let Some(message) = self.connection.as_mut().unwrap().next_message() {
-            let message_type = Proto::get_type(&message);
-
-            if message_type.is_err() {
-                continue;
-            }
+        if mouse_coord_y > self.graphics.height_divided*9
+            && mouse_coord_y < self.graphics.height_divided*10
+            && mouse_clicked
+        {
+            self.connection.as_mut().unwrap().send(ClientMessage::LeaveMatch);
 
-            match message_type.unwrap() {
-                Proto::TCT_PLAYER_LIST => {
-                    self.players.clear();
+            self.leave_match(String::from("Left lobby"));
+            return;
+        }
 
-                    let player_names = Proto::parse_tct_player_list(&message);
+        if self.player_i == Some(self.host_i) && mouse_clicked {
+            let row = mouse_coord_y / self.graphics.height_divided;
 
-                    if player_names.is_err() {
-                        continue;
-                    }
+            if row >= 3 && row < 3 + self.players.len() as i32 {
+                let kick_i = (row - 3) as usize;
 
-                    let player_names = player_names.unwrap();
+                if kick_i != self.host_i {
+                    let kick_player_message = ClientMessage::KickPlayer { player_i: kick_i };
+                    self.connection.as_mut().unwrap().send(kick_player_message);
+                }
+            } else if row == 10 {
+                self.connection.as_mut().unwrap().send(ClientMessage::ForceStart);
+            } else if row == 11 {
+                self.connection.as_mut().unwrap().send(ClientMessage::LockLobby);
+            } else if let Some(setting_i) = self.graphics.lobby_setting_at(mouse_coord_x, mouse_coord_y) {
+                // Only changed locally once the server sends the new settings back
+                let mut settings = self.settings.clone();
+                settings.cycle(setting_i);
+
+                self.connection.as_mut().unwrap().send(ClientMessage::UpdateSettings(settings));
+            }
+        }
 
+        while let Some(message) = self.connection.as_mut().unwrap().next_message() {
+            match message {
+                ServerMessage::PlayerList { names } => {
                     self.players.clear();
 
-                    for player_name in player_names {
+                    for player_name in names {
                         self.players.push(Player::new(player_name));
                     }
 
 
-                    self.graphics.render_lobby(&self.code, &self.players);
+                    self.render_lobby();
                 },
-                Proto::TCT_TOGGLE_READY => {
-                    let toggle_message = Proto::parse_tct_toggle_ready(&message);
+                ServerMessage::ToggleReady { player_i, ready } => {
+                    self.players[player_i].ready = ready;
 
-                    if toggle_message.is_err() {
-                        continue;
-                    }
+                    self.render_lobby();
+                },
+                ServerMessage::LobbyState { host_i, player_i, locked } => {
+                    self.host_i = host_i;
+                    self.lobby_locked = locked;
 
-                    let (player_i, ready) = toggle_message.unwrap();
+                    if player_i != Proto::SPECTATOR as usize {
+                        self.player_i = Some(player_i);
+                    }
 
-                    self.players[player_i].ready = ready;
+                    self.render_lobby();
+                },
+                ServerMessage::Kicked { reason } => {
+                    self.leave_match(Self::kick_notice(reason));
+                    return;
+                },
+                ServerMessage::MatchSettings(settings) => {
+                    self.settings = settings;
 
-                    self.graphics.render_lobby(&self.code, &self.players);
+                    self.render_lobby();
                 },
-                Proto::TCT_START_GAME => {
-                    let (player_i, x, y) = Proto::parse_tct_start_game(&message).unwrap();
+                ServerMessage::StartGame { player_i, x, y } => {
                     self.player_i = Some(player_i);
                     self.players[player_i].x = x;
                     self.players[player_i].y = y;
 
+                    for player in self.players.iter_mut() {
+                        player.apply_settings(&self.settings);
+                    }
+
                     self.state = States::Game;
                     self.state_changed = true;
                     break;
-                }
+                },
+                ServerMessage::ResumeResult { status, .. } => {
+                    if !self.handle_resume_result(status) {
+                        return;
+                    }
+                },
+                // The game started while this client was reconnecting or it joined a game that
+                // was already running
+                ServerMessage::GameState { player_i, players, weapons, projectiles } => {
+                    self.apply_game_state(player_i, players, weapons, projectiles);
+
+                    self.state = States::Game;
+                    self.state_changed = true;
+                    break;
+                },
+                ServerMessage::ServerShutdown { .. } => {
+                    self.leave_match(String::from("Server shutting down"));
+                    return;
+                },
+                ServerMessage::MatchError => {
+                    self.leave_match(String::from(MATCH_ERROR_NOTICE));
+                    return;
+                },
+                // The server was updated while this client was reconnecting
+                ServerMessage::VersionMismatch { .. } => {
+                    self.leave_match(String::from(VERSION_MISMATCH_NOTICE));
+                    return;
+                },
                 _ => ()
             }
         }
@@ -316,305 +582,677 @@ impl Engine {
             self.state_changed=false;
         }
 
+        let connected = self.reconnect(time_elapsed).await;
+
+        // Gave up on reconnecting
+        if !matches!(self.state, States::Game) {
+            return;
+        }
+
         let (mouse_coord_x, mouse_coord_y) = self.input.mouse_coordinates();
 
-        'game_loop: while let Some(message) = self.connection.as_mut().unwrap().next_message() {
-            let message_type = Proto::get_type(&message);
+        while let Some(message) = self.connection.as_mut().unwrap().next_message() {
+            if !self.handle_game_message(message).await {
+                return;
+            }
+        }
 
-            if message_type.is_err() {
-                continue;
+        // A spectator can be watching a match with nobody left in it
+        if self.players.is_empty() {
+            return;
+        }
+
+        if self.spectating {
+            // Clicking follows the next player
+            if self.input.mouse_clicked() {
+                self.follow_i = (self.follow_i + 1) % self.players.len().max(1);
             }
 
-            match message_type.unwrap() {
-                Proto::TCT_NEW_POS => {
-                    let result = Proto::parse_tct_new_pos(&message);
+            self.follow_i = self.follow_i.min(self.players.len().max(1) - 1);
+        } else {
+            let this_player = &mut self.players[self.player_i.unwrap()];
+
+            // Keep simulating locally while reconnecting but don't send anything
+            if this_player.alive && connected {
+                let mut jump = false;
 
-                    if result.is_err() {
-                        log(result.unwrap_err());
-                        continue;
+                if self.input.is_down('W' as u32) {
+                    if self.jump_debounce == false && this_player.jumps > 0 {
+                        jump = true;
+                        self.jump_debounce = true;
                     }
+                } else {
+                    self.jump_debounce = false;
+                }
 
-                    let (player_i, x, y) = result.unwrap();
+                this_player.input_seq += 1;
 
-                    self.players[player_i].x_last = self.players[player_i].x;
-                    self.players[player_i].y_last = self.players[player_i].y;
+                let input = PlayerInput {
+                    seq: this_player.input_seq,
+                    left: self.input.is_down('A' as u32),
+                    right: self.input.is_down('D' as u32),
+                    jump,
+                    angle: (((Graphics::GAME_CANVAS_HEIGHT/2) - mouse_coord_y) as f32)
+                        .atan2(((Graphics::GAME_CANVAS_WIDTH/2) - mouse_coord_x) as f32),
+                    time_elapsed: time_elapsed.min(PlayerInput::MAX_TIME_ELAPSED),
+                };
 
-                    self.players[player_i].x_new = x;
-                    self.players[player_i].y_new = y;
-                },
-                Proto::TCT_NEW_ANGLE => {
-                    let result = Proto::parse_tct_new_angle(&message);
+                // Move straight away, the server's ack corrects it if it disagrees
+                MovementSystem::apply_input(
+                    this_player,
+                    &input,
+                    &self.graphics.map_context,
+                    &self.settings,
+                );
 
-                    if result.is_err() {
-                        continue;
-                    }
+                self.connection.as_mut().unwrap().send(ClientMessage::Input(input.clone()));
 
-                    let (player_i, angle) = result.unwrap();
+                self.pending_inputs.push_back(input);
 
-                    self.players[player_i].angle = angle;
-                },
-                Proto::TCT_WEAPON_SPAWN => {
-                    let result = Proto::parse_tct_weapon_spawn(&message);
+                if self.input.is_down('E' as u32) {
+                    self.connection.as_mut().unwrap().send(ClientMessage::TakeWeapon);
+                }
 
-                    if result.is_err() {
-                        log(result.unwrap_err());
-                    }
+                let (mouse_state_changed, new_state) = self.input.mouse_state_changed();
+                if mouse_state_changed {
+                    let trigger_message = match new_state {
+                        true => ClientMessage::TriggerPulled,
+                        false => ClientMessage::TriggerReleased,
+                    };
 
-                    log("Weapon spawned");
+                    self.connection.as_mut().unwrap().send(trigger_message);
+                }
+            } else {
+                self.players[self.player_i.unwrap()].time_to_respawn -= time_elapsed as i32;
+            }
+        }
 
-                    let (weapon_type, x, y) = result.unwrap();
-                    
-                    let new_weapon = Weapon::new(weapon_type, x, y);
+        self.update_world(time_elapsed);
 
-                    self.ground_weapons.push(new_weapon);
-                },
-                Proto::TCT_REMOVE_WEAPON => {
-                    let weapon_i = Proto::parse_tct_remove_weapon(&message);
+        self.graphics.render_game(
+            &self.players,
+            self.camera_player_i(),
+            self.spectating,
+            &self.ground_weapons,
+            &self.projectiles,
+            mouse_coord_x,
+            mouse_coord_y,
+            &self.flashes,
+        );
+
+        if let Some(shutdown_time_left) = self.shutdown_time_left {
+            let shutdown_time_left = (shutdown_time_left - time_elapsed).max(0.0);
+            self.graphics.render_game_notice(
+                &format!("Server shutting down in {}s", (shutdown_time_left / 1000.0).ceil()),
+            );
+            self.shutdown_time_left = Some(shutdown_time_left);
+        } else if !connected {
+            self.graphics.render_game_notice(&String::from("Reconnecting..."));
+        } else if self.spectating {
+            self.graphics.render_game_notice(
+                &format!("Spectating {} - click to switch", self.players[self.follow_i].name),
+            );
+        }
+    }
+
+    // Moves everything between server updates
+    fn update_world(&mut self, time_elapsed: f32) {
+        for projectile in self.projectiles.values_mut() {
+            VelSystem::update_projectile(projectile, time_elapsed);
+        }
+
+        for player_i in 0..self.players.len() {
+            // Don't interpolate this player's location
+            if Some(player_i) == self.player_i {
+                continue;
+            }
+
+            InterpSystem::update_player(
+                &mut self.players[player_i],
+                time_elapsed,
+                self.settings.server_tick,
+            );
+        }
+
+        let mut flashes_removed: usize = 0;
+        for flash_i in 0..self.flashes.len() {
+            let flash_i = flash_i - flashes_removed;
+
+            self.flashes[flash_i].ttl -= time_elapsed;
+
+            if self.flashes[flash_i].ttl < 0.0 {
+                self.flashes.remove(flash_i);
+                flashes_removed += 1;
+            }
+        }
+    }
+
+    // Plays a recorded match back as a spectator would have seen it. Space pauses, the arrow
+    // keys seek and change speed, escape leaves and clicking switches the followed player
+    pub async fn replay(&mut self, time_elapsed: f32) {
+        if self.state_changed {
+            self.graphics.update_canvas(States::Replay);
+            self.state_changed = false;
+
+            if self.replay.is_none() {
+                let name = self.replay_name.clone().unwrap_or_default();
 
-                    if weapon_i.is_err() {
-                        continue;
+                match ReplayPlayer::load(&self.window, &name).await {
+                    Ok(replay) => self.replay = Some(replay),
+                    Err(err) => {
+                        self.leave_match(String::from(err));
+                        return;
                     }
+                }
+            }
+
+            self.restart_replay();
+        }
+
+        let (mouse_coord_x, mouse_coord_y) = self.input.mouse_coordinates();
+        let replay = self.replay.as_mut().unwrap();
+
+        let time = replay.time;
+        let mut seeked = false;
+        let mut rewound = false;
+
+        for key in self.input.get_typed_keys() {
+            match key {
+                // Space
+                0x20 => {
+                    replay.paused = !replay.paused;
+                },
+                // Left and right arrows
+                0x25 => {
+                    rewound |= replay.seek(time - ReplayPlayer::SEEK_STEP);
+                    seeked = true;
+                },
+                0x27 => {
+                    rewound |= replay.seek(time + ReplayPlayer::SEEK_STEP);
+                    seeked = true;
+                },
+                // Up and down arrows
+                0x26 => {
+                    replay.change_speed(true);
+                },
+                0x28 => {
+                    replay.change_speed(false);
+                },
+                // Escape
+                0x1B => {
+                    self.leave_match(String::from("Left replay"));
+                    return;
+                },
+                _ => (),
+            }
+        }
 
-                    self.ground_weapons.remove(weapon_i.unwrap());
+        if self.input.mouse_clicked() {
+            match self.graphics.replay_progress_at(mouse_coord_x, mouse_coord_y) {
+                Some(progress) => {
+                    rewound |= replay.seek(replay.duration() * progress);
+                    seeked = true;
+                },
+                None => {
+                    self.follow_i = (self.follow_i + 1) % self.players.len().max(1);
                 },
-                Proto::TCT_ASSIGN_WEAPON => {
-                    let result = Proto::parse_tct_assign_weapon(&message);
+            }
+        }
 
-                    if result.is_err() {
-                        continue;
-                    }
+        if rewound {
+            self.restart_replay();
+        }
+
+        let time_elapsed = self.replay.as_mut().unwrap().advance(time_elapsed);
 
-                    let (player_i, weapon_type) = result.unwrap();
+        // Skipping ahead would otherwise play every sound in between at once. Projectiles that
+        // were in flight through a skip only move again once the server's next update arrives
+        let audio = match seeked {
+            true => self.audio.take(),
+            false => None,
+        };
 
-                    self.players[player_i].assign_weapon(weapon_type);
+        while let Some((recipient, message)) = self.replay.as_mut().unwrap().next_message() {
+            let message = match ServerMessage::decode(&message) {
+                Ok(message) => message,
+                Err(err) => {
+                    log(&err.to_string());
+                    continue;
                 },
-                Proto::TCT_NEW_PROJECTILE => {
-                    let result = Proto::parse_tct_new_projectile(&message);
+            };
 
-                    if result.is_err() {
-                        continue;
+            // Messages sent to a single player only matter for their HUD, apart from the start
+            // positions which nobody else is sent
+            if recipient != Replay::ALL {
+                if let ServerMessage::StartGame { player_i, x, y } = message {
+                    if player_i < self.players.len() {
+                        self.players[player_i].respawn(x, y, &self.settings);
                     }
+                }
+
+                continue;
+            }
 
-                    let (projectile_type, x, y, vel_x, vel_y) = result.unwrap();
+            if !self.handle_game_message(message).await {
+                self.replay = None;
+                self.replay_name = None;
+                self.spectating = false;
+                return;
+            }
+        }
 
-                    if self.audio.is_some() { 
-                        let audio = self.audio.as_ref().unwrap();
-                        let sound = match projectile_type {
-                            Projectile::TYPE_GRENADE => Sounds::GLAUNCHER,
-                            Projectile::TYPE_BULLET | _ => Sounds::MINIGUN,
-                        };
-                        let sound_x = x - self.players[self.player_i.unwrap()].x;
-                        let sound_y = y - self.players[self.player_i.unwrap()].y;
+        if seeked {
+            self.audio = audio;
+        }
 
-                        audio.play(sound, sound_x, sound_y).await;
-                    };
+        if self.players.is_empty() {
+            return;
+        }
 
+        self.update_world(time_elapsed);
 
-                    let new_projectile = Projectile {
-                        projectile_type,
-                        x,
-                        y,
-                        vel_x,
-                        vel_y,
-                        // Only the server needs to track projectile owners
-                        owner: 0
-                    };
+        self.graphics.render_game(
+            &self.players,
+            self.follow_i,
+            true,
+            &self.ground_weapons,
+            &self.projectiles,
+            mouse_coord_x,
+            mouse_coord_y,
+            &self.flashes,
+        );
 
-                    self.projectiles.push(new_projectile);
-                },
-                Proto::TCT_PROJECTILE_EXPLOSION => {
-                    let projectile = Proto::parse_tct_projectile_explosion(&message);
+        self.graphics.render_game_notice(
+            &format!("Following {} - click to switch", self.players[self.follow_i].name),
+        );
 
-                    if projectile.is_err() {
-                        continue;
-                    }
+        let replay = self.replay.as_ref().unwrap();
+        self.graphics.render_replay_controls(
+            replay.time,
+            replay.duration(),
+            replay.speed(),
+            replay.paused,
+        );
+    }
 
-                    let projectile = projectile.unwrap();
+    // Puts the game back how it was when the recording started
+    fn restart_replay(&mut self) {
+        let replay = self.replay.as_ref().unwrap();
 
-                    if self.audio.is_some() { 
-                        let audio = self.audio.as_ref().unwrap();
-                        let sound = match projectile.projectile_type {
-                            Projectile::TYPE_GRENADE => Sounds::GRENADE,
-                            Projectile::TYPE_BULLET | _ => Sounds::BULLET,
-                        };
-                        let sound_x = projectile.x - self.players[self.player_i.unwrap()].x;
-                        let sound_y = projectile.y - self.players[self.player_i.unwrap()].y;
+        let player_names = match ServerMessage::decode(&replay.player_list_message) {
+            Ok(ServerMessage::PlayerList { names }) => names,
+            _ => Vec::new(),
+        };
 
-                        audio.play(sound, sound_x, sound_y).await;
-                    };
+        self.settings = match ServerMessage::decode(&replay.settings_message) {
+            Ok(ServerMessage::MatchSettings(settings)) => settings,
+            _ => MatchSettings::new(),
+        };
+        self.players.clear();
 
-                    for pixel in projectile.draw_explosion() {
-                        let (pixel_x, pixel_y) = pixel;
-                        self.graphics.clear_map_pixel(pixel_x, pixel_y);
-                    }
+        for name in player_names {
+            let mut player = Player::new(name);
+            player.apply_settings(&self.settings);
+            self.players.push(player);
+        }
 
-                    let damage_radius
-                        = Projectile::PROJECTILE_TYPES[projectile.projectile_type].damage_radius;
+        self.ground_weapons.clear();
+        self.projectiles.clear();
+        self.flashes.clear();
+        self.graphics.reset_map();
 
-                    self.flashes.push(Flash::new(projectile.x, projectile.y, damage_radius));
-                },
-                Proto::TCT_DESTROY_PROJECTILE => {
-                    let projectile_i = Proto::parse_tct_destroy_projectile(&message);
+        self.player_i = None;
+        self.spectating = true;
+        self.follow_i = self.follow_i.min(self.players.len().max(1) - 1);
+    }
 
-                    if projectile_i.is_err() {
-                        continue;
-                    }
+    // Shared by live games and replays. Returns false once the game is over for this client
+    async fn handle_game_message(&mut self, message: ServerMessage) -> bool {
+        match message {
+            ServerMessage::NewPos { player_i, x, y } => {
+                self.players[player_i].x_last = self.players[player_i].x;
+                self.players[player_i].y_last = self.players[player_i].y;
 
-                    self.projectiles.remove(projectile_i.unwrap());
+                self.players[player_i].x_new = x;
+                self.players[player_i].y_new = y;
+            },
+            ServerMessage::InputAck { seq, x, y, vel_y, jumps } => {
+                if self.player_i.is_none() {
+                    return true;
                 }
-                Proto::TCT_UPDATE_HEALTH => {
-                    let new_health = Proto::parse_tct_update_health(&message);
 
-                    if new_health.is_err() {
-                        continue;
-                    }
+                while self.pending_inputs.front().map_or(false, |input| input.seq <= seq) {
+                    self.pending_inputs.pop_front();
+                }
 
-                    self.players[self.player_i.unwrap()].health = new_health.unwrap();
-                },
-                Proto::TCT_REMOVE_AMMO => {
-                    self.players[self.player_i.unwrap()].ammo -= 1;
-                },
-                Proto::TCT_KILL_PLAYER => {
-                    let killed_player_i = Proto::parse_tct_kill_player(&message);
+                let this_player = &mut self.players[self.player_i.unwrap()];
+
+                // Start from where the server put the player and redo what it hasn't seen yet
+                this_player.x = x;
+                this_player.y = y;
+                this_player.vel_y = vel_y;
+                this_player.jumps = jumps;
+
+                for input in &self.pending_inputs {
+                    MovementSystem::apply_input(
+                        this_player,
+                        input,
+                        &self.graphics.map_context,
+                        &self.settings,
+                    );
+                }
+            },
+            ServerMessage::NewAngle { player_i, angle } => {
+                self.players[player_i].angle = angle;
+            },
+            ServerMessage::WeaponSpawn { weapon_id, weapon_type, x, y } => {
+                log("Weapon spawned");
 
-                    if killed_player_i.is_err() {
-                        continue;
-                    }
-                    log("Player killed");
+                let new_weapon = Weapon::new(weapon_id, weapon_type, x, y);
 
-                    self.players[killed_player_i.unwrap()].kill();
-                },
-                Proto::TCT_RESPAWN_PLAYER => {
-                    let result = Proto::parse_tct_respawn_player(&message);
+                self.ground_weapons.insert(weapon_id, new_weapon);
+            },
+            ServerMessage::RemoveWeapon { weapon_id } => {
+                self.ground_weapons.remove(&weapon_id);
+            },
+            ServerMessage::AssignWeapon { player_i, weapon_type } => {
+                self.players[player_i].assign_weapon(weapon_type);
+            },
+            ServerMessage::NewProjectile { projectile_id, projectile_type, x, y, vel_x, vel_y } => {
+                // There is no one to hear it from until a spectator has someone to follow
+                if self.audio.is_some() && self.camera_player_i() < self.players.len() {
+                    let audio = self.audio.as_ref().unwrap();
+                    let sound = match projectile_type {
+                        Projectile::TYPE_GRENADE => Sounds::GLAUNCHER,
+                        Projectile::TYPE_BULLET | _ => Sounds::MINIGUN,
+                    };
+                    let sound_x = x - self.players[self.camera_player_i()].x;
+                    let sound_y = y - self.players[self.camera_player_i()].y;
 
-                    if result.is_err() {
-                        continue;
-                    }
+                    audio.play(sound, sound_x, sound_y).await;
+                };
 
-                    let (respawn_player_i, x, y) = result.unwrap();
 
-                    self.players[respawn_player_i].respawn(x, y);
-                },
-                Proto::TCT_GAME_OVER_STATS => {
-                    let stats = Proto::parse_tct_game_over_stats(&message);
+                let new_projectile = Projectile {
+                    id: projectile_id,
+                    projectile_type,
+                    x,
+                    y,
+                    vel_x,
+                    vel_y,
+                    // Only the server needs to track projectile owners
+                    owner: 0
+                };
 
-                    for i in 0..stats.len() {
-                        let (kills, deaths) = stats[i];
-                        self.players[i].kills = kills;
-                        self.players[i].deaths = deaths;
-                    }
+                self.projectiles.insert(projectile_id, new_projectile);
+            },
+            ServerMessage::ProjectileExplosion { projectile_type, x, y } => {
+                // Only used to draw the explosion, the projectile itself is destroyed separately
+                let projectile = Projectile {
+                    id: 0,
+                    projectile_type,
+                    x,
+                    y,
+                    vel_x: 0.0,
+                    vel_y: 0.0,
+                    owner: 0,
+                };
 
-                    self.state = States::GameOver;
-                    self.state_changed = true;
+                if self.audio.is_some() && self.camera_player_i() < self.players.len() {
+                    let audio = self.audio.as_ref().unwrap();
+                    let sound = match projectile.projectile_type {
+                        Projectile::TYPE_GRENADE => Sounds::GRENADE,
+                        Projectile::TYPE_BULLET | _ => Sounds::BULLET,
+                    };
+                    let sound_x = projectile.x - self.players[self.camera_player_i()].x;
+                    let sound_y = projectile.y - self.players[self.camera_player_i()].y;
 
-                    self.connection.as_mut().unwrap().disconnect();
+                    audio.play(sound, sound_x, sound_y).await;
+                };
 
-                    break 'game_loop;
+                for pixel in projectile.draw_explosion() {
+                    let (pixel_x, pixel_y) = pixel;
+                    self.graphics.clear_map_pixel(pixel_x, pixel_y);
                 }
 
-                _ => ()
+                let damage_radius
+                    = Projectile::PROJECTILE_TYPES[projectile.projectile_type].damage_radius;
+
+                self.flashes.push(Flash::new(projectile.x, projectile.y, damage_radius));
+            },
+            ServerMessage::DestroyProjectile { projectile_id } => {
+                self.projectiles.remove(&projectile_id);
             }
-        }
+            ServerMessage::UpdateHealth { health } => {
+                self.players[self.player_i.unwrap()].health = health;
+            },
+            ServerMessage::RemoveAmmo => {
+                self.players[self.player_i.unwrap()].ammo -= 1;
+            },
+            ServerMessage::KillPlayer { player_i } => {
+                log("Player killed");
 
-        let this_player = &mut self.players[self.player_i.unwrap()];
+                self.players[player_i].kill(&self.settings);
+            },
+            ServerMessage::RespawnPlayer { player_i: respawn_player_i, x, y } => {
+                self.players[respawn_player_i].respawn(x, y, &self.settings);
 
-        if this_player.alive {
-            if self.input.is_down('A' as u32) {
-                this_player.x -= Player::MOVE_SPEED*time_elapsed;
-            } else if self.input.is_down('D' as u32) {
-                this_player.x += Player::MOVE_SPEED*time_elapsed;
-            }
+                // Anything sent before dying no longer applies
+                if Some(respawn_player_i) == self.player_i {
+                    self.pending_inputs.clear();
+                }
+            },
+            ServerMessage::GameOverStats { kills_deaths } => {
+                for i in 0..kills_deaths.len() {
+                    let (kills, deaths) = kills_deaths[i];
+                    self.players[i].kills = kills;
+                    self.players[i].deaths = deaths;
+                }
 
-            if self.input.is_down('W' as u32) {
-                if self.jump_debounce == false && this_player.jumps > 0 {
-                    this_player.vel_y = Player::JUMP_VEL;
-                    this_player.jumps -= 1;
-                    self.jump_debounce = true;
+                self.state = States::GameOver;
+                self.state_changed = true;
+
+                if let Some(connection) = &self.connection {
+                    connection.disconnect();
+                }
+                self.resume_token = None;
+
+                return false;
+            },
+            ServerMessage::ServerShutdown { seconds } => {
+                self.shutdown_time_left = Some((seconds * 1000) as f32);
+            },
+            ServerMessage::ResumeResult { status, .. } => {
+                if !self.handle_resume_result(status) {
+                    return false;
+                }
+            },
+            ServerMessage::Kicked { reason } => {
+                self.leave_match(Self::kick_notice(reason));
+                return false;
+            },
+            ServerMessage::VersionMismatch { .. } => {
+                self.leave_match(String::from(VERSION_MISMATCH_NOTICE));
+                return false;
+            },
+            ServerMessage::MatchError => {
+                self.leave_match(String::from(MATCH_ERROR_NOTICE));
+                return false;
+            },
+            ServerMessage::GameState { player_i, players, weapons, projectiles } => {
+                self.apply_game_state(player_i, players, weapons, projectiles);
+            },
+            ServerMessage::MatchSettings(settings) => {
+                self.settings = settings;
+            },
+            ServerMessage::PlayerJoined { player_i, x, y, name } => {
+                // Player indexes are assigned in join order
+                if player_i != self.players.len() {
+                    log("Player joined out of order");
+                    return true;
+                }
+
+                let mut new_player = Player::new(name);
+                new_player.apply_settings(&self.settings);
+                new_player.respawn(x, y, &self.settings);
+                self.players.push(new_player);
+            },
+            ServerMessage::Terrain { runs } => {
+                self.graphics.reset_map();
+
+                for (x, y, length) in runs {
+                    self.graphics.clear_map_run(x, y, length);
                 }
-            } else {
-                self.jump_debounce = false;
             }
 
-            this_player.angle = (((Graphics::GAME_CANVAS_HEIGHT/2) - mouse_coord_y) as f32)
-                .atan2(((Graphics::GAME_CANVAS_WIDTH/2) - mouse_coord_x) as f32);
+            _ => ()
+        }
 
-            VelSystem::update_player(this_player, time_elapsed);
-            CollisionSystem::update_player(this_player, &self.graphics.map_context);
+        return true;
+    }
 
-            if (this_player.x-this_player.x_last).abs() > 1.0
-                || (this_player.y-this_player.y_last).abs() > 1.0
-            {
-                    let new_pos_message = Proto::tst_new_pos(
-                        this_player.x.round(),
-                        this_player.y.round());
-                    self.connection.as_mut().unwrap().send(new_pos_message);
+    // Returns false while the connection is down. Once it drops a new one is opened every
+    // RECONNECT_PERIOD and the resume token is sent to reclaim this player's slot
+    async fn reconnect(&mut self, time_elapsed: f32) -> bool {
+        if !self.connection.as_ref().unwrap().is_closed() {
+            return true;
+        }
 
-                    this_player.x_last = this_player.x;
-                    this_player.y_last = this_player.y;
-            }
+        if self.resume_token.is_none() {
+            self.leave_match(String::from("Disconnected"));
+            return false;
+        }
 
-            if self.input.is_down('E' as u32) {
-                let take_weapon_message = Proto::tst_take_weapon();
-                self.connection.as_mut().unwrap().send(take_weapon_message);
-            }
+        self.next_reconnect -= time_elapsed;
 
-            let (mouse_state_changed, new_state) = self.input.mouse_state_changed();
-            if mouse_state_changed {
-                let trigger_message = match new_state {
-                    true => Proto::tst_trigger_pulled(),
-                    false => Proto::tst_trigger_released(),
-                };
+        if self.next_reconnect > 0.0 {
+            return false;
+        }
 
-                self.connection.as_mut().unwrap().send(trigger_message);
-            }
+        self.next_reconnect = RECONNECT_PERIOD;
 
-            self.next_angle_update -= time_elapsed;
-            if self.next_angle_update < 0.0 {
-                let new_angle_message = Proto::tst_new_angle(this_player.angle);
-                self.connection.as_mut().unwrap().send(new_angle_message);
+        let connection = Connection::new(&self.window).await;
 
-                self.next_angle_update = ANGLE_UPDATE_PERIOD;
-            }
-        } else {
-            self.players[self.player_i.unwrap()].time_to_respawn -= time_elapsed as i32;
+        if connection.is_closed() {
+            return false;
         }
 
-        for projectile in &mut self.projectiles {
-            VelSystem::update_projectile(projectile, time_elapsed);
+        connection.send(ClientMessage::Resume { resume_token: self.resume_token.unwrap() });
+
+        self.connection = Some(connection);
+
+        return true;
+    }
+
+    // Returns false if the server no longer has a slot for this player
+    fn handle_resume_result(&mut self, status: u8) -> bool {
+        if status != Proto::RESUME_RESULT_SUCCESS {
+            self.leave_match(String::from("Unable to rejoin game"));
+            return false;
         }
 
-        for player_i in 0..self.players.len() {
-            // Don't interpolate this player's location
-            if player_i == self.player_i.unwrap() {
-                continue;
-            }
+        return true;
+    }
 
-            InterpSystem::update_player(&mut self.players[player_i], time_elapsed);
+    fn apply_game_state(
+        &mut self,
+        player_i: usize,
+        players: Vec<Player>,
+        ground_weapons: Vec<Weapon>,
+        projectiles: Vec<Projectile>,
+    ) {
+        self.pending_inputs.clear();
+
+        if player_i != Proto::SPECTATOR as usize {
+            self.player_i = Some(player_i);
         }
 
-        let mut flashes_removed: usize = 0;
-        for flash_i in 0..self.flashes.len() {
-            let flash_i = flash_i - flashes_removed;
+        for (player, state) in self.players.iter_mut().zip(players.iter()) {
+            player.apply_settings(&self.settings);
+
+            player.x_last = state.x;
+            player.y_last = state.y;
+            player.x = state.x;
+            player.y = state.y;
+            player.x_new = state.x;
+            player.y_new = state.y;
+            player.angle = state.angle;
+            player.health = state.health;
+            player.alive = state.alive;
+            player.weapon_type = state.weapon_type;
+            player.ammo = state.ammo;
+            player.time_to_respawn = state.time_to_respawn;
+            player.kills = state.kills;
+            player.deaths = state.deaths;
+        }
 
-            self.flashes[flash_i].ttl -= time_elapsed;
+        self.ground_weapons.clear();
+        for ground_weapon in ground_weapons {
+            self.ground_weapons.insert(ground_weapon.id, ground_weapon);
+        }
 
-            if self.flashes[flash_i].ttl < 0.0 {
-                self.flashes.remove(flash_i);
-                flashes_removed += 1;
-            }
+        self.projectiles.clear();
+        for projectile in projectiles {
+            self.projectiles.insert(projectile.id, projectile);
         }
+    }
 
-        self.graphics.render_game(
+    // The player the game view is centered on
+    fn camera_player_i(&self) -> usize {
+        match self.player_i {
+            Some(player_i) => player_i,
+            None => self.follow_i,
+        }
+    }
+
+    fn render_lobby(&mut self) {
+        self.graphics.render_lobby(
+            &self.code,
             &self.players,
-            self.player_i.unwrap(),
-            &self.ground_weapons,
-            &self.projectiles,
-            mouse_coord_x,
-            mouse_coord_y,
-            &self.flashes,
+            self.player_i,
+            self.host_i,
+            self.lobby_locked,
+            &self.settings,
+            self.spectating,
         );
     }
 
+    fn kick_notice(reason: u8) -> String {
+        let notice = match reason {
+            Proto::KICK_REASON_FLOOD => "Kicked for sending too many messages",
+            Proto::KICK_REASON_INVALID_MESSAGE => "Kicked for sending invalid messages",
+            Proto::KICK_REASON_SPEED => "Kicked for moving too fast",
+            _ => "Kicked from lobby",
+        };
+
+        return String::from(notice);
+    }
+
+    fn leave_match(&mut self, notice: String) {
+        if let Some(connection) = &self.connection {
+            connection.disconnect();
+        }
+
+        self.connection = None;
+        self.players.clear();
+        self.ground_weapons.clear();
+        self.projectiles.clear();
+        self.pending_inputs.clear();
+        self.resume_token = None;
+        self.player_i = None;
+        self.host_i = 0;
+        self.lobby_locked = false;
+        self.settings = MatchSettings::new();
+        self.spectating = false;
+        self.follow_i = 0;
+        self.replay = None;
+        self.replay_name = None;
+
+        self.unmatched_notice = Some(notice);
+        self.state = States::Unmatched;
+        self.state_changed = true;
+    }
+
     pub fn game_over(&mut self) {
         if self.state_changed {
             self.players.sort_by_key(|player| core::cmp::Reverse(player.kills));
@@ -622,6 +1260,7 @@ impl Engine {
             self.graphics.update_canvas(States::GameOver);
             self.graphics.first_render_game_over(&self.players);
             self.state_changed = false;
+            self.shutdown_time_left = None;
         }
 
         let (_, y) = self.input.mouse_coordinates();
@@ -1,7 +1,9 @@
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::*;
+use std::collections::HashMap;
 
+use pixel_demolition_common::match_settings::MatchSettings;
 use pixel_demolition_common::weapon::Weapon;
 use pixel_demolition_common::player::Player;
 use pixel_demolition_common::projectile::Projectile;
@@ -50,6 +52,15 @@ impl Graphics {
 
     const PARALLAX_DIVIDER: f32 = 3.0;
 
+    // Open lobbies are listed from the second section down
+    pub const BROWSER_ROWS: i32 = 8;
+
+    // Match settings sit above and below the player list
+    const LOBBY_SETTINGS_ROWS: [i32; 2] = [2, 7];
+
+    // Replay progress bar along the bottom of the game canvas
+    const REPLAY_BAR_HEIGHT: i32 = 8;
+
     pub fn new(window: &web_sys::Window) -> Graphics {
         let document = window.document().unwrap();
 
@@ -235,7 +246,7 @@ impl Graphics {
 
     pub fn update_canvas(&mut self, state: States) {
         match state {
-            States::Unmatched | States::Lobby | States::GameOver => {
+            States::Unmatched | States::Browser | States::Lobby | States::GameOver => {
                 self.width = Self::LOBBY_CANVS_WIDTH;
                 self.height = Self::LOBBY_CANVAS_HEIGHT;
                 let _ = self.canvas.style().set_property("cursor", "auto");
@@ -244,6 +255,12 @@ impl Graphics {
                 self.width = Self::GAME_CANVAS_WIDTH;
                 self.height = Self::GAME_CANVAS_HEIGHT;
                 let _ = self.canvas.style().set_property("cursor", "none");
+            },
+            // Same view as a game but the mouse is used for the replay controls
+            States::Replay => {
+                self.width = Self::GAME_CANVAS_WIDTH;
+                self.height = Self::GAME_CANVAS_HEIGHT;
+                let _ = self.canvas.style().set_property("cursor", "auto");
             }
         }
 
@@ -302,8 +319,11 @@ impl Graphics {
         self.draw_text(4, &String::from("Enter Existing Game Code"));
         self.draw_boxed_text(5, &String::from(""));
         self.draw_boxed_text(6, &String::from("Join Existing Game"));
-        self.draw_boxed_text(8, &String::from("Start New Game"));
+        self.draw_boxed_text(7, &String::from("Browse Open Games"));
+        self.draw_boxed_text(8, &String::from("Start New Public Game"));
+        self.draw_boxed_text(9, &String::from("Start New Private Game"));
         self.draw_boxed_text(10, &String::from("Enable Audio"));
+        self.draw_boxed_text(11, &String::from("Spectate Existing Game"));
     }
 
     pub fn update_render_unmatched_name_code(&mut self, name: &String, code: &String) {
@@ -317,14 +337,69 @@ impl Graphics {
         self.draw_boxed_text(10, &String::from("Audio Enabled"));
     }
 
-    pub fn render_lobby(&mut self, code: &String, players: &Vec<Player>) {
+    pub fn render_browser(&mut self, lobbies: &Vec<(String, String, usize)>, notice: &String) {
         self.context.set_fill_style(&"black".into());
         self.context
             .fill_rect(0.0, 0.0, self.width as f64, self.height as f64);
 
-        self.draw_text(1, &format!("Code: {}", code));
+        self.draw_text(0, &String::from("Open Games"));
+
+        for i in 0..lobbies.len() {
+            let (code, host, players) = &lobbies[i];
 
-        self.draw_boxed_text(8, &String::from("Ready"));
+            self.draw_boxed_text(
+                (i as i32) + 2,
+                &format!("{} - {} players - {}", host, players, code),
+            );
+        }
+
+        self.draw_text(10, notice);
+        self.draw_boxed_text(11, &String::from("Back"));
+    }
+
+    // The host also gets kick buttons on the other players and the start and lock buttons
+    pub fn render_lobby(
+        &mut self,
+        code: &String,
+        players: &Vec<Player>,
+        this_player_i: Option<usize>,
+        host_i: usize,
+        locked: bool,
+        settings: &MatchSettings,
+        spectating: bool,
+    ) {
+        self.context.set_fill_style(&"black".into());
+        self.context
+            .fill_rect(0.0, 0.0, self.width as f64, self.height as f64);
+
+        match locked {
+            true => self.draw_text(1, &format!("Code: {} - Locked", code)),
+            false => self.draw_text(1, &format!("Code: {}", code)),
+        }
+
+        match spectating {
+            true => self.draw_text(8, &String::from("Spectating")),
+            false => self.draw_boxed_text(8, &String::from("Ready")),
+        }
+
+        self.draw_boxed_text(9, &String::from("Leave"));
+
+        let is_host = this_player_i == Some(host_i);
+
+        // Only the host can click the settings to change them
+        let labels = settings.labels();
+        for setting_i in 0..labels.len() {
+            self.draw_lobby_setting(setting_i, &labels[setting_i], is_host);
+        }
+
+        if is_host {
+            self.draw_boxed_text(10, &String::from("Start Now"));
+
+            match locked {
+                true => self.draw_boxed_text(11, &String::from("Unlock Lobby")),
+                false => self.draw_boxed_text(11, &String::from("Lock Lobby")),
+            }
+        }
 
         for i in 0..players.len() {
             let ready = match players[i].ready {
@@ -332,28 +407,106 @@ impl Graphics {
                 false => "Not Ready",
             };
 
-            self.draw_text(
-                (i as i32) + 3,
-                &format!(
-                    "{} - {} - {}",
-                    players[i].name,
-                    Player::COLOR_NAMES[i],
-                    ready
-                ),
+            let mut text = format!(
+                "{} - {} - {}",
+                players[i].name,
+                Player::COLOR_NAMES[i],
+                ready
             );
+
+            if i == host_i {
+                text.push_str(" - Host");
+            }
+
+            if is_host && i != host_i {
+                text.push_str(" - Kick");
+                self.draw_boxed_text((i as i32) + 3, &text);
+            } else {
+                self.draw_text((i as i32) + 3, &text);
+            }
+        }
+    }
+
+    // Settings are laid out in two rows of cells either side of the player list
+    fn lobby_setting_cell(&self, setting_i: usize) -> (i32, i32, i32) {
+        let cells_per_row = (MatchSettings::SETTINGS_COUNT / 2) as i32;
+        let cell_width = (self.width - 200) / cells_per_row;
+
+        let row = match (setting_i as i32) < cells_per_row {
+            true => Self::LOBBY_SETTINGS_ROWS[0],
+            false => Self::LOBBY_SETTINGS_ROWS[1],
+        };
+
+        let x = 100 + (setting_i as i32 % cells_per_row) * cell_width;
+
+        return (row, x, cell_width);
+    }
+
+    fn draw_lobby_setting(&self, setting_i: usize, text: &String, boxed: bool) {
+        let (row, x, cell_width) = self.lobby_setting_cell(setting_i);
+
+        let y = self.height_divided * row + (self.height_divided / 10);
+        let height = self.height_divided - (self.height_divided / 5);
+
+        if boxed {
+            self.context.set_stroke_style(&"white".into());
+            self.context.stroke_rect(x as f64, y as f64, cell_width as f64, height as f64);
+        }
+
+        self.context
+            .set_font(&format!("{}px monospace", self.height_divided / 3));
+
+        self.context.set_text_align("center");
+        self.context.set_text_baseline("middle");
+
+        self.context.set_fill_style(&"white".into());
+
+        self.context
+            .fill_text(
+                &text,
+                (x + cell_width / 2) as f64,
+                (self.height_divided * row + self.height_divided / 2) as f64,
+            )
+            .unwrap();
+    }
+
+    pub fn lobby_setting_at(&self, mouse_x: i32, mouse_y: i32) -> Option<usize> {
+        for setting_i in 0..MatchSettings::SETTINGS_COUNT {
+            let (row, x, cell_width) = self.lobby_setting_cell(setting_i);
+
+            if mouse_y / self.height_divided == row && mouse_x >= x && mouse_x < x + cell_width {
+                return Some(setting_i);
+            }
         }
+
+        return None;
     }
 
     pub fn clear_map_pixel(&self, x: i32, y: i32) {
         self.map_context.clear_rect(x as f64, y as f64, 1.0, 1.0)
     }
 
+    pub fn clear_map_run(&self, x: i32, y: i32, length: i32) {
+        self.map_context.clear_rect(x as f64, y as f64, length as f64, 1.0)
+    }
+
+    // Put back every pixel that has been blown up
+    pub fn reset_map(&self) {
+        let width = self.map.width() as f64;
+        let height = self.map.height() as f64;
+
+        self.map_context.clear_rect(0.0, 0.0, width, height);
+        let _ = self.map_context.draw_image_with_html_image_element(&self.map, 0.0, 0.0);
+    }
+
+    // Spectators pass the player they are following and get no HUD
     pub fn render_game(
         &self,
         players: &Vec<Player>,
         this_player_i: usize,
-        ground_weapons: &Vec<Weapon>,
-        projectiles: &Vec<Projectile>,
+        spectating: bool,
+        ground_weapons: &HashMap<u32, Weapon>,
+        projectiles: &HashMap<u32, Projectile>,
         mouse_coord_x: i32,
         mouse_coord_y: i32,
         flashes: &Vec<Flash>,
@@ -477,34 +630,40 @@ impl Graphics {
             self.context.restore();
         }
 
-        for i in 0..ground_weapons.len() {
-            let weapon_type = ground_weapons[i].weapon_type;
+        for ground_weapon in ground_weapons.values() {
+            let weapon_type = ground_weapon.weapon_type;
 
             self.context
                 .draw_image_with_html_image_element(
                     &self.weapons[weapon_type as usize],
-                    ground_weapons[i].x as f64 - players[this_player_i as usize].x.round() as f64
+                    ground_weapon.x as f64 - players[this_player_i as usize].x.round() as f64
                         + (self.width / 2) as f64,
-                    ground_weapons[i].y as f64 - players[this_player_i as usize].y.round() as f64
+                    ground_weapon.y as f64 - players[this_player_i as usize].y.round() as f64
                         + (self.height / 2) as f64,
                 )
                 .expect("Unable to draw sprite");
         }
 
-        for i in 0..projectiles.len() {
-            let projectile_type = projectiles[i].projectile_type;
+        for projectile in projectiles.values() {
+            let projectile_type = projectile.projectile_type;
 
             self.context
                 .draw_image_with_html_image_element(
                     &self.projectiles[projectile_type as usize],
-                    projectiles[i].x as f64 - players[this_player_i as usize].x.round() as f64
+                    projectile.x as f64 - players[this_player_i as usize].x.round() as f64
                         + (self.width / 2) as f64,
-                    projectiles[i].y as f64 - players[this_player_i as usize].y.round() as f64
+                    projectile.y as f64 - players[this_player_i as usize].y.round() as f64
                         + (self.height / 2) as f64,
                 )
                 .expect("Unable to draw sprite");
         }
 
+        self.render_flashes(players, this_player_i, flashes);
+
+        if spectating {
+            return;
+        }
+
         let reticle_x = mouse_coord_x - (self.reticle.width() / 2) as i32;
         let reticle_y = mouse_coord_y - (self.reticle.height() / 2) as i32;
         let _ = self.context.draw_image_with_html_image_element(
@@ -560,7 +719,9 @@ impl Graphics {
                 )
                 .unwrap();
         }
+    }
 
+    fn render_flashes(&self, players: &Vec<Player>, this_player_i: usize, flashes: &Vec<Flash>) {
         for flash in flashes {
             self.context.set_fill_style(&"yellow".into());
 
@@ -581,6 +742,83 @@ impl Graphics {
         }
     }
 
+    // Drawn along the top of the game canvas
+    pub fn render_game_notice(&self, notice: &String) {
+        self.context
+            .set_font(&format!("{}px monospace", self.height / 16));
+
+        self.context.set_text_align("center");
+        self.context.set_text_baseline("top");
+
+        self.context.set_fill_style(&"white".into());
+
+        self.context
+            .fill_text(
+                notice,
+                (self.width / 2) as f64,
+                6.0,
+            )
+            .unwrap();
+    }
+
+    // Times are in ms
+    pub fn render_replay_controls(&self, time: f32, duration: f32, speed: f32, paused: bool) {
+        let bar_y = (self.height - Self::REPLAY_BAR_HEIGHT) as f64;
+
+        self.context.set_fill_style(&"black".into());
+        self.context.fill_rect(0.0, bar_y, self.width as f64, Self::REPLAY_BAR_HEIGHT as f64);
+
+        let progress = match duration > 0.0 {
+            true => time / duration,
+            false => 0.0,
+        };
+
+        self.context.set_fill_style(&"white".into());
+        self.context.fill_rect(
+            0.0,
+            bar_y,
+            self.width as f64 * progress as f64,
+            Self::REPLAY_BAR_HEIGHT as f64,
+        );
+
+        let status = match paused {
+            true => String::from("Paused"),
+            false => format!("{}x", speed),
+        };
+
+        let controls = format!(
+            "{} {} / {}",
+            status,
+            Self::format_replay_time(time),
+            Self::format_replay_time(duration),
+        );
+
+        self.context
+            .set_font(&format!("{}px monospace", self.height / 16));
+
+        self.context.set_text_align("center");
+        self.context.set_text_baseline("bottom");
+
+        self.context
+            .fill_text(&controls, (self.width / 2) as f64, bar_y - 4.0)
+            .unwrap();
+    }
+
+    fn format_replay_time(time: f32) -> String {
+        let secs = (time / 1000.0) as i32;
+
+        return format!("{}:{:02}", secs / 60, secs % 60);
+    }
+
+    // Returns how far along the replay a click on the progress bar is, from 0 to 1
+    pub fn replay_progress_at(&self, mouse_x: i32, mouse_y: i32) -> Option<f32> {
+        if mouse_y < self.height - Self::REPLAY_BAR_HEIGHT * 3 {
+            return None;
+        }
+
+        return Some((mouse_x as f32 / self.width as f32).max(0.0).min(1.0));
+    }
+
     pub fn first_render_game_over(&mut self, players_sorted: &Vec<Player>) {
         self.context.set_fill_style(&"black".into());
         self.context
@@ -1,11 +1,11 @@
 use pixel_demolition_common::player::Player;
-use pixel_demolition_common::server_tick::ServerTick;
 
 pub struct InterpSystem {}
 
 impl InterpSystem {
-    pub fn update_player(player: &mut Player, time_elapsed: f32) {
-        let interp_count = (ServerTick::SERVER_TICK as f32)/time_elapsed;
+    // server_tick is in ms, the time between the updates being interpolated
+    pub fn update_player(player: &mut Player, time_elapsed: f32, server_tick: i32) {
+        let interp_count = (server_tick as f32)/time_elapsed;
 
         // If the player is moving from left to right
         if player.x_last < player.x_new {
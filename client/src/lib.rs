@@ -7,3 +7,4 @@ mod selected;
 mod audio;
 mod interp_system;
 mod flash;
+mod replay_player;
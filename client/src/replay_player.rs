@@ -0,0 +1,152 @@
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Request, RequestInit, RequestMode, Response};
+
+use pixel_demolition_common::replay::{Replay, ReplayRecord};
+
+// Steps through a recorded match, handing back messages as their tick comes up
+pub struct ReplayPlayer {
+    // In ms
+    server_tick: i32,
+    pub player_list_message: Vec<u8>,
+    pub settings_message: Vec<u8>,
+    // (tick, recipient, message)
+    records: Vec<ReplayRecord>,
+    next_record: usize,
+    // In ms since the game started
+    pub time: f32,
+    speed_i: usize,
+    pub paused: bool,
+}
+
+impl ReplayPlayer {
+    pub const SPEEDS: [f32; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+    const DEFAULT_SPEED_I: usize = 2;
+
+    // Arrow keys skip 5 seconds
+    pub const SEEK_STEP: f32 = 5000.0;
+
+    pub fn new(data: &[u8]) -> Result<ReplayPlayer, &'static str> {
+        let (server_tick, player_list_message, settings_message, records) = Replay::parse(data)?;
+
+        if server_tick < 1 {
+            return Err("Bad replay server tick");
+        }
+
+        return Ok(ReplayPlayer {
+            server_tick,
+            player_list_message,
+            settings_message,
+            records,
+            next_record: 0,
+            time: 0.0,
+            speed_i: Self::DEFAULT_SPEED_I,
+            paused: false,
+        });
+    }
+
+    // Replays are served by the same server as the client
+    pub async fn load(window: &web_sys::Window, name: &String) -> Result<ReplayPlayer, &'static str> {
+        let mut opts = RequestInit::new();
+        opts.method("GET");
+        opts.mode(RequestMode::Cors);
+
+        let hostname = window.location().hostname().unwrap();
+        let port = window.location().port().unwrap();
+
+        let url = format!("http://{}:{}/replays/{}", hostname, port, name);
+
+        let request = Request::new_with_str_and_init(&url, &opts)
+            .map_err(|_| "Bad replay name")?;
+
+        let resp = JsFuture::from(window.fetch_with_request(&request))
+            .await
+            .map_err(|_| "Unable to load replay")?;
+        let resp: Response = resp.dyn_into().unwrap();
+
+        if !resp.ok() {
+            return Err("Replay not found");
+        }
+
+        let array_buffer = JsFuture::from(resp.array_buffer().unwrap())
+            .await
+            .map_err(|_| "Unable to load replay")?;
+
+        let data = js_sys::Uint8Array::new(&array_buffer).to_vec();
+
+        return Self::new(&data);
+    }
+
+    // In ms, up to the last recorded message
+    pub fn duration(&self) -> f32 {
+        return match self.records.last() {
+            Some((tick, _, _)) => (*tick as i32 * self.server_tick) as f32,
+            None => 0.0,
+        };
+    }
+
+    pub fn speed(&self) -> f32 {
+        return Self::SPEEDS[self.speed_i];
+    }
+
+    pub fn change_speed(&mut self, faster: bool) {
+        if faster && self.speed_i + 1 < Self::SPEEDS.len() {
+            self.speed_i += 1;
+        } else if !faster && self.speed_i > 0 {
+            self.speed_i -= 1;
+        }
+    }
+
+    // Returns how much game time passed, stopping at the end of the recording
+    pub fn advance(&mut self, time_elapsed: f32) -> f32 {
+        if self.paused {
+            return 0.0;
+        }
+
+        let time_elapsed = time_elapsed * self.speed();
+        let duration = self.duration();
+
+        if self.time + time_elapsed >= duration {
+            let time_elapsed = (duration - self.time).max(0.0);
+            self.time = duration;
+            self.paused = true;
+            return time_elapsed;
+        }
+
+        self.time += time_elapsed;
+
+        return time_elapsed;
+    }
+
+    // Returns true when seeking backwards, the caller then has to reset the game and play it
+    // again from the start
+    pub fn seek(&mut self, time: f32) -> bool {
+        let time = time.max(0.0).min(self.duration());
+        let rewound = time < self.time;
+
+        if rewound {
+            self.next_record = 0;
+        }
+
+        self.time = time;
+
+        return rewound;
+    }
+
+    // Returns (recipient, message) for the next message that is due
+    pub fn next_message(&mut self) -> Option<(u8, Vec<u8>)> {
+        if self.next_record >= self.records.len() {
+            return None;
+        }
+
+        let (tick, recipient, message) = &self.records[self.next_record];
+
+        if (*tick as i32 * self.server_tick) as f32 > self.time {
+            return None;
+        }
+
+        self.next_record += 1;
+
+        return Some((*recipient, message.clone()));
+    }
+}
@@ -1,6 +1,8 @@
 pub enum States {
     Unmatched,
+    Browser,
     Lobby,
     Game,
     GameOver,
+    Replay,
 }
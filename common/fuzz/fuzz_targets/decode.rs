@@ -0,0 +1,26 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use pixel_demolition_common::client_message::ClientMessage;
+use pixel_demolition_common::proto::Proto;
+use pixel_demolition_common::server_message::ServerMessage;
+
+// Run with `cargo fuzz run decode` from common/. Every decoder has to return an error rather
+// than panic, and anything that does decode has to encode back to the exact same bytes. Bytes
+// are compared rather than messages since NaN never equals itself
+fuzz_target!(|data: &[u8]| {
+    let message = data.to_vec();
+
+    if let Ok(decoded) = ClientMessage::decode(&message) {
+        assert_eq!(decoded.encode(), message);
+    }
+
+    if let Ok(decoded) = ServerMessage::decode(&message) {
+        assert_eq!(decoded.encode(), message);
+    }
+
+    if let Ok(messages) = Proto::unbundle(&message) {
+        assert_eq!(Proto::bundle(&messages), message);
+    }
+});
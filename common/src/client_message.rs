@@ -0,0 +1,310 @@
+use crate::match_settings::MatchSettings;
+use crate::player_input::PlayerInput;
+use crate::proto::{Proto, ProtoError};
+
+// Everything the client sends the server, see Proto for the byte layouts
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientMessage {
+    JoinExisting { code: String, name: String },
+    CreateNew { public: bool, name: String },
+    ToggleReady,
+    TakeWeapon,
+    TriggerPulled,
+    TriggerReleased,
+    ListLobbies,
+    Resume { resume_token: [u8; Proto::RESUME_TOKEN_LEN] },
+    LeaveMatch,
+    KickPlayer { player_i: usize },
+    ForceStart,
+    LockLobby,
+    UpdateSettings(MatchSettings),
+    SpectateExisting { code: String, name: String },
+    Input(PlayerInput),
+    Hello { protocol_version: u16 },
+}
+
+impl ClientMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::new();
+
+        match self {
+            ClientMessage::JoinExisting { code, name } => {
+                data.push(Proto::TST_JOIN_EXISTING);
+                data.extend_from_slice(code.as_bytes());
+                data.extend_from_slice(name.as_bytes());
+            },
+            ClientMessage::CreateNew { public, name } => {
+                data.push(Proto::TST_CREATE_NEW);
+                data.push(Proto::bool_byte(*public));
+                data.extend_from_slice(name.as_bytes());
+            },
+            ClientMessage::ToggleReady => data.push(Proto::TST_TOGGLE_READY),
+            ClientMessage::TakeWeapon => data.push(Proto::TST_TAKE_WEAPON),
+            ClientMessage::TriggerPulled => data.push(Proto::TST_TRIGGER_PULLED),
+            ClientMessage::TriggerReleased => data.push(Proto::TST_TRIGGER_RELEASED),
+            ClientMessage::ListLobbies => data.push(Proto::TST_LIST_LOBBIES),
+            ClientMessage::Resume { resume_token } => {
+                data.push(Proto::TST_RESUME);
+                data.extend_from_slice(resume_token);
+            },
+            ClientMessage::LeaveMatch => data.push(Proto::TST_LEAVE_MATCH),
+            ClientMessage::KickPlayer { player_i } => {
+                data.push(Proto::TST_KICK_PLAYER);
+                data.push(*player_i as u8);
+            },
+            ClientMessage::ForceStart => data.push(Proto::TST_FORCE_START),
+            ClientMessage::LockLobby => data.push(Proto::TST_LOCK_LOBBY),
+            ClientMessage::UpdateSettings(settings) => {
+                data.push(Proto::TST_UPDATE_SETTINGS);
+                Proto::push_match_settings(&mut data, settings);
+            },
+            ClientMessage::SpectateExisting { code, name } => {
+                data.push(Proto::TST_SPECTATE_EXISTING);
+                data.extend_from_slice(code.as_bytes());
+                data.extend_from_slice(name.as_bytes());
+            },
+            ClientMessage::Input(input) => {
+                let mut flags: u8 = 0;
+
+                if input.left {
+                    flags |= Proto::INPUT_LEFT;
+                }
+
+                if input.right {
+                    flags |= Proto::INPUT_RIGHT;
+                }
+
+                if input.jump {
+                    flags |= Proto::INPUT_JUMP;
+                }
+
+                data.push(Proto::TST_INPUT);
+                data.extend_from_slice(&(input.seq.to_le_bytes()));
+                data.push(flags);
+                data.extend_from_slice(&(input.angle.to_le_bytes()));
+                data.extend_from_slice(&(input.time_elapsed.to_le_bytes()));
+            },
+            ClientMessage::Hello { protocol_version } => {
+                data.push(Proto::TST_HELLO);
+                data.extend_from_slice(&(protocol_version.to_le_bytes()));
+            },
+        }
+
+        return data;
+    }
+
+    pub fn decode(message: &Vec<u8>) -> Result<ClientMessage, ProtoError> {
+        let message_type = Proto::get_type(message)?;
+
+        match message_type {
+            Proto::TST_JOIN_EXISTING | Proto::TST_SPECTATE_EXISTING => {
+                let (code, name) = Self::decode_code_name(message)?;
+
+                if message_type == Proto::TST_JOIN_EXISTING {
+                    return Ok(ClientMessage::JoinExisting { code, name });
+                }
+
+                return Ok(ClientMessage::SpectateExisting { code, name });
+            },
+            Proto::TST_CREATE_NEW => {
+                if message.len() < 3 {
+                    return Err(ProtoError::TooShort);
+                }
+
+                return Ok(ClientMessage::CreateNew {
+                    public: Proto::parse_bool(message[1])?,
+                    name: Proto::parse_str(&message[2..])?,
+                });
+            },
+            Proto::TST_TOGGLE_READY
+            | Proto::TST_TAKE_WEAPON
+            | Proto::TST_TRIGGER_PULLED
+            | Proto::TST_TRIGGER_RELEASED
+            | Proto::TST_LIST_LOBBIES
+            | Proto::TST_LEAVE_MATCH
+            | Proto::TST_FORCE_START
+            | Proto::TST_LOCK_LOBBY => {
+                Proto::check_len(message, 1)?;
+
+                return Ok(match message_type {
+                    Proto::TST_TOGGLE_READY => ClientMessage::ToggleReady,
+                    Proto::TST_TAKE_WEAPON => ClientMessage::TakeWeapon,
+                    Proto::TST_TRIGGER_PULLED => ClientMessage::TriggerPulled,
+                    Proto::TST_TRIGGER_RELEASED => ClientMessage::TriggerReleased,
+                    Proto::TST_LIST_LOBBIES => ClientMessage::ListLobbies,
+                    Proto::TST_LEAVE_MATCH => ClientMessage::LeaveMatch,
+                    Proto::TST_FORCE_START => ClientMessage::ForceStart,
+                    _ => ClientMessage::LockLobby,
+                });
+            },
+            Proto::TST_RESUME => {
+                Proto::check_len(message, 1 + Proto::RESUME_TOKEN_LEN)?;
+
+                let resume_token: [u8; Proto::RESUME_TOKEN_LEN] = message[1..].try_into().unwrap();
+
+                return Ok(ClientMessage::Resume { resume_token });
+            },
+            Proto::TST_KICK_PLAYER => {
+                Proto::check_len(message, 2)?;
+
+                return Ok(ClientMessage::KickPlayer { player_i: message[1] as usize });
+            },
+            Proto::TST_UPDATE_SETTINGS => {
+                return Ok(ClientMessage::UpdateSettings(Proto::parse_match_settings(message)?));
+            },
+            Proto::TST_INPUT => {
+                Proto::check_len(message, 14)?;
+
+                let seq_bytes:[u8;4] = message[1..5].try_into().unwrap();
+                let flags = message[5];
+                let angle_bytes:[u8;4] = message[6..10].try_into().unwrap();
+                let time_elapsed_bytes:[u8;4] = message[10..14].try_into().unwrap();
+
+                if flags & !(Proto::INPUT_LEFT | Proto::INPUT_RIGHT | Proto::INPUT_JUMP) > 0 {
+                    return Err(ProtoError::UnknownValue);
+                }
+
+                let angle = f32::from_le_bytes(angle_bytes);
+                let time_elapsed = f32::from_le_bytes(time_elapsed_bytes);
+
+                // NaN would get past every comparison the engine makes
+                if !angle.is_finite() || !time_elapsed.is_finite() {
+                    return Err(ProtoError::NonFinite);
+                }
+
+                return Ok(ClientMessage::Input(PlayerInput {
+                    seq: u32::from_le_bytes(seq_bytes),
+                    left: flags & Proto::INPUT_LEFT > 0,
+                    right: flags & Proto::INPUT_RIGHT > 0,
+                    jump: flags & Proto::INPUT_JUMP > 0,
+                    angle,
+                    time_elapsed,
+                }));
+            },
+            Proto::TST_HELLO => {
+                Proto::check_len(message, 3)?;
+
+                let protocol_version_bytes:[u8;2] = message[1..3].try_into().unwrap();
+
+                return Ok(ClientMessage::Hello {
+                    protocol_version: u16::from_le_bytes(protocol_version_bytes),
+                });
+            },
+            _ => return Err(ProtoError::UnknownValue),
+        }
+    }
+
+    // Codes are a fixed length so the name is whatever follows
+    fn decode_code_name(message: &Vec<u8>) -> Result<(String, String), ProtoError> {
+        if message.len() < 1 + Proto::GAME_CODE_LEN + 1 {
+            return Err(ProtoError::TooShort);
+        }
+
+        let code = Proto::parse_str(&message[1..(Proto::GAME_CODE_LEN + 1)])?;
+        let name = Proto::parse_str(&message[(Proto::GAME_CODE_LEN + 1)..])?;
+
+        return Ok((code, name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(message: ClientMessage) {
+        let decoded = ClientMessage::decode(&message.encode());
+        assert_eq!(decoded, Ok(message));
+    }
+
+    #[test]
+    fn round_trips_every_message() {
+        let mut settings = MatchSettings::new();
+        settings.kills_to_win = 12;
+        settings.max_health = 7.5;
+        settings.server_tick = 25;
+
+        let messages = [
+            ClientMessage::JoinExisting { code: String::from("ABC123"), name: String::from("Bob") },
+            ClientMessage::CreateNew { public: true, name: String::from("Alice") },
+            ClientMessage::CreateNew { public: false, name: String::from("Ålice") },
+            ClientMessage::ToggleReady,
+            ClientMessage::TakeWeapon,
+            ClientMessage::TriggerPulled,
+            ClientMessage::TriggerReleased,
+            ClientMessage::ListLobbies,
+            ClientMessage::Resume { resume_token: [7; Proto::RESUME_TOKEN_LEN] },
+            ClientMessage::LeaveMatch,
+            ClientMessage::KickPlayer { player_i: 3 },
+            ClientMessage::ForceStart,
+            ClientMessage::LockLobby,
+            ClientMessage::UpdateSettings(settings),
+            ClientMessage::SpectateExisting { code: String::from("XYZ789"), name: String::from("Eve") },
+            ClientMessage::Input(PlayerInput {
+                seq: 4000000000,
+                left: true,
+                right: false,
+                jump: true,
+                angle: -1.25,
+                time_elapsed: 16.6,
+            }),
+            ClientMessage::Hello { protocol_version: Proto::PROTOCOL_VERSION },
+        ];
+
+        for message in messages {
+            round_trip(message);
+        }
+    }
+
+    #[test]
+    fn keeps_the_wire_layout() {
+        let message = ClientMessage::CreateNew { public: true, name: String::from("Al") };
+        assert_eq!(message.encode(), vec![Proto::TST_CREATE_NEW, Proto::TRUE, b'A', b'l']);
+
+        let message = ClientMessage::KickPlayer { player_i: 2 };
+        assert_eq!(message.encode(), vec![Proto::TST_KICK_PLAYER, 2]);
+
+        let message = ClientMessage::Hello { protocol_version: 0x0102 };
+        assert_eq!(message.encode(), vec![0x11, 0x02, 0x01]);
+
+        let input = PlayerInput {
+            seq: 1,
+            left: false,
+            right: true,
+            jump: true,
+            angle: 0.0,
+            time_elapsed: 0.0,
+        };
+        let encoded = ClientMessage::Input(input).encode();
+        assert_eq!(encoded.len(), 14);
+        assert_eq!(encoded[0..6], [Proto::TST_INPUT, 1, 0, 0, 0, Proto::INPUT_RIGHT | Proto::INPUT_JUMP]);
+    }
+
+    #[test]
+    fn rejects_bad_messages() {
+        assert_eq!(ClientMessage::decode(&vec![]), Err(ProtoError::TooShort));
+        assert_eq!(ClientMessage::decode(&vec![0x7f]), Err(ProtoError::UnknownValue));
+        assert_eq!(ClientMessage::decode(&vec![Proto::TST_KICK_PLAYER]), Err(ProtoError::TooShort));
+        assert_eq!(ClientMessage::decode(&vec![Proto::TST_KICK_PLAYER, 1, 2]), Err(ProtoError::TrailingBytes));
+        assert_eq!(ClientMessage::decode(&vec![Proto::TST_TOGGLE_READY, 0]), Err(ProtoError::TrailingBytes));
+        assert_eq!(ClientMessage::decode(&vec![Proto::TST_CREATE_NEW, 0x01, b'A']), Err(ProtoError::UnknownValue));
+        assert_eq!(ClientMessage::decode(&vec![Proto::TST_CREATE_NEW, Proto::TRUE, 0xC3]), Err(ProtoError::BadUtf8));
+        assert_eq!(ClientMessage::decode(&vec![Proto::TST_JOIN_EXISTING, b'A', b'B']), Err(ProtoError::TooShort));
+
+        let input = PlayerInput {
+            seq: 1,
+            left: false,
+            right: false,
+            jump: false,
+            angle: 0.0,
+            time_elapsed: 16.0,
+        };
+
+        let mut nan_input = ClientMessage::Input(input.clone()).encode();
+        nan_input[6..10].copy_from_slice(&f32::NAN.to_le_bytes());
+        assert_eq!(ClientMessage::decode(&nan_input), Err(ProtoError::NonFinite));
+
+        let mut unknown_flag_input = ClientMessage::Input(input).encode();
+        unknown_flag_input[5] = 0x80;
+        assert_eq!(ClientMessage::decode(&unknown_flag_input), Err(ProtoError::UnknownValue));
+    }
+}
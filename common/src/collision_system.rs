@@ -1,7 +1,6 @@
-#[cfg(target_family = "wasm")]
-use web_sys::CanvasRenderingContext2d;
-
+use crate::match_settings::MatchSettings;
 use crate::player::Player;
+use crate::terrain::Terrain;
 
 #[cfg(not(target_family = "wasm"))]
 use crate::projectile::Projectile;
@@ -11,8 +10,7 @@ use image::{self, RgbaImage};
 pub struct CollisionSystem {}
 
 impl CollisionSystem {
-    #[cfg(target_family = "wasm")]
-    pub fn update_player(player: &mut Player, map_context: &CanvasRenderingContext2d) {
+    pub fn update_player<T: Terrain>(player: &mut Player, terrain: &T, settings: &MatchSettings) {
         const PLAYER_WIDTH:f32 = Player::PLAYER_WIDTH as f32;
         const PLAYER_HEIGHT:f32 = Player::PLAYER_HEIGHT as f32;
 
@@ -24,33 +22,33 @@ impl CollisionSystem {
         let horizontal_cb_start_x = (player.x-(PLAYER_WIDTH/2.0)).round();
         let horizontal_cb_start_y = (player.y-(horizontal_cb_height/2.0)).round();
 
-        let top_coll_data = map_context.get_image_data(
-            vertical_cb_start_x as f64,
-            vertical_cb_start_y as f64,
-            vertical_cb_width as f64,
-            (PLAYER_HEIGHT/2.0).round() as f64,
-        ).expect("Unable to get map data").data();
-
-        let bottom_coll_data = map_context.get_image_data(
-            vertical_cb_start_x as f64,
-            player.y.round() as f64,
-            vertical_cb_width as f64,
-            (PLAYER_HEIGHT/2.0).round() as f64,
-        ).expect("Unable to get map data").data();
-
-        let left_coll_data = map_context.get_image_data(
-            horizontal_cb_start_x as f64,
-            horizontal_cb_start_y as f64,
-            (PLAYER_WIDTH/2.0).round() as f64,
-            horizontal_cb_height as f64,
-        ).expect("Unable to get map data").data();
-
-        let right_coll_data = map_context.get_image_data(
-            player.x.round() as f64,
-            horizontal_cb_start_y as f64,
-            (PLAYER_WIDTH/2.0).round() as f64,
-            horizontal_cb_height as f64
-        ).expect("Unable to get map data").data();
+        let top_coll_data = terrain.rgba_rect(
+            vertical_cb_start_x as i32,
+            vertical_cb_start_y as i32,
+            vertical_cb_width as i32,
+            (PLAYER_HEIGHT/2.0).round() as i32,
+        );
+
+        let bottom_coll_data = terrain.rgba_rect(
+            vertical_cb_start_x as i32,
+            player.y.round() as i32,
+            vertical_cb_width as i32,
+            (PLAYER_HEIGHT/2.0).round() as i32,
+        );
+
+        let left_coll_data = terrain.rgba_rect(
+            horizontal_cb_start_x as i32,
+            horizontal_cb_start_y as i32,
+            (PLAYER_WIDTH/2.0).round() as i32,
+            horizontal_cb_height as i32,
+        );
+
+        let right_coll_data = terrain.rgba_rect(
+            player.x.round() as i32,
+            horizontal_cb_start_y as i32,
+            (PLAYER_WIDTH/2.0).round() as i32,
+            horizontal_cb_height as i32
+        );
 
         const COLLIDE_BOTTOM:u8=0;
         const COLLIDE_TOP:u8=1;
@@ -66,7 +64,7 @@ impl CollisionSystem {
                         player.y -= line_from_bottom as f32;
                         player.y = player.y.round();
                         player.vel_y = 0.0;
-                        player.jumps = 3;
+                        player.jumps = settings.air_jumps;
                         break;
                     }
                 }
@@ -142,6 +140,16 @@ impl CollisionSystem {
         return Self::oob(player.x, player.y, map);
     }
 
+    // Only checks the middle of the player, collisions keep everything else clear of the terrain
+    #[cfg(not(target_family = "wasm"))]
+    pub fn player_in_terrain(player: &Player, map: &RgbaImage) -> bool {
+        if Self::player_oob(player, map) {
+            return false;
+        }
+
+        return map.get_pixel(player.x as u32, player.y as u32)[3] > 0;
+    }
+
     #[cfg(not(target_family = "wasm"))]
     pub fn oob(x: f32, y: f32, map: &RgbaImage) -> bool {
         if x < 2.0 || x >= (map.width() - 2) as f32 {
@@ -1,7 +1,14 @@
+pub mod client_message;
 pub mod collision_system;
+pub mod match_settings;
+pub mod movement_system;
 pub mod player;
+pub mod player_input;
 pub mod projectile;
 pub mod proto;
+pub mod replay;
+pub mod server_message;
 pub mod server_tick;
+pub mod terrain;
 pub mod vel_system;
 pub mod weapon;
@@ -0,0 +1,153 @@
+use crate::player::Player;
+use crate::server_tick::ServerTick;
+
+// Rules for a single match, picked by the host in the lobby
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchSettings {
+    pub kills_to_win: i32,
+    // In ms
+    pub time_to_respawn: i32,
+    pub max_health: f32,
+    pub air_jumps: i32,
+    // In seconds
+    pub weapon_spawn_interval: i32,
+    pub weapon_spawn_count: usize,
+    // In ms, comes from the server's config rather than the host so clients interpolate at the
+    // rate the match actually runs
+    pub server_tick: i32,
+}
+
+impl MatchSettings {
+    pub const KILLS_TO_WIN: i32 = 5;
+    // Spawn new weapons every 30 seconds
+    pub const WEAPON_SPAWN_INTERVAL: i32 = 30;
+    pub const WEAPON_SPAWN_COUNT: usize = 10;
+
+    pub const SETTING_KILLS_TO_WIN: usize = 0;
+    pub const SETTING_TIME_TO_RESPAWN: usize = 1;
+    pub const SETTING_MAX_HEALTH: usize = 2;
+    pub const SETTING_AIR_JUMPS: usize = 3;
+    pub const SETTING_WEAPON_SPAWN_INTERVAL: usize = 4;
+    pub const SETTING_WEAPON_SPAWN_COUNT: usize = 5;
+    pub const SETTINGS_COUNT: usize = 6;
+
+    // Values the lobby cycles through when the host clicks a setting
+    #[cfg(target_family = "wasm")]
+    const KILLS_TO_WIN_OPTIONS: [i32; 5] = [1, 3, 5, 10, 20];
+    #[cfg(target_family = "wasm")]
+    const TIME_TO_RESPAWN_OPTIONS: [i32; 4] = [1000, 3000, 5000, 10000];
+    #[cfg(target_family = "wasm")]
+    const MAX_HEALTH_OPTIONS: [f32; 4] = [5.0, 10.0, 15.0, 20.0];
+    #[cfg(target_family = "wasm")]
+    const AIR_JUMPS_OPTIONS: [i32; 5] = [0, 1, 2, 3, 5];
+    #[cfg(target_family = "wasm")]
+    const WEAPON_SPAWN_INTERVAL_OPTIONS: [i32; 4] = [10, 20, 30, 60];
+    #[cfg(target_family = "wasm")]
+    const WEAPON_SPAWN_COUNT_OPTIONS: [usize; 4] = [5, 10, 20, 30];
+
+    pub fn new() -> MatchSettings {
+        return MatchSettings {
+            kills_to_win: Self::KILLS_TO_WIN,
+            time_to_respawn: Player::TIME_TO_RESPAWN,
+            max_health: Player::MAX_HEALTH,
+            air_jumps: Player::AIR_JUMPS,
+            weapon_spawn_interval: Self::WEAPON_SPAWN_INTERVAL,
+            weapon_spawn_count: Self::WEAPON_SPAWN_COUNT,
+            server_tick: ServerTick::SERVER_TICK,
+        }
+    }
+
+    // Settings come from the host's client so they are checked before being used
+    #[cfg(not(target_family = "wasm"))]
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.kills_to_win < 1 || self.kills_to_win > 100 {
+            return Err("Kills to win must be between 1 and 100");
+        }
+
+        if self.time_to_respawn < 0 || self.time_to_respawn > 60*1000 {
+            return Err("Respawn delay must be between 0 and 60 seconds");
+        }
+
+        if !(self.max_health >= 1.0 && self.max_health <= 100.0) {
+            return Err("Max health must be between 1 and 100");
+        }
+
+        if self.air_jumps < 0 || self.air_jumps > 10 {
+            return Err("Air jumps must be between 0 and 10");
+        }
+
+        if self.weapon_spawn_interval < 1 || self.weapon_spawn_interval > 600 {
+            return Err("Weapon spawn interval must be between 1 and 600 seconds");
+        }
+
+        if self.weapon_spawn_count < 1 || self.weapon_spawn_count > 50 {
+            return Err("Weapon spawn count must be between 1 and 50");
+        }
+
+        return Ok(());
+    }
+
+    // Moves a setting on to its next option, wrapping back to the first
+    #[cfg(target_family = "wasm")]
+    pub fn cycle(&mut self, setting_i: usize) {
+        match setting_i {
+            Self::SETTING_KILLS_TO_WIN => {
+                self.kills_to_win = Self::next_option(&Self::KILLS_TO_WIN_OPTIONS, self.kills_to_win);
+            },
+            Self::SETTING_TIME_TO_RESPAWN => {
+                self.time_to_respawn
+                    = Self::next_option(&Self::TIME_TO_RESPAWN_OPTIONS, self.time_to_respawn);
+            },
+            Self::SETTING_MAX_HEALTH => {
+                self.max_health = Self::next_option(&Self::MAX_HEALTH_OPTIONS, self.max_health);
+            },
+            Self::SETTING_AIR_JUMPS => {
+                self.air_jumps = Self::next_option(&Self::AIR_JUMPS_OPTIONS, self.air_jumps);
+            },
+            Self::SETTING_WEAPON_SPAWN_INTERVAL => {
+                self.weapon_spawn_interval = Self::next_option(
+                    &Self::WEAPON_SPAWN_INTERVAL_OPTIONS,
+                    self.weapon_spawn_interval,
+                );
+            },
+            Self::SETTING_WEAPON_SPAWN_COUNT => {
+                self.weapon_spawn_count = Self::next_option(
+                    &Self::WEAPON_SPAWN_COUNT_OPTIONS,
+                    self.weapon_spawn_count,
+                );
+            },
+            _ => (),
+        }
+    }
+
+    // Values that aren't one of the options (set in the server config) go back to the first
+    #[cfg(target_family = "wasm")]
+    fn next_option<T: Copy + PartialEq>(options: &[T], current: T) -> T {
+        for i in 0..options.len() {
+            if options[i] == current {
+                return options[(i + 1) % options.len()];
+            }
+        }
+
+        return options[0];
+    }
+
+    // Indexed the same as the SETTING_ consts
+    #[cfg(target_family = "wasm")]
+    pub fn labels(&self) -> [String; Self::SETTINGS_COUNT] {
+        return [
+            format!("Kills: {}", self.kills_to_win),
+            format!("Respawn: {}s", self.time_to_respawn / 1000),
+            format!("Health: {}", self.max_health),
+            format!("Jumps: {}", self.air_jumps),
+            format!("Spawn every: {}s", self.weapon_spawn_interval),
+            format!("Weapons: {}", self.weapon_spawn_count),
+        ];
+    }
+}
+
+impl Default for MatchSettings {
+    fn default() -> MatchSettings {
+        return MatchSettings::new();
+    }
+}
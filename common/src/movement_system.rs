@@ -0,0 +1,36 @@
+use crate::collision_system::CollisionSystem;
+use crate::match_settings::MatchSettings;
+use crate::player::Player;
+use crate::player_input::PlayerInput;
+use crate::terrain::Terrain;
+use crate::vel_system::VelSystem;
+
+pub struct MovementSystem {}
+
+impl MovementSystem {
+    // Run by the client to predict its own player and by the server to decide where it really is
+    pub fn apply_input<T: Terrain>(
+        player: &mut Player,
+        input: &PlayerInput,
+        terrain: &T,
+        settings: &MatchSettings,
+    ) {
+        let time_elapsed = input.time_elapsed.clamp(0.0, PlayerInput::MAX_TIME_ELAPSED);
+
+        if input.left {
+            player.x -= Player::MOVE_SPEED*time_elapsed;
+        } else if input.right {
+            player.x += Player::MOVE_SPEED*time_elapsed;
+        }
+
+        if input.jump && player.jumps > 0 {
+            player.vel_y = Player::JUMP_VEL;
+            player.jumps -= 1;
+        }
+
+        player.angle = input.angle;
+
+        VelSystem::update_player(player, time_elapsed);
+        CollisionSystem::update_player(player, terrain, settings);
+    }
+}
@@ -1,5 +1,7 @@
+use crate::match_settings::MatchSettings;
 use crate::weapon::Weapon;
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct Player {
     pub name: String,
 
@@ -27,6 +29,9 @@ pub struct Player {
 
     pub kills: i32,
     pub deaths: i32,
+
+    // The client's last sent input, or the last one the server applied
+    pub input_seq: u32,
 }
 
 impl Player {
@@ -44,15 +49,14 @@ impl Player {
         [150, 50, 150],
     ];
 
+    // Defaults for MatchSettings
     pub const AIR_JUMPS: i32 = 3;
 
-    #[cfg(target_family = "wasm")]
     pub const JUMP_VEL: f32 = -0.5;
 
     pub const MAX_HEALTH: f32 = 10.0;
 
     // Move 100 pixels per second
-    #[cfg(target_family = "wasm")]
     pub const MOVE_SPEED: f32 = 100.0/1000.0;
 
     // Respawn after 5 seconds
@@ -80,26 +84,33 @@ impl Player {
             time_to_respawn: 0,
             kills: 0,
             deaths: 0,
+            input_seq: 0,
         }
     }
 
+    // Players are created in the lobby before the host has settled on the rules
+    pub fn apply_settings(&mut self, settings: &MatchSettings) {
+        self.health = settings.max_health;
+        self.jumps = settings.air_jumps;
+    }
+
     pub fn assign_weapon(&mut self, weapon_type: usize) {
         self.weapon_type = Some(weapon_type);
         self.ammo = Weapon::WEAPON_TYPES[weapon_type].ammo_count;
         self.ticks_since_last_fire = -1;
     }
 
-    pub fn kill(&mut self) {
+    pub fn kill(&mut self, settings: &MatchSettings) {
         self.alive = false;
         self.ammo = 0;
         self.weapon_type = None;
         self.deaths += 1;
-        self.time_to_respawn = Self::TIME_TO_RESPAWN;
+        self.time_to_respawn = settings.time_to_respawn;
     }
 
-    pub fn respawn(&mut self, x: f32, y: f32) {
+    pub fn respawn(&mut self, x: f32, y: f32, settings: &MatchSettings) {
         self.alive = true;
-        self.health = Self::MAX_HEALTH;
+        self.health = settings.max_health;
         self.x_last = x;
         self.y_last = y;
         self.x = x;
@@ -0,0 +1,18 @@
+// One frame of a player's controls. The client applies it straight away and sends it to the
+// server, which applies it again and has the final say on where the player ends up
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlayerInput {
+    // Counts up from 1 so the server can say which inputs it has applied
+    pub seq: u32,
+    pub left: bool,
+    pub right: bool,
+    pub jump: bool,
+    pub angle: f32,
+    // In ms
+    pub time_elapsed: f32,
+}
+
+impl PlayerInput {
+    // Longer frames are cut short so a stalled client can't skip through terrain
+    pub const MAX_TIME_ELAPSED: f32 = 100.0;
+}
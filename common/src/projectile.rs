@@ -6,7 +6,10 @@ pub struct ProjectileType {
     pub damage: f32,
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct Projectile {
+    // Handed out by the server, see Proto
+    pub id: u32,
     pub projectile_type: usize,
     pub x: f32,
     pub y: f32,
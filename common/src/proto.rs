@@ -1,26 +1,80 @@
-use crate::projectile::Projectile;
+use crate::match_settings::MatchSettings;
 
+use std::fmt;
 use std::str;
 
+// Message type ids and the byte layout of each message. ClientMessage and ServerMessage do the
+// actual encoding and decoding
 pub struct Proto {}
 
+// Why a message couldn't be decoded. Decoding never panics, whatever the bytes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProtoError {
+    TooShort,
+    TrailingBytes,
+    BadUtf8,
+    // A message type, status, flag or type index that doesn't exist
+    UnknownValue,
+    // NaN or infinity where only real numbers make sense
+    NonFinite,
+}
+
+impl fmt::Display for ProtoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            ProtoError::TooShort => "Message too short",
+            ProtoError::TrailingBytes => "Unexpected bytes at the end of the message",
+            ProtoError::BadUtf8 => "Invalid UTF-8 in message",
+            ProtoError::UnknownValue => "Unknown value in message",
+            ProtoError::NonFinite => "Non-finite number in message",
+        };
+
+        return write!(f, "{}", description);
+    }
+}
+
 impl Proto {
     pub const TST_JOIN_EXISTING: u8 = 0x00;
+    // TST (u8) + public (u8) + name
     pub const TST_CREATE_NEW: u8 = 0x01;
     pub const TST_TOGGLE_READY: u8 = 0x02;
-    pub const TST_NEW_POS: u8 = 0x03;
-    pub const TST_NEW_ANGLE: u8 = 0x04;
     pub const TST_TAKE_WEAPON: u8 = 0x05;
     pub const TST_TRIGGER_PULLED: u8 = 0x06;
     pub const TST_TRIGGER_RELEASED: u8 = 0x07;
-
-    // TCT (u8) + status (u8)
+    pub const TST_LIST_LOBBIES: u8 = 0x08;
+    // TST (u8) + resume token
+    pub const TST_RESUME: u8 = 0x09;
+    pub const TST_LEAVE_MATCH: u8 = 0x0A;
+    // TST (u8) + player index (u8), host only
+    pub const TST_KICK_PLAYER: u8 = 0x0B;
+    // Host only
+    pub const TST_FORCE_START: u8 = 0x0C;
+    // Host only, toggles whether new players can join
+    pub const TST_LOCK_LOBBY: u8 = 0x0D;
+    // TST (u8) + match settings, host only
+    pub const TST_UPDATE_SETTINGS: u8 = 0x0E;
+    // TST (u8) + code + name, watches the match without a player
+    pub const TST_SPECTATE_EXISTING: u8 = 0x0F;
+    // TST (u8) + sequence number (u32) + input flags (u8) + angle, time elapsed (f32)
+    pub const TST_INPUT: u8 = 0x10;
+    pub const INPUT_LEFT: u8 = 0x01;
+    pub const INPUT_RIGHT: u8 = 0x02;
+    pub const INPUT_JUMP: u8 = 0x04;
+    // TST (u8) + protocol version (u16), always the first message on a connection. Its layout
+    // can never change so that any two versions can tell they don't match
+    pub const TST_HELLO: u8 = 0x11;
+
+    // TCT (u8) + status (u8) + resume token on success
     pub const TCT_JOIN_EXISTING_RESULT: u8 = 0x80;
     pub const JOIN_EXISTING_RESULT_SUCCESS: u8 = 0x01;
     pub const JOIN_EXISTING_RESULT_BAD_CODE: u8 = 0x02;
     pub const JOIN_EXISTING_RESULT_SERVER_ERROR: u8 = 0x03;
+    pub const JOIN_EXISTING_RESULT_FULL: u8 = 0x04;
+    pub const JOIN_EXISTING_RESULT_LOCKED: u8 = 0x05;
+    // Spectators can't resume so they don't get a token
+    pub const JOIN_EXISTING_RESULT_SPECTATING: u8 = 0x06;
 
-    // TCT (u8) + status (u8)
+    // TCT (u8) + status (u8) + resume token and code on success
     pub const TCT_CREATE_NEW_RESULT: u8 = 0x81;
     //pub const CREATE_NEW_RESULT_SUCCESS: u8 = 0x01;
     //pub const CREATE_NEW_RESULT_SERVER_ERROR: u8 = 0x03;
@@ -36,10 +90,18 @@ impl Proto {
 
     pub const TCT_NEW_POS: u8 = 0x85;
     pub const TCT_NEW_ANGLE: u8 = 0x86;
+
+    // Ground weapons and projectiles are referred to by ids (u32) the engine hands out in
+    // order, so they stay valid however either list is rearranged
+
+    // TCT (u8) + weapon id (u32) + weapon type (u8) + x, y (f32)
     pub const TCT_WEAPON_SPAWN: u8 = 0x87;
+    // TCT (u8) + weapon id (u32)
     pub const TCT_REMOVE_WEAPON: u8 = 0x88;
     pub const TCT_ASSIGN_WEAPON: u8 = 0x89;
+    // TCT (u8) + projectile id (u32) + projectile type (u8) + x, y, vel_x, vel_y (f32)
     pub const TCT_NEW_PROJECTILE: u8 = 0x90;
+    // TCT (u8) + projectile id (u32)
     pub const TCT_DESTROY_PROJECTILE: u8 = 0x91;
     pub const TCT_PROJECTILE_EXPLOSION: u8 = 0x92;
     pub const TCT_UPDATE_HEALTH: u8 = 0x93;
@@ -48,649 +110,257 @@ impl Proto {
     pub const TCT_RESPAWN_PLAYER: u8 = 0x96;
     pub const TCT_GAME_OVER_STATS: u8 = 0x97;
 
-    pub const SEPARATOR: u8 = 0x1E;
-
-    pub const FALSE: u8 = 0x00;
-    pub const TRUE: u8 = 0xff;
-
-    pub fn get_type(message: &Vec<u8>) -> Result<u8, &'static str> {
-        if message.len() > 0 {
-            return Ok(message[0]);
-        }
-
-        return Err("Unable to get message type");
-    }
-
-    // +--------+
-    // | Pasers |
-    // +--------+
-
-    #[cfg(not(target_family = "wasm"))]
-    pub fn parse_tst_join_existing(
-        message: &Vec<u8>,
-        game_code_length: i32,
-    ) -> Result<(String, String), &'static str> {
-        let game_code_length = game_code_length as usize;
-
-        if message.len() >= 1 + game_code_length + 1 {
-            let code = str::from_utf8(&message[1..(game_code_length + 1)]);
-            let name = str::from_utf8(&message[(game_code_length + 1)..]);
-
-            if code.is_err() {
-                return Err("Invalid code bytes");
-            }
-
-            if name.is_err() {
-                return Err("Invalid name bytes");
-            }
-
-            return Ok((
-                String::from(code.unwrap()),
-                String::from(name.unwrap()),
-            ));
-        }
-
-        return Err("Message too short");
-    }
+    // TCT (u8) + seconds until the server forces the match to end (u32)
+    pub const TCT_SERVER_SHUTDOWN: u8 = 0x98;
 
-    #[cfg(target_family = "wasm")]
-    pub fn parse_tct_player_list(
-        message: &Vec<u8>,
-    ) -> Result<Vec<String>, &'static str> {
-        if message.len() >= 2 {
-            let mut player_names: Vec<String> = Vec::new();
-
-            let mut name_buff: String = String::new();
-
-            for i in 1..message.len() {
-                if &message[i] == &Proto::SEPARATOR {
-                    let new_player = String::from(name_buff.clone());
-                    name_buff.clear();
-                    player_names.push(new_player);
-
-                } else {
-                    let character = message[i].clone();
-                    let character = &[character];
-                    let character = str::from_utf8(character);
-                    if character.is_err() {
-                        return Err("Unable to parse name");
-                    }
-                    name_buff += character.unwrap();
-                }
-            }
-
-            return Ok(player_names);
-        }
+    // TCT (u8) + per lobby: player count (u8) + code + SEPARATOR + host name + SEPARATOR
+    pub const TCT_LOBBY_LIST: u8 = 0x99;
 
-        return Err("Message too short");
-    }
-
-    #[cfg(not(target_family = "wasm"))]
-    pub fn parse_tst_create_new(
-        message: &Vec<u8>,
-    ) -> Result<String, &'static str> {
-        if message.len() > 1 {
-            let name = str::from_utf8(&message[1..]);
-
-            if name.is_err() {
-                return Err("Invalid name bytes");
-            }
-
-            return Ok(String::from(name.unwrap()));
-        }
+    // TCT (u8) + status (u8) + player index (u8)
+    pub const TCT_RESUME_RESULT: u8 = 0xA0;
+    pub const RESUME_RESULT_SUCCESS: u8 = 0x01;
+    pub const RESUME_RESULT_EXPIRED: u8 = 0x02;
 
-        return Err("Message too short");
-    }
+    // TCT (u8) + your player index (u8)
+    // + player count (u8) + per player: x, y, angle, health (f32) + alive (u8) + weapon type (u8)
+    //   + ammo, time to respawn, kills, deaths (i32)
+    // + weapon count (u8) + per weapon: weapon id (u32) + weapon type (u8) + x, y (f32)
+    // + projectile count (u16) + per projectile: projectile id (u32) + projectile type (u8)
+    //   + x, y, vel_x, vel_y (f32)
+    pub const TCT_GAME_STATE: u8 = 0xA1;
+    // Sent as the player index to spectators
+    pub const SPECTATOR: u8 = 0xff;
+    pub const NO_WEAPON: u8 = 0xff;
 
-    #[cfg(target_family = "wasm")]
-    pub fn parse_tct_join_existing_result(
-        message: &Vec<u8>,
-    ) -> Result<u8, &'static str> {
-        if message.len() > 1 {
-            return Ok(message[1]);
-        }
+    // TCT (u8) + per run of destroyed map pixels: x, y, length (u16)
+    pub const TCT_TERRAIN: u8 = 0xA2;
 
-        return Err("Message too short");
-    }
+    // TCT (u8) + player index (u8) + x, y (f32) + name
+    pub const TCT_PLAYER_JOINED: u8 = 0xA3;
 
-    #[cfg(target_family = "wasm")]
-    pub fn parse_tct_create_new_result(
-        message: &Vec<u8>,
-    ) -> Result<(u8, String), &'static str> {
-        if message.len() > 2 {
-            let status = message[1];
-            let code = str::from_utf8(&message[2..]);
+    // TCT (u8) + host index (u8) + your player index (u8) + locked (u8)
+    pub const TCT_LOBBY_STATE: u8 = 0xA4;
 
-            if code.is_err() {
-                return Err("Invalid code bytes");
-            }
+    // TCT (u8) + reason (u8), sent to a player right before they are removed
+    pub const TCT_KICKED: u8 = 0xA5;
+    pub const KICK_REASON_HOST: u8 = 0x01;
+    pub const KICK_REASON_FLOOD: u8 = 0x02;
+    pub const KICK_REASON_INVALID_MESSAGE: u8 = 0x03;
+    pub const KICK_REASON_SPEED: u8 = 0x04;
 
-            return Ok((status, String::from(code.unwrap())))
-        }
+    // TCT (u8) + match settings
+    pub const TCT_MATCH_SETTINGS: u8 = 0xA6;
 
-        return Err("Message too short");
-    }
+    // TCT (u8) + last applied sequence number (u32) + x, y, vel_y (f32) + jumps (u8)
+    pub const TCT_INPUT_ACK: u8 = 0xA7;
 
-    #[cfg(target_family = "wasm")]
-    pub fn parse_tct_toggle_ready(
-        message: &Vec<u8>,
-    ) -> Result<(usize, bool), &'static str> {
-        if message.len() > 2 {
-            let player_i = message[1] as usize;
-
-            match message[2] {
-                Proto::TRUE => {
-                    return Ok((player_i, true));
-                }
-                Proto::FALSE => {
-                    return Ok((player_i, false));
-                }
-                _ => {
-                    return Err("Unknown ready state");
-                }
-            }
-        }
+    // TCT (u8) + the server's protocol version (u16), sent right before the server closes a
+    // connection whose hello didn't match. Its layout can never change either
+    pub const TCT_VERSION_MISMATCH: u8 = 0xA8;
 
-        return Err("Message too short");
-    }
+    // TCT (u8) + for each message: length (u32) + message. Everything the server sends a client
+    // during a tick goes out together as one of these
+    pub const TCT_BUNDLE: u8 = 0xA9;
 
-    #[cfg(target_family = "wasm")]
-    pub fn parse_tct_start_game(
-        message: &Vec<u8>,
-    ) -> Result<(usize, f32, f32), &'static str> {
-        if message.len() >= 10 {
-            let player_i = message[1] as usize;
-            let x_bytes:[u8;4] = message[2..6].try_into().unwrap();
-            let y_bytes:[u8;4] = message[6..10].try_into().unwrap();
-            let x = f32::from_le_bytes(x_bytes);
-            let y = f32::from_le_bytes(y_bytes);
-
-            return Ok((player_i, x, y));
-        }
+    // TCT (u8), the match hit a bug on the server and was shut down
+    pub const TCT_MATCH_ERROR: u8 = 0xAA;
 
-        return Err("Message too short");
-    }
+    // Bumped whenever a message is added or its layout changes
+    pub const PROTOCOL_VERSION: u16 = 5;
 
-    #[cfg(not(target_family = "wasm"))]
-    pub fn parse_tst_new_pos(
-        message: &Vec<u8>,
-    ) -> Result<(f32, f32), &'static str> {
-        if message.len() >= 9 {
-            let x_bytes:[u8;4] = message[1..5].try_into().unwrap();
-            let y_bytes:[u8;4] = message[5..9].try_into().unwrap();
+    // Match settings: kills to win, time to respawn (i32) + max health (f32) + air jumps,
+    // weapon spawn interval (i32) + weapon spawn count (u8) + server tick (i32)
+    const MATCH_SETTINGS_LEN: usize = 25;
 
-            let x = f32::from_le_bytes(x_bytes);
-            let y = f32::from_le_bytes(y_bytes);
+    // Handed out when joining a match and used to reclaim the slot after a disconnect
+    pub const RESUME_TOKEN_LEN: usize = 16;
 
-            return Ok((x, y));
-        }
+    // Codes have no separator, the name starts right after them
+    pub const GAME_CODE_LEN: usize = 6;
 
-        return Err("Message too short");
-    }
-
-    #[cfg(not(target_family = "wasm"))]
-    pub fn parse_tst_new_angle (
-        message: &Vec<u8>,
-    ) -> Result<f32, &'static str> {
-        if message.len() >= 5 {
-            let angle_bytes:[u8;4] = message[1..5].try_into().unwrap();
-
-            let angle = f32::from_le_bytes(angle_bytes);
-
-            return Ok(angle);
-        }
-
-        return Err("Message too short");
-    }
-
-    #[cfg(target_family = "wasm")]
-    pub fn parse_tct_new_pos(
-        message: &Vec<u8>,
-    ) -> Result<(usize, f32, f32), &'static str> {
-        if message.len() >= 10 {
-            let player_i = message[1] as usize;
-
-            let x_bytes:[u8;4] = message[2..6].try_into().unwrap();
-            let y_bytes:[u8;4] = message[6..10].try_into().unwrap();
-
-            let x = f32::from_le_bytes(x_bytes);
-            let y = f32::from_le_bytes(y_bytes);
-
-            return Ok((player_i, x, y));
-        }
-
-        return Err("Message too short");
-    }
-
-    #[cfg(target_family = "wasm")]
-    pub fn parse_tct_new_angle(
-        message: &Vec<u8>,
-    ) -> Result<(usize, f32), &'static str> {
-        if message.len() > 5 {
-            let player_i = message[1] as usize;
-
-            let angle_bytes:[u8;4] = message[2..6].try_into().unwrap();
-
-            let angle = f32::from_le_bytes(angle_bytes);
-
-            return Ok((player_i, angle));
-        }
-
-        return Err("Message too short");
-    }
-
-    #[cfg(target_family = "wasm")]
-    pub fn parse_tct_weapon_spawn(
-        message: &Vec<u8>,
-    ) -> Result<(usize, f32, f32), &'static str> {
-        if message.len() >= 10 {
-            let weapon_type = message[1] as usize;
-
-            let x_bytes:[u8;4] = message[2..6].try_into().unwrap();
-            let y_bytes:[u8;4] = message[6..10].try_into().unwrap();
-
-            let x = f32::from_le_bytes(x_bytes);
-            let y = f32::from_le_bytes(y_bytes);
-
-            return Ok((weapon_type, x, y));
-        }
-
-        return Err("Message too short");
-    }
+    pub const SEPARATOR: u8 = 0x1E;
 
-    #[cfg(target_family = "wasm")]
-    pub fn parse_tct_remove_weapon(
-        message: &Vec<u8>,
-    ) -> Result<usize, &'static str> {
-        if message.len() > 1 {
-            let weapon_i = message[1] as usize;
+    pub const FALSE: u8 = 0x00;
+    pub const TRUE: u8 = 0xff;
 
-            return Ok(weapon_i);
+    pub fn get_type(message: &Vec<u8>) -> Result<u8, ProtoError> {
+        if message.len() > 0 {
+            return Ok(message[0]);
         }
 
-        return Err("Message too short");
-    }
-
-    #[cfg(target_family = "wasm")]
-    pub fn parse_tct_assign_weapon(
-        message: &Vec<u8>,
-    ) -> Result<(usize, usize), &'static str> {
-        if message.len() > 2 {
-            let player_i = message[1] as usize;
-            let weapon_type = message[2] as usize;
-
-            return Ok((player_i, weapon_type));
+        return Err(ProtoError::TooShort);
+    }
+
+    // Human readable message type, used for labelling metrics and logs
+    pub fn type_name(message_type: u8) -> &'static str {
+        match message_type {
+            Self::TST_JOIN_EXISTING => "tst_join_existing",
+            Self::TST_CREATE_NEW => "tst_create_new",
+            Self::TST_TOGGLE_READY => "tst_toggle_ready",
+            Self::TST_TAKE_WEAPON => "tst_take_weapon",
+            Self::TST_TRIGGER_PULLED => "tst_trigger_pulled",
+            Self::TST_TRIGGER_RELEASED => "tst_trigger_released",
+            Self::TST_LIST_LOBBIES => "tst_list_lobbies",
+            Self::TST_RESUME => "tst_resume",
+            Self::TST_LEAVE_MATCH => "tst_leave_match",
+            Self::TST_KICK_PLAYER => "tst_kick_player",
+            Self::TST_FORCE_START => "tst_force_start",
+            Self::TST_LOCK_LOBBY => "tst_lock_lobby",
+            Self::TST_UPDATE_SETTINGS => "tst_update_settings",
+            Self::TST_SPECTATE_EXISTING => "tst_spectate_existing",
+            Self::TST_INPUT => "tst_input",
+            Self::TST_HELLO => "tst_hello",
+            Self::TCT_JOIN_EXISTING_RESULT => "tct_join_existing_result",
+            Self::TCT_CREATE_NEW_RESULT => "tct_create_new_result",
+            Self::TCT_PLAYER_LIST => "tct_player_list",
+            Self::TCT_TOGGLE_READY => "tct_toggle_ready",
+            Self::TCT_START_GAME => "tct_start_game",
+            Self::TCT_NEW_POS => "tct_new_pos",
+            Self::TCT_NEW_ANGLE => "tct_new_angle",
+            Self::TCT_WEAPON_SPAWN => "tct_weapon_spawn",
+            Self::TCT_REMOVE_WEAPON => "tct_remove_weapon",
+            Self::TCT_ASSIGN_WEAPON => "tct_assign_weapon",
+            Self::TCT_NEW_PROJECTILE => "tct_new_projectile",
+            Self::TCT_DESTROY_PROJECTILE => "tct_destroy_projectile",
+            Self::TCT_PROJECTILE_EXPLOSION => "tct_projectile_explosion",
+            Self::TCT_UPDATE_HEALTH => "tct_update_health",
+            Self::TCT_REMOVE_AMMO => "tct_remove_ammo",
+            Self::TCT_KILL_PLAYER => "tct_kill_player",
+            Self::TCT_RESPAWN_PLAYER => "tct_respawn_player",
+            Self::TCT_GAME_OVER_STATS => "tct_game_over_stats",
+            Self::TCT_SERVER_SHUTDOWN => "tct_server_shutdown",
+            Self::TCT_LOBBY_LIST => "tct_lobby_list",
+            Self::TCT_RESUME_RESULT => "tct_resume_result",
+            Self::TCT_GAME_STATE => "tct_game_state",
+            Self::TCT_TERRAIN => "tct_terrain",
+            Self::TCT_PLAYER_JOINED => "tct_player_joined",
+            Self::TCT_LOBBY_STATE => "tct_lobby_state",
+            Self::TCT_KICKED => "tct_kicked",
+            Self::TCT_MATCH_SETTINGS => "tct_match_settings",
+            Self::TCT_INPUT_ACK => "tct_input_ack",
+            Self::TCT_VERSION_MISMATCH => "tct_version_mismatch",
+            Self::TCT_BUNDLE => "tct_bundle",
+            Self::TCT_MATCH_ERROR => "tct_match_error",
+            _ => "unknown",
         }
-
-        return Err("Message too short");
     }
 
-    #[cfg(target_family = "wasm")]
-    pub fn parse_tct_new_projectile(
-        message: &Vec<u8>,
-    ) -> Result<(usize, f32, f32, f32, f32), &'static str> {
-        if message.len() > 17 {
-            let projectile_type = message[1] as usize;
-
-            let x_bytes:[u8;4] = message[2..6].try_into().unwrap();
-            let x = f32::from_le_bytes(x_bytes);
-            let y_bytes:[u8;4] = message[6..10].try_into().unwrap();
-            let y = f32::from_le_bytes(y_bytes);
-
-            let vel_x_bytes:[u8;4] = message[10..14].try_into().unwrap();
-            let vel_x = f32::from_le_bytes(vel_x_bytes);
-            let vel_y_bytes:[u8;4] = message[14..18].try_into().unwrap();
-            let vel_y = f32::from_le_bytes(vel_y_bytes);
-
-            return Ok((projectile_type, x, y, vel_x, vel_y));
-        }
-
-        return Err("Message too short");
-    }
-
-    #[cfg(target_family = "wasm")]
-    pub fn parse_tct_projectile_explosion(
-        message: &Vec<u8>,
-    ) -> Result<Projectile, &'static str> {
-        if message.len() >= 10 {
-            let projectile_type = message[1] as usize;
-            let x_bytes:[u8;4] = message[2..6].try_into().unwrap();
-            let x = f32::from_le_bytes(x_bytes);
-            let y_bytes:[u8;4] = message[6..10].try_into().unwrap();
-            let y = f32::from_le_bytes(y_bytes);
-
-            return Ok(Projectile {
-                projectile_type: projectile_type,
-                x,
-                y,
-                vel_x: 0.0,
-                vel_y: 0.0,
-                owner: 0,
-            });
-        }
-
-        return Err("Message too short");
+    pub fn bool_byte(value: bool) -> u8 {
+        return match value {
+            true => Self::TRUE,
+            false => Self::FALSE,
+        };
     }
 
-    #[cfg(target_family = "wasm")]
-    pub fn parse_tct_destroy_projectile(
-        message: &Vec<u8>,
-    ) -> Result<usize, &'static str> {
-        if message.len() > 1 {
-            let projectile_i:usize = message[1] as usize;
-            return Ok(projectile_i);
+    // For messages with nothing variable length in them
+    pub fn check_len(message: &Vec<u8>, len: usize) -> Result<(), ProtoError> {
+        if message.len() < len {
+            return Err(ProtoError::TooShort);
         }
 
-        return Err("Message too short");
-    }
-
-    #[cfg(target_family = "wasm")]
-    pub fn parse_tct_update_health(
-        message: &Vec<u8>,
-    ) -> Result<f32, &'static str> {
-        if message.len() >= 4 {
-            let health_bytes:[u8;4] = message[1..5].try_into().unwrap();
-            let health = f32::from_le_bytes(health_bytes);
-            return Ok(health);
+        if message.len() > len {
+            return Err(ProtoError::TrailingBytes);
         }
 
-        return Err("Message too short");
+        return Ok(());
     }
 
-    #[cfg(target_family = "wasm")]
-    pub fn parse_tct_kill_player(
-        message: &Vec<u8>,
-    ) -> Result<usize, &'static str> {
-        if message.len() > 1 {
-            let player_i = message[1] as usize;
-            return Ok(player_i);
-        }
-
-        return Err("Message too short");
+    pub fn parse_bool(byte: u8) -> Result<bool, ProtoError> {
+        return match byte {
+            Self::TRUE => Ok(true),
+            Self::FALSE => Ok(false),
+            _ => Err(ProtoError::UnknownValue),
+        };
     }
 
-    #[cfg(target_family = "wasm")]
-    pub fn parse_tct_respawn_player(
-        message: &Vec<u8>,
-    ) -> Result<(usize, f32, f32), &'static str> {
-        if message.len() >= 10 {
-            let player_i = message[1] as usize;
-            let x_bytes:[u8;4] = message[2..6].try_into().unwrap();
-            let y_bytes:[u8;4] = message[6..10].try_into().unwrap();
-            let x = f32::from_le_bytes(x_bytes);
-            let y = f32::from_le_bytes(y_bytes);
-
-            return Ok((player_i, x, y));
-        }
-
-        return Err("Message too short");
+    pub fn parse_str(bytes: &[u8]) -> Result<String, ProtoError> {
+        return match str::from_utf8(bytes) {
+            Ok(string) => Ok(String::from(string)),
+            Err(_) => Err(ProtoError::BadUtf8),
+        };
     }
 
-    #[cfg(target_family = "wasm")]
-    pub fn parse_tct_game_over_stats(message: &Vec<u8>) -> Vec<(i32, i32)> {
-        let mut kills_deaths: Vec<(i32, i32)> = Vec::new();
-
-        for i in (1..(message.len()-1)).step_by(8) {
-            let kills_bytes:[u8;4] = message[i..(i+4)].try_into().unwrap();
-            let deaths_bytes:[u8;4] = message[(i+4)..(i+8)].try_into().unwrap();
-            let kills = i32::from_le_bytes(kills_bytes);
-            let deaths = i32::from_le_bytes(deaths_bytes);
-            kills_deaths.push((kills, deaths));
+    // Weapon and projectile types get used to index their type tables
+    pub fn parse_type_index(byte: u8, type_count: usize) -> Result<usize, ProtoError> {
+        if byte as usize >= type_count {
+            return Err(ProtoError::UnknownValue);
         }
 
-        return kills_deaths;
-    }
-
-    // +-------------+
-    // | Serializers |
-    // +-------------+
-
-    #[cfg(target_family = "wasm")]
-    pub fn tst_create_new(name: &String) -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::new();
-        data.push(Self::TST_CREATE_NEW);
-        data.extend_from_slice(name.as_bytes());
-        return data;
-    }
-
-    #[cfg(target_family = "wasm")]
-    pub fn tst_join_existing(code: &String, name: &String) -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::new();
-        data.push(Self::TST_JOIN_EXISTING);
-        data.extend_from_slice(code.as_bytes());
-        data.extend_from_slice(name.as_bytes());
-        return data;
-    }
-
-    #[cfg(not(target_family = "wasm"))]
-    pub fn tct_join_existing_result(status: u8) -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::new();
-        data.push(Self::TCT_JOIN_EXISTING_RESULT);
-        data.push(status);
-        return data;
-    }
-
-    #[cfg(not(target_family = "wasm"))]
-    pub fn tct_create_new_result(status: u8, code: &String) -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::new();
-        data.push(Self::TCT_CREATE_NEW_RESULT);
-        data.push(status);
-        data.extend_from_slice(code.as_bytes());
-        return data;
+        return Ok(byte as usize);
     }
 
-
-    #[cfg(not(target_family = "wasm"))]
-    pub fn tct_player_list(names: &Vec<String>) -> Vec<u8> {
+    // Messages are already encoded, a bundle only frames them
+    pub fn bundle(messages: &Vec<Vec<u8>>) -> Vec<u8> {
         let mut data: Vec<u8> = Vec::new();
-        data.push(Self::TCT_PLAYER_LIST);
+        data.push(Self::TCT_BUNDLE);
 
-        for name in names {
-            data.extend_from_slice(name.as_bytes());
-            data.push(Self::SEPARATOR);
+        for message in messages {
+            data.extend_from_slice(&(message.len() as u32).to_le_bytes());
+            data.extend_from_slice(message);
         }
 
         return data;
     }
 
-    #[cfg(target_family = "wasm")]
-    pub fn tst_toggle_ready() -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::new();
-        data.push(Self::TST_TOGGLE_READY);
-        return data;
-    }
-
-    #[cfg(not(target_family = "wasm"))]
-    pub fn tct_toggle_ready(player_i: usize, ready: bool) -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::new();
-        data.push(Self::TCT_TOGGLE_READY);
-        data.push(player_i as u8);
-
-        match ready {
-            true => data.push(Self::TRUE),
-            false => data.push(Self::FALSE),
+    // Hands back the messages still encoded, each one is decoded on its own
+    pub fn unbundle(message: &Vec<u8>) -> Result<Vec<Vec<u8>>, ProtoError> {
+        if Self::get_type(message)? != Self::TCT_BUNDLE {
+            return Err(ProtoError::UnknownValue);
         }
 
-        return data;
-    }
-
-    #[cfg(not(target_family = "wasm"))]
-    pub fn tct_start_game(player_i: usize, player_x: f32, player_y: f32) -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::new();
-
-        data.push(Self::TCT_START_GAME);
-        data.push(player_i as u8);
-        data.extend_from_slice(&(player_x.to_le_bytes()));
-        data.extend_from_slice(&(player_y.to_le_bytes()));
-
-        return data;
-    }
-
-    #[cfg(target_family = "wasm")]
-    pub fn tst_new_pos(player_x: f32, player_y: f32) -> Vec<u8> {
-        let mut data:Vec<u8> = Vec::new();
-
-        data.push(Proto::TST_NEW_POS);
-        data.extend_from_slice(&(player_x.to_le_bytes()));
-        data.extend_from_slice(&(player_y.to_le_bytes()));
-
-        return data;
-    }
-
-    #[cfg(not(target_family = "wasm"))]
-    pub fn tct_new_pos(player_i: usize, player_x: f32, player_y: f32) -> Vec<u8> {
-        let mut data:Vec<u8> = Vec::new();
+        let mut messages: Vec<Vec<u8>> = Vec::new();
 
-        data.push(Proto::TCT_NEW_POS);
-        data.push(player_i as u8);
-        data.extend_from_slice(&(player_x.to_le_bytes()));
-        data.extend_from_slice(&(player_y.to_le_bytes()));
-
-        return data;
-    }
-
-    #[cfg(not(target_family = "wasm"))]
-    pub fn tct_new_angle(player_i: usize, angle: f32) -> Vec<u8> {
-        let mut data:Vec<u8> = Vec::new();
-
-        data.push(Proto::TCT_NEW_ANGLE);
-        data.push(player_i as u8);
-        data.extend_from_slice(&(angle.to_le_bytes()));
-
-        return data;
-    }
-
-    #[cfg(target_family = "wasm")]
-    pub fn tst_new_angle(angle: f32) -> Vec<u8> {
-        let mut data:Vec<u8> = Vec::new();
-
-        data.push(Proto::TST_NEW_ANGLE);
-        data.extend_from_slice(&(angle.to_le_bytes()));
-
-        return data;
-    }
+        let mut i = 1;
 
-    #[cfg(target_family = "wasm")]
-    pub fn tst_take_weapon() -> Vec<u8> {
-        let mut data:Vec<u8> = Vec::new();
-        data.push(Proto::TST_TAKE_WEAPON);
-        return data;
-    }
-
-    #[cfg(target_family = "wasm")]
-    pub fn tst_trigger_pulled() -> Vec<u8> {
-        let mut data:Vec<u8> = Vec::new();
-        data.push(Proto::TST_TRIGGER_PULLED);
-        return data;
-    }
-
-    #[cfg(target_family = "wasm")]
-    pub fn tst_trigger_released() -> Vec<u8> {
-        let mut data:Vec<u8> = Vec::new();
-        data.push(Proto::TST_TRIGGER_RELEASED);
-        return data;
-    }
-
-    #[cfg(not(target_family = "wasm"))]
-    pub fn tct_weapon_spawn(weapon_type: usize, x: f32, y: f32) -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::new();
-        data.push(Self::TCT_WEAPON_SPAWN);
-        data.push(weapon_type as u8);
-        data.extend_from_slice(&x.to_le_bytes());
-        data.extend_from_slice(&y.to_le_bytes());
-        return data;
-    }
-
-    #[cfg(not(target_family = "wasm"))]
-    pub fn tct_remove_weapon(weapon_i: usize) -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::new();
-        data.push(Self::TCT_REMOVE_WEAPON);
-        data.push(weapon_i as u8);
-        return data;
-    }
-
-    #[cfg(not(target_family = "wasm"))]
-    pub fn tct_assign_weapon(player_i: usize, weapon_type: usize) -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::new();
-        data.push(Self::TCT_ASSIGN_WEAPON);
-        data.push(player_i as u8);
-        data.push(weapon_type as u8);
-        return data;
-    }
-
-    #[cfg(not(target_family = "wasm"))]
-    pub fn tct_new_projectile(projectile: &Projectile) -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::new();
-        data.push(Self::TCT_NEW_PROJECTILE);
-        data.push(projectile.projectile_type as u8);
-        data.extend_from_slice(&(projectile.x.to_le_bytes()));
-        data.extend_from_slice(&(projectile.y.to_le_bytes()));
-        data.extend_from_slice(&(projectile.vel_x.to_le_bytes()));
-        data.extend_from_slice(&(projectile.vel_y.to_le_bytes()));
-        return data;
-    }
-
-    #[cfg(not(target_family = "wasm"))]
-    pub fn tct_destroy_projectile(projectile_i: usize) -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::new();
-        data.push(Self::TCT_DESTROY_PROJECTILE);
-        data.push(projectile_i as u8);
-        return data;
-    }
-
-    #[cfg(not(target_family = "wasm"))]
-    pub fn tct_projectile_explosion(projectile: &Projectile) -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::new();
-        data.push(Self::TCT_PROJECTILE_EXPLOSION);
-        data.push(projectile.projectile_type as u8);
-        data.extend_from_slice(&(projectile.x.to_le_bytes()));
-        data.extend_from_slice(&(projectile.y.to_le_bytes()));
-        return data;
-    }
-
-    #[cfg(not(target_family = "wasm"))]
-    pub fn tct_update_health(health: f32) -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::new();
-        data.push(Self::TCT_UPDATE_HEALTH);
-        data.extend_from_slice(&(health.to_le_bytes()));
-        return data;
-    }
-
-    #[cfg(not(target_family = "wasm"))]
-    pub fn tct_remove_ammo() -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::new();
-        data.push(Self::TCT_REMOVE_AMMO);
-        return data;
-    }
-
-    #[cfg(not(target_family = "wasm"))]
-    pub fn tct_kill_player(player_i: usize) -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::new();
-        data.push(Self::TCT_KILL_PLAYER);
-        data.push(player_i as u8);
-        return data;
-    }
+        while i < message.len() {
+            if message.len() < i + 4 {
+                return Err(ProtoError::TooShort);
+            }
 
-    #[cfg(not(target_family = "wasm"))]
-    pub fn tct_respawn_player(player_i: usize, x: f32, y: f32) -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::new();
-        data.push(Self::TCT_RESPAWN_PLAYER);
-        data.push(player_i as u8);
-        data.extend_from_slice(&(x.to_le_bytes()));
-        data.extend_from_slice(&(y.to_le_bytes()));
-        return data;
-    }
+            let len_bytes:[u8;4] = message[i..(i+4)].try_into().unwrap();
+            let len = u32::from_le_bytes(len_bytes) as usize;
+            i += 4;
 
-    #[cfg(not(target_family = "wasm"))]
-    pub fn tct_game_over_stats(kills_deaths: &Vec<(i32, i32)>) -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::new();
-        data.push(Self::TCT_GAME_OVER_STATS);
+            if message.len() - i < len {
+                return Err(ProtoError::TooShort);
+            }
 
-        for i in 0..kills_deaths.len() {
-            data.extend_from_slice(&(kills_deaths[i].0).to_le_bytes());
-            data.extend_from_slice(&(kills_deaths[i].1).to_le_bytes());
+            messages.push(message[i..(i+len)].to_vec());
+            i += len;
         }
 
-        return data;
+        return Ok(messages);
+    }
+
+    // Shared by both directions, the settings always follow the message type
+    pub fn push_match_settings(data: &mut Vec<u8>, settings: &MatchSettings) {
+        data.extend_from_slice(&(settings.kills_to_win.to_le_bytes()));
+        data.extend_from_slice(&(settings.time_to_respawn.to_le_bytes()));
+        data.extend_from_slice(&(settings.max_health.to_le_bytes()));
+        data.extend_from_slice(&(settings.air_jumps.to_le_bytes()));
+        data.extend_from_slice(&(settings.weapon_spawn_interval.to_le_bytes()));
+        data.push(settings.weapon_spawn_count as u8);
+        data.extend_from_slice(&(settings.server_tick.to_le_bytes()));
+    }
+
+    pub fn parse_match_settings(message: &Vec<u8>) -> Result<MatchSettings, ProtoError> {
+        Self::check_len(message, 1 + Self::MATCH_SETTINGS_LEN)?;
+
+        let kills_to_win_bytes:[u8;4] = message[1..5].try_into().unwrap();
+        let time_to_respawn_bytes:[u8;4] = message[5..9].try_into().unwrap();
+        let max_health_bytes:[u8;4] = message[9..13].try_into().unwrap();
+        let air_jumps_bytes:[u8;4] = message[13..17].try_into().unwrap();
+        let weapon_spawn_interval_bytes:[u8;4] = message[17..21].try_into().unwrap();
+        let server_tick_bytes:[u8;4] = message[22..26].try_into().unwrap();
+
+        return Ok(MatchSettings {
+            kills_to_win: i32::from_le_bytes(kills_to_win_bytes),
+            time_to_respawn: i32::from_le_bytes(time_to_respawn_bytes),
+            max_health: f32::from_le_bytes(max_health_bytes),
+            air_jumps: i32::from_le_bytes(air_jumps_bytes),
+            weapon_spawn_interval: i32::from_le_bytes(weapon_spawn_interval_bytes),
+            weapon_spawn_count: message[21] as usize,
+            server_tick: i32::from_le_bytes(server_tick_bytes),
+        });
     }
 }
@@ -0,0 +1,153 @@
+#[cfg(not(target_family = "wasm"))]
+use crate::match_settings::MatchSettings;
+#[cfg(not(target_family = "wasm"))]
+use crate::server_message::ServerMessage;
+
+// Layout of the match recordings the server writes:
+// magic + version (u8) + server tick in ms (u32) + player list message + match settings message
+// + map PNG, each of the last three prefixed with its length (u32). After the header come
+// records of either TICK + tick (u32), or recipient (u8) + length (varint) + TCT message
+pub struct Replay {}
+
+// (tick, recipient, message)
+pub type ReplayRecord = (u32, u8, Vec<u8>);
+// (server tick, player list message, match settings message, records)
+pub type ParsedReplay = (i32, Vec<u8>, Vec<u8>, Vec<ReplayRecord>);
+
+impl Replay {
+    pub const MAGIC: [u8; 4] = *b"PDRP";
+    pub const VERSION: u8 = 3;
+
+    // Recipients below these are player indices
+    pub const TICK: u8 = 0xfd;
+    pub const ALL: u8 = 0xfe;
+
+    #[cfg(not(target_family = "wasm"))]
+    pub fn header(
+        server_tick: i32,
+        names: &Vec<String>,
+        settings: &MatchSettings,
+        map_png: &[u8],
+    ) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::new();
+        data.extend_from_slice(&Self::MAGIC);
+        data.push(Self::VERSION);
+        data.extend_from_slice(&(server_tick as u32).to_le_bytes());
+
+        let sections = [
+            ServerMessage::PlayerList { names: names.clone() }.encode(),
+            ServerMessage::MatchSettings(settings.clone()).encode(),
+            map_png.to_vec(),
+        ];
+
+        for section in &sections {
+            data.extend_from_slice(&(section.len() as u32).to_le_bytes());
+            data.extend_from_slice(section);
+        }
+
+        return data;
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    pub fn push_tick(data: &mut Vec<u8>, tick: u32) {
+        data.push(Self::TICK);
+        data.extend_from_slice(&tick.to_le_bytes());
+    }
+
+    // Most messages are only a few bytes so the length is a varint rather than a u32
+    #[cfg(not(target_family = "wasm"))]
+    pub fn push_message(data: &mut Vec<u8>, recipient: u8, message: &[u8]) {
+        data.push(recipient);
+
+        let mut len = message.len();
+
+        while len >= 0x80 {
+            data.push((len as u8 & 0x7f) | 0x80);
+            len >>= 7;
+        }
+
+        data.push(len as u8);
+        data.extend_from_slice(message);
+    }
+
+    // The map is skipped, the client already has it
+    pub fn parse(data: &[u8]) -> Result<ParsedReplay, &'static str> {
+        if data.len() < 9 || data[0..4] != Self::MAGIC {
+            return Err("Not a replay file");
+        }
+
+        if data[4] != Self::VERSION {
+            return Err("Unsupported replay version");
+        }
+
+        let server_tick = u32::from_le_bytes(data[5..9].try_into().unwrap()) as i32;
+
+        let mut pos = 9;
+        let mut sections: Vec<Vec<u8>> = Vec::new();
+
+        for _ in 0..3 {
+            if data.len() < pos + 4 {
+                return Err("Replay header cut short");
+            }
+
+            let len = u32::from_le_bytes(data[pos..pos+4].try_into().unwrap()) as usize;
+            pos += 4;
+
+            if data.len() < pos + len {
+                return Err("Replay header cut short");
+            }
+
+            sections.push(data[pos..pos+len].to_vec());
+            pos += len;
+        }
+
+        let mut records: Vec<ReplayRecord> = Vec::new();
+        let mut tick: u32 = 0;
+
+        while pos < data.len() {
+            let recipient = data[pos];
+            pos += 1;
+
+            if recipient == Self::TICK {
+                if data.len() < pos + 4 {
+                    return Err("Replay tick cut short");
+                }
+
+                tick = u32::from_le_bytes(data[pos..pos+4].try_into().unwrap());
+                pos += 4;
+                continue;
+            }
+
+            let mut len: usize = 0;
+            let mut shift = 0;
+
+            loop {
+                if pos >= data.len() || shift > 28 {
+                    return Err("Bad replay message length");
+                }
+
+                let byte = data[pos];
+                pos += 1;
+
+                len |= ((byte & 0x7f) as usize) << shift;
+                shift += 7;
+
+                if byte < 0x80 {
+                    break;
+                }
+            }
+
+            if data.len() < pos + len {
+                return Err("Replay message cut short");
+            }
+
+            records.push((tick, recipient, data[pos..pos+len].to_vec()));
+            pos += len;
+        }
+
+        let settings_message = sections.remove(1);
+        let player_list_message = sections.remove(0);
+
+        return Ok((server_tick, player_list_message, settings_message, records));
+    }
+}
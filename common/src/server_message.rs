@@ -0,0 +1,928 @@
+use crate::match_settings::MatchSettings;
+use crate::player::Player;
+use crate::projectile::Projectile;
+use crate::proto::{Proto, ProtoError};
+use crate::weapon::Weapon;
+
+// Everything the server sends the client, see Proto for the byte layouts
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServerMessage {
+    JoinExistingResult { status: u8, resume_token: Option<[u8; Proto::RESUME_TOKEN_LEN]> },
+    // The code and token are only sent on success
+    CreateNewResult { status: u8, code: String, resume_token: Option<[u8; Proto::RESUME_TOKEN_LEN]> },
+    PlayerList { names: Vec<String> },
+    ToggleReady { player_i: usize, ready: bool },
+    StartGame { player_i: usize, x: f32, y: f32 },
+    NewPos { player_i: usize, x: f32, y: f32 },
+    NewAngle { player_i: usize, angle: f32 },
+    WeaponSpawn { weapon_id: u32, weapon_type: usize, x: f32, y: f32 },
+    RemoveWeapon { weapon_id: u32 },
+    AssignWeapon { player_i: usize, weapon_type: usize },
+    NewProjectile { projectile_id: u32, projectile_type: usize, x: f32, y: f32, vel_x: f32, vel_y: f32 },
+    DestroyProjectile { projectile_id: u32 },
+    ProjectileExplosion { projectile_type: usize, x: f32, y: f32 },
+    UpdateHealth { health: f32 },
+    RemoveAmmo,
+    KillPlayer { player_i: usize },
+    RespawnPlayer { player_i: usize, x: f32, y: f32 },
+    GameOverStats { kills_deaths: Vec<(i32, i32)> },
+    ServerShutdown { seconds: u32 },
+    // Each lobby is (code, host name, player count)
+    LobbyList { lobbies: Vec<(String, String, usize)> },
+    ResumeResult { status: u8, player_i: usize },
+    // Players only carry their game state, names are already known from the lobby
+    GameState {
+        player_i: usize,
+        players: Vec<Player>,
+        weapons: Vec<Weapon>,
+        projectiles: Vec<Projectile>,
+    },
+    // (x, y, length) for each horizontal run of destroyed pixels
+    Terrain { runs: Vec<(i32, i32, i32)> },
+    PlayerJoined { player_i: usize, x: f32, y: f32, name: String },
+    LobbyState { host_i: usize, player_i: usize, locked: bool },
+    Kicked { reason: u8 },
+    MatchSettings(MatchSettings),
+    InputAck { seq: u32, x: f32, y: f32, vel_y: f32, jumps: i32 },
+    VersionMismatch { protocol_version: u16 },
+    MatchError,
+}
+
+impl ServerMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::new();
+
+        match self {
+            ServerMessage::JoinExistingResult { status, resume_token } => {
+                data.push(Proto::TCT_JOIN_EXISTING_RESULT);
+                data.push(*status);
+
+                if let Some(resume_token) = resume_token {
+                    data.extend_from_slice(resume_token);
+                }
+            },
+            ServerMessage::CreateNewResult { status, code, resume_token } => {
+                data.push(Proto::TCT_CREATE_NEW_RESULT);
+                data.push(*status);
+
+                if let Some(resume_token) = resume_token {
+                    data.extend_from_slice(resume_token);
+                    data.extend_from_slice(code.as_bytes());
+                }
+            },
+            ServerMessage::PlayerList { names } => {
+                data.push(Proto::TCT_PLAYER_LIST);
+
+                for name in names {
+                    data.extend_from_slice(name.as_bytes());
+                    data.push(Proto::SEPARATOR);
+                }
+            },
+            ServerMessage::ToggleReady { player_i, ready } => {
+                data.push(Proto::TCT_TOGGLE_READY);
+                data.push(*player_i as u8);
+                data.push(Proto::bool_byte(*ready));
+            },
+            ServerMessage::StartGame { player_i, x, y } => {
+                data.push(Proto::TCT_START_GAME);
+                data.push(*player_i as u8);
+                data.extend_from_slice(&(x.to_le_bytes()));
+                data.extend_from_slice(&(y.to_le_bytes()));
+            },
+            ServerMessage::NewPos { player_i, x, y } => {
+                data.push(Proto::TCT_NEW_POS);
+                data.push(*player_i as u8);
+                data.extend_from_slice(&(x.to_le_bytes()));
+                data.extend_from_slice(&(y.to_le_bytes()));
+            },
+            ServerMessage::NewAngle { player_i, angle } => {
+                data.push(Proto::TCT_NEW_ANGLE);
+                data.push(*player_i as u8);
+                data.extend_from_slice(&(angle.to_le_bytes()));
+            },
+            ServerMessage::WeaponSpawn { weapon_id, weapon_type, x, y } => {
+                data.push(Proto::TCT_WEAPON_SPAWN);
+                data.extend_from_slice(&(weapon_id.to_le_bytes()));
+                data.push(*weapon_type as u8);
+                data.extend_from_slice(&(x.to_le_bytes()));
+                data.extend_from_slice(&(y.to_le_bytes()));
+            },
+            ServerMessage::RemoveWeapon { weapon_id } => {
+                data.push(Proto::TCT_REMOVE_WEAPON);
+                data.extend_from_slice(&(weapon_id.to_le_bytes()));
+            },
+            ServerMessage::AssignWeapon { player_i, weapon_type } => {
+                data.push(Proto::TCT_ASSIGN_WEAPON);
+                data.push(*player_i as u8);
+                data.push(*weapon_type as u8);
+            },
+            ServerMessage::NewProjectile { projectile_id, projectile_type, x, y, vel_x, vel_y } => {
+                data.push(Proto::TCT_NEW_PROJECTILE);
+                data.extend_from_slice(&(projectile_id.to_le_bytes()));
+                data.push(*projectile_type as u8);
+                data.extend_from_slice(&(x.to_le_bytes()));
+                data.extend_from_slice(&(y.to_le_bytes()));
+                data.extend_from_slice(&(vel_x.to_le_bytes()));
+                data.extend_from_slice(&(vel_y.to_le_bytes()));
+            },
+            ServerMessage::DestroyProjectile { projectile_id } => {
+                data.push(Proto::TCT_DESTROY_PROJECTILE);
+                data.extend_from_slice(&(projectile_id.to_le_bytes()));
+            },
+            ServerMessage::ProjectileExplosion { projectile_type, x, y } => {
+                data.push(Proto::TCT_PROJECTILE_EXPLOSION);
+                data.push(*projectile_type as u8);
+                data.extend_from_slice(&(x.to_le_bytes()));
+                data.extend_from_slice(&(y.to_le_bytes()));
+            },
+            ServerMessage::UpdateHealth { health } => {
+                data.push(Proto::TCT_UPDATE_HEALTH);
+                data.extend_from_slice(&(health.to_le_bytes()));
+            },
+            ServerMessage::RemoveAmmo => data.push(Proto::TCT_REMOVE_AMMO),
+            ServerMessage::KillPlayer { player_i } => {
+                data.push(Proto::TCT_KILL_PLAYER);
+                data.push(*player_i as u8);
+            },
+            ServerMessage::RespawnPlayer { player_i, x, y } => {
+                data.push(Proto::TCT_RESPAWN_PLAYER);
+                data.push(*player_i as u8);
+                data.extend_from_slice(&(x.to_le_bytes()));
+                data.extend_from_slice(&(y.to_le_bytes()));
+            },
+            ServerMessage::GameOverStats { kills_deaths } => {
+                data.push(Proto::TCT_GAME_OVER_STATS);
+
+                for (kills, deaths) in kills_deaths {
+                    data.extend_from_slice(&(kills.to_le_bytes()));
+                    data.extend_from_slice(&(deaths.to_le_bytes()));
+                }
+            },
+            ServerMessage::ServerShutdown { seconds } => {
+                data.push(Proto::TCT_SERVER_SHUTDOWN);
+                data.extend_from_slice(&(seconds.to_le_bytes()));
+            },
+            ServerMessage::LobbyList { lobbies } => {
+                data.push(Proto::TCT_LOBBY_LIST);
+
+                for (code, host, players) in lobbies {
+                    data.push(*players as u8);
+                    data.extend_from_slice(code.as_bytes());
+                    data.push(Proto::SEPARATOR);
+                    data.extend_from_slice(host.as_bytes());
+                    data.push(Proto::SEPARATOR);
+                }
+            },
+            ServerMessage::ResumeResult { status, player_i } => {
+                data.push(Proto::TCT_RESUME_RESULT);
+                data.push(*status);
+                data.push(*player_i as u8);
+            },
+            ServerMessage::GameState { player_i, players, weapons, projectiles } => {
+                data.push(Proto::TCT_GAME_STATE);
+                data.push(*player_i as u8);
+
+                data.push(players.len() as u8);
+                for player in players {
+                    data.extend_from_slice(&(player.x.to_le_bytes()));
+                    data.extend_from_slice(&(player.y.to_le_bytes()));
+                    data.extend_from_slice(&(player.angle.to_le_bytes()));
+                    data.extend_from_slice(&(player.health.to_le_bytes()));
+                    data.push(Proto::bool_byte(player.alive));
+
+                    match player.weapon_type {
+                        Some(weapon_type) => data.push(weapon_type as u8),
+                        None => data.push(Proto::NO_WEAPON),
+                    }
+
+                    data.extend_from_slice(&(player.ammo.to_le_bytes()));
+                    data.extend_from_slice(&(player.time_to_respawn.to_le_bytes()));
+                    data.extend_from_slice(&(player.kills.to_le_bytes()));
+                    data.extend_from_slice(&(player.deaths.to_le_bytes()));
+                }
+
+                data.push(weapons.len() as u8);
+                for weapon in weapons {
+                    data.extend_from_slice(&(weapon.id.to_le_bytes()));
+                    data.push(weapon.weapon_type as u8);
+                    data.extend_from_slice(&(weapon.x.to_le_bytes()));
+                    data.extend_from_slice(&(weapon.y.to_le_bytes()));
+                }
+
+                data.extend_from_slice(&(projectiles.len() as u16).to_le_bytes());
+                for projectile in projectiles {
+                    data.extend_from_slice(&(projectile.id.to_le_bytes()));
+                    data.push(projectile.projectile_type as u8);
+                    data.extend_from_slice(&(projectile.x.to_le_bytes()));
+                    data.extend_from_slice(&(projectile.y.to_le_bytes()));
+                    data.extend_from_slice(&(projectile.vel_x.to_le_bytes()));
+                    data.extend_from_slice(&(projectile.vel_y.to_le_bytes()));
+                }
+            },
+            ServerMessage::Terrain { runs } => {
+                data.push(Proto::TCT_TERRAIN);
+
+                for (x, y, length) in runs {
+                    data.extend_from_slice(&(*x as u16).to_le_bytes());
+                    data.extend_from_slice(&(*y as u16).to_le_bytes());
+                    data.extend_from_slice(&(*length as u16).to_le_bytes());
+                }
+            },
+            ServerMessage::PlayerJoined { player_i, x, y, name } => {
+                data.push(Proto::TCT_PLAYER_JOINED);
+                data.push(*player_i as u8);
+                data.extend_from_slice(&(x.to_le_bytes()));
+                data.extend_from_slice(&(y.to_le_bytes()));
+                data.extend_from_slice(name.as_bytes());
+            },
+            ServerMessage::LobbyState { host_i, player_i, locked } => {
+                data.push(Proto::TCT_LOBBY_STATE);
+                data.push(*host_i as u8);
+                data.push(*player_i as u8);
+                data.push(Proto::bool_byte(*locked));
+            },
+            ServerMessage::Kicked { reason } => {
+                data.push(Proto::TCT_KICKED);
+                data.push(*reason);
+            },
+            ServerMessage::MatchSettings(settings) => {
+                data.push(Proto::TCT_MATCH_SETTINGS);
+                Proto::push_match_settings(&mut data, settings);
+            },
+            ServerMessage::InputAck { seq, x, y, vel_y, jumps } => {
+                data.push(Proto::TCT_INPUT_ACK);
+                data.extend_from_slice(&(seq.to_le_bytes()));
+                data.extend_from_slice(&(x.to_le_bytes()));
+                data.extend_from_slice(&(y.to_le_bytes()));
+                data.extend_from_slice(&(vel_y.to_le_bytes()));
+                data.push((*jumps).max(0) as u8);
+            },
+            ServerMessage::VersionMismatch { protocol_version } => {
+                data.push(Proto::TCT_VERSION_MISMATCH);
+                data.extend_from_slice(&(protocol_version.to_le_bytes()));
+            },
+            ServerMessage::MatchError => data.push(Proto::TCT_MATCH_ERROR),
+        }
+
+        return data;
+    }
+
+    pub fn decode(message: &Vec<u8>) -> Result<ServerMessage, ProtoError> {
+        let message_type = Proto::get_type(message)?;
+
+        match message_type {
+            Proto::TCT_JOIN_EXISTING_RESULT => {
+                if message.len() < 2 {
+                    return Err(ProtoError::TooShort);
+                }
+
+                let status = message[1];
+
+                match status {
+                    Proto::JOIN_EXISTING_RESULT_SUCCESS => (),
+                    Proto::JOIN_EXISTING_RESULT_BAD_CODE
+                    | Proto::JOIN_EXISTING_RESULT_SERVER_ERROR
+                    | Proto::JOIN_EXISTING_RESULT_FULL
+                    | Proto::JOIN_EXISTING_RESULT_LOCKED
+                    | Proto::JOIN_EXISTING_RESULT_SPECTATING => {
+                        Proto::check_len(message, 2)?;
+
+                        return Ok(ServerMessage::JoinExistingResult { status, resume_token: None });
+                    },
+                    _ => return Err(ProtoError::UnknownValue),
+                }
+
+                Proto::check_len(message, 2 + Proto::RESUME_TOKEN_LEN)?;
+
+                let resume_token: [u8; Proto::RESUME_TOKEN_LEN] = message[2..].try_into().unwrap();
+
+                return Ok(ServerMessage::JoinExistingResult { status, resume_token: Some(resume_token) });
+            },
+            Proto::TCT_CREATE_NEW_RESULT => {
+                if message.len() < 2 {
+                    return Err(ProtoError::TooShort);
+                }
+
+                let status = message[1];
+
+                match status {
+                    Proto::CREATE_NEW_RESULT_SUCCESS => (),
+                    Proto::CREATE_NEW_RESULT_SERVER_ERROR => {
+                        Proto::check_len(message, 2)?;
+
+                        return Ok(ServerMessage::CreateNewResult {
+                            status,
+                            code: String::new(),
+                            resume_token: None,
+                        });
+                    },
+                    _ => return Err(ProtoError::UnknownValue),
+                }
+
+                Proto::check_len(message, 2 + Proto::RESUME_TOKEN_LEN + Proto::GAME_CODE_LEN)?;
+
+                let resume_token: [u8; Proto::RESUME_TOKEN_LEN]
+                    = message[2..(2 + Proto::RESUME_TOKEN_LEN)].try_into().unwrap();
+
+                return Ok(ServerMessage::CreateNewResult {
+                    status,
+                    code: Proto::parse_str(&message[(2 + Proto::RESUME_TOKEN_LEN)..])?,
+                    resume_token: Some(resume_token),
+                });
+            },
+            Proto::TCT_PLAYER_LIST => {
+                let mut names: Vec<String> = Vec::new();
+                let mut name_start = 1;
+
+                // Every name ends in a separator
+                for i in 1..message.len() {
+                    if message[i] != Proto::SEPARATOR {
+                        continue;
+                    }
+
+                    names.push(Proto::parse_str(&message[name_start..i])?);
+                    name_start = i + 1;
+                }
+
+                if name_start != message.len() {
+                    return Err(ProtoError::TrailingBytes);
+                }
+
+                return Ok(ServerMessage::PlayerList { names });
+            },
+            Proto::TCT_TOGGLE_READY => {
+                Proto::check_len(message, 3)?;
+
+                return Ok(ServerMessage::ToggleReady {
+                    player_i: message[1] as usize,
+                    ready: Proto::parse_bool(message[2])?,
+                });
+            },
+            Proto::TCT_START_GAME | Proto::TCT_NEW_POS | Proto::TCT_RESPAWN_PLAYER => {
+                Proto::check_len(message, 10)?;
+
+                let player_i = message[1] as usize;
+                let x_bytes:[u8;4] = message[2..6].try_into().unwrap();
+                let y_bytes:[u8;4] = message[6..10].try_into().unwrap();
+                let x = f32::from_le_bytes(x_bytes);
+                let y = f32::from_le_bytes(y_bytes);
+
+                return Ok(match message_type {
+                    Proto::TCT_START_GAME => ServerMessage::StartGame { player_i, x, y },
+                    Proto::TCT_NEW_POS => ServerMessage::NewPos { player_i, x, y },
+                    _ => ServerMessage::RespawnPlayer { player_i, x, y },
+                });
+            },
+            Proto::TCT_NEW_ANGLE => {
+                Proto::check_len(message, 6)?;
+
+                let angle_bytes:[u8;4] = message[2..6].try_into().unwrap();
+
+                return Ok(ServerMessage::NewAngle {
+                    player_i: message[1] as usize,
+                    angle: f32::from_le_bytes(angle_bytes),
+                });
+            },
+            Proto::TCT_WEAPON_SPAWN => {
+                Proto::check_len(message, 14)?;
+
+                let weapon_id_bytes:[u8;4] = message[1..5].try_into().unwrap();
+                let x_bytes:[u8;4] = message[6..10].try_into().unwrap();
+                let y_bytes:[u8;4] = message[10..14].try_into().unwrap();
+
+                return Ok(ServerMessage::WeaponSpawn {
+                    weapon_id: u32::from_le_bytes(weapon_id_bytes),
+                    weapon_type: Proto::parse_type_index(message[5], Weapon::WEAPON_TYPES.len())?,
+                    x: f32::from_le_bytes(x_bytes),
+                    y: f32::from_le_bytes(y_bytes),
+                });
+            },
+            Proto::TCT_PROJECTILE_EXPLOSION => {
+                Proto::check_len(message, 10)?;
+
+                let x_bytes:[u8;4] = message[2..6].try_into().unwrap();
+                let y_bytes:[u8;4] = message[6..10].try_into().unwrap();
+
+                return Ok(ServerMessage::ProjectileExplosion {
+                    projectile_type: Proto::parse_type_index(message[1], Projectile::PROJECTILE_TYPES.len())?,
+                    x: f32::from_le_bytes(x_bytes),
+                    y: f32::from_le_bytes(y_bytes),
+                });
+            },
+            Proto::TCT_REMOVE_WEAPON => {
+                Proto::check_len(message, 5)?;
+
+                let weapon_id_bytes:[u8;4] = message[1..5].try_into().unwrap();
+
+                return Ok(ServerMessage::RemoveWeapon { weapon_id: u32::from_le_bytes(weapon_id_bytes) });
+            },
+            Proto::TCT_ASSIGN_WEAPON => {
+                Proto::check_len(message, 3)?;
+
+                return Ok(ServerMessage::AssignWeapon {
+                    player_i: message[1] as usize,
+                    weapon_type: Proto::parse_type_index(message[2], Weapon::WEAPON_TYPES.len())?,
+                });
+            },
+            Proto::TCT_NEW_PROJECTILE => {
+                Proto::check_len(message, 22)?;
+
+                let projectile_id_bytes:[u8;4] = message[1..5].try_into().unwrap();
+                let x_bytes:[u8;4] = message[6..10].try_into().unwrap();
+                let y_bytes:[u8;4] = message[10..14].try_into().unwrap();
+                let vel_x_bytes:[u8;4] = message[14..18].try_into().unwrap();
+                let vel_y_bytes:[u8;4] = message[18..22].try_into().unwrap();
+
+                return Ok(ServerMessage::NewProjectile {
+                    projectile_id: u32::from_le_bytes(projectile_id_bytes),
+                    projectile_type: Proto::parse_type_index(message[5], Projectile::PROJECTILE_TYPES.len())?,
+                    x: f32::from_le_bytes(x_bytes),
+                    y: f32::from_le_bytes(y_bytes),
+                    vel_x: f32::from_le_bytes(vel_x_bytes),
+                    vel_y: f32::from_le_bytes(vel_y_bytes),
+                });
+            },
+            Proto::TCT_DESTROY_PROJECTILE => {
+                Proto::check_len(message, 5)?;
+
+                let projectile_id_bytes:[u8;4] = message[1..5].try_into().unwrap();
+
+                return Ok(ServerMessage::DestroyProjectile {
+                    projectile_id: u32::from_le_bytes(projectile_id_bytes),
+                });
+            },
+            Proto::TCT_UPDATE_HEALTH => {
+                Proto::check_len(message, 5)?;
+
+                let health_bytes:[u8;4] = message[1..5].try_into().unwrap();
+
+                return Ok(ServerMessage::UpdateHealth { health: f32::from_le_bytes(health_bytes) });
+            },
+            Proto::TCT_REMOVE_AMMO => {
+                Proto::check_len(message, 1)?;
+
+                return Ok(ServerMessage::RemoveAmmo);
+            },
+            Proto::TCT_KILL_PLAYER => {
+                Proto::check_len(message, 2)?;
+
+                return Ok(ServerMessage::KillPlayer { player_i: message[1] as usize });
+            },
+            Proto::TCT_GAME_OVER_STATS => {
+                // The last player's stats were cut short
+                if !(message.len() - 1).is_multiple_of(8) {
+                    return Err(ProtoError::TooShort);
+                }
+
+                let mut kills_deaths: Vec<(i32, i32)> = Vec::new();
+
+                for i in (1..message.len()).step_by(8) {
+                    let kills_bytes:[u8;4] = message[i..(i+4)].try_into().unwrap();
+                    let deaths_bytes:[u8;4] = message[(i+4)..(i+8)].try_into().unwrap();
+                    kills_deaths.push((i32::from_le_bytes(kills_bytes), i32::from_le_bytes(deaths_bytes)));
+                }
+
+                return Ok(ServerMessage::GameOverStats { kills_deaths });
+            },
+            Proto::TCT_SERVER_SHUTDOWN => {
+                Proto::check_len(message, 5)?;
+
+                let seconds_bytes:[u8;4] = message[1..5].try_into().unwrap();
+
+                return Ok(ServerMessage::ServerShutdown { seconds: u32::from_le_bytes(seconds_bytes) });
+            },
+            Proto::TCT_LOBBY_LIST => {
+                let mut lobbies: Vec<(String, String, usize)> = Vec::new();
+
+                let mut i = 1;
+                while i < message.len() {
+                    let players = message[i] as usize;
+                    i += 1;
+
+                    let mut fields: Vec<String> = Vec::new();
+
+                    for _ in 0..2 {
+                        let field_start = i;
+
+                        while i < message.len() && message[i] != Proto::SEPARATOR {
+                            i += 1;
+                        }
+
+                        if i >= message.len() {
+                            return Err(ProtoError::TooShort);
+                        }
+
+                        fields.push(Proto::parse_str(&message[field_start..i])?);
+
+                        // Skip the separator
+                        i += 1;
+                    }
+
+                    let host = fields.pop().unwrap();
+                    let code = fields.pop().unwrap();
+
+                    lobbies.push((code, host, players));
+                }
+
+                return Ok(ServerMessage::LobbyList { lobbies });
+            },
+            Proto::TCT_RESUME_RESULT => {
+                Proto::check_len(message, 3)?;
+
+                let status = match message[1] {
+                    Proto::RESUME_RESULT_SUCCESS | Proto::RESUME_RESULT_EXPIRED => message[1],
+                    _ => return Err(ProtoError::UnknownValue),
+                };
+
+                return Ok(ServerMessage::ResumeResult { status, player_i: message[2] as usize });
+            },
+            Proto::TCT_GAME_STATE => return Self::decode_game_state(message),
+            Proto::TCT_TERRAIN => {
+                // The last run was cut short
+                if !(message.len() - 1).is_multiple_of(6) {
+                    return Err(ProtoError::TooShort);
+                }
+
+                let mut runs: Vec<(i32, i32, i32)> = Vec::new();
+
+                for i in (1..message.len()).step_by(6) {
+                    let x_bytes:[u8;2] = message[i..(i+2)].try_into().unwrap();
+                    let y_bytes:[u8;2] = message[(i+2)..(i+4)].try_into().unwrap();
+                    let length_bytes:[u8;2] = message[(i+4)..(i+6)].try_into().unwrap();
+
+                    runs.push((
+                        u16::from_le_bytes(x_bytes) as i32,
+                        u16::from_le_bytes(y_bytes) as i32,
+                        u16::from_le_bytes(length_bytes) as i32,
+                    ));
+                }
+
+                return Ok(ServerMessage::Terrain { runs });
+            },
+            Proto::TCT_PLAYER_JOINED => {
+                if message.len() < 10 {
+                    return Err(ProtoError::TooShort);
+                }
+
+                let x_bytes:[u8;4] = message[2..6].try_into().unwrap();
+                let y_bytes:[u8;4] = message[6..10].try_into().unwrap();
+
+                return Ok(ServerMessage::PlayerJoined {
+                    player_i: message[1] as usize,
+                    x: f32::from_le_bytes(x_bytes),
+                    y: f32::from_le_bytes(y_bytes),
+                    name: Proto::parse_str(&message[10..])?,
+                });
+            },
+            Proto::TCT_LOBBY_STATE => {
+                Proto::check_len(message, 4)?;
+
+                return Ok(ServerMessage::LobbyState {
+                    host_i: message[1] as usize,
+                    player_i: message[2] as usize,
+                    locked: Proto::parse_bool(message[3])?,
+                });
+            },
+            Proto::TCT_KICKED => {
+                Proto::check_len(message, 2)?;
+
+                let reason = match message[1] {
+                    Proto::KICK_REASON_HOST
+                    | Proto::KICK_REASON_FLOOD
+                    | Proto::KICK_REASON_INVALID_MESSAGE
+                    | Proto::KICK_REASON_SPEED => message[1],
+                    _ => return Err(ProtoError::UnknownValue),
+                };
+
+                return Ok(ServerMessage::Kicked { reason });
+            },
+            Proto::TCT_MATCH_SETTINGS => {
+                return Ok(ServerMessage::MatchSettings(Proto::parse_match_settings(message)?));
+            },
+            Proto::TCT_INPUT_ACK => {
+                Proto::check_len(message, 18)?;
+
+                let seq_bytes:[u8;4] = message[1..5].try_into().unwrap();
+                let x_bytes:[u8;4] = message[5..9].try_into().unwrap();
+                let y_bytes:[u8;4] = message[9..13].try_into().unwrap();
+                let vel_y_bytes:[u8;4] = message[13..17].try_into().unwrap();
+
+                return Ok(ServerMessage::InputAck {
+                    seq: u32::from_le_bytes(seq_bytes),
+                    x: f32::from_le_bytes(x_bytes),
+                    y: f32::from_le_bytes(y_bytes),
+                    vel_y: f32::from_le_bytes(vel_y_bytes),
+                    jumps: message[17] as i32,
+                });
+            },
+            Proto::TCT_VERSION_MISMATCH => {
+                Proto::check_len(message, 3)?;
+
+                let protocol_version_bytes:[u8;2] = message[1..3].try_into().unwrap();
+
+                return Ok(ServerMessage::VersionMismatch {
+                    protocol_version: u16::from_le_bytes(protocol_version_bytes),
+                });
+            },
+            Proto::TCT_MATCH_ERROR => {
+                Proto::check_len(message, 1)?;
+
+                return Ok(ServerMessage::MatchError);
+            },
+            _ => return Err(ProtoError::UnknownValue),
+        }
+    }
+
+    fn decode_game_state(message: &Vec<u8>) -> Result<ServerMessage, ProtoError> {
+        if message.len() < 3 {
+            return Err(ProtoError::TooShort);
+        }
+
+        let player_i = message[1] as usize;
+        let player_count = message[2] as usize;
+
+        let mut i = 3;
+
+        let mut players: Vec<Player> = Vec::new();
+
+        for _ in 0..player_count {
+            if message.len() < i + 34 {
+                return Err(ProtoError::TooShort);
+            }
+
+            let mut player = Player::new(String::new());
+
+            let x_bytes:[u8;4] = message[i..(i+4)].try_into().unwrap();
+            let y_bytes:[u8;4] = message[(i+4)..(i+8)].try_into().unwrap();
+            let angle_bytes:[u8;4] = message[(i+8)..(i+12)].try_into().unwrap();
+            let health_bytes:[u8;4] = message[(i+12)..(i+16)].try_into().unwrap();
+            player.x = f32::from_le_bytes(x_bytes);
+            player.y = f32::from_le_bytes(y_bytes);
+            player.angle = f32::from_le_bytes(angle_bytes);
+            player.health = f32::from_le_bytes(health_bytes);
+
+            player.alive = Proto::parse_bool(message[i+16])?;
+
+            player.weapon_type = match message[i+17] {
+                Proto::NO_WEAPON => None,
+                weapon_type => Some(Proto::parse_type_index(weapon_type, Weapon::WEAPON_TYPES.len())?),
+            };
+
+            let ammo_bytes:[u8;4] = message[(i+18)..(i+22)].try_into().unwrap();
+            let time_to_respawn_bytes:[u8;4] = message[(i+22)..(i+26)].try_into().unwrap();
+            let kills_bytes:[u8;4] = message[(i+26)..(i+30)].try_into().unwrap();
+            let deaths_bytes:[u8;4] = message[(i+30)..(i+34)].try_into().unwrap();
+            player.ammo = i32::from_le_bytes(ammo_bytes);
+            player.time_to_respawn = i32::from_le_bytes(time_to_respawn_bytes);
+            player.kills = i32::from_le_bytes(kills_bytes);
+            player.deaths = i32::from_le_bytes(deaths_bytes);
+
+            players.push(player);
+            i += 34;
+        }
+
+        if message.len() < i + 1 {
+            return Err(ProtoError::TooShort);
+        }
+
+        let weapon_count = message[i] as usize;
+        i += 1;
+
+        let mut weapons: Vec<Weapon> = Vec::new();
+
+        for _ in 0..weapon_count {
+            if message.len() < i + 13 {
+                return Err(ProtoError::TooShort);
+            }
+
+            let id_bytes:[u8;4] = message[i..(i+4)].try_into().unwrap();
+            let x_bytes:[u8;4] = message[(i+5)..(i+9)].try_into().unwrap();
+            let y_bytes:[u8;4] = message[(i+9)..(i+13)].try_into().unwrap();
+
+            weapons.push(Weapon::new(
+                u32::from_le_bytes(id_bytes),
+                Proto::parse_type_index(message[i+4], Weapon::WEAPON_TYPES.len())?,
+                f32::from_le_bytes(x_bytes),
+                f32::from_le_bytes(y_bytes),
+            ));
+            i += 13;
+        }
+
+        if message.len() < i + 2 {
+            return Err(ProtoError::TooShort);
+        }
+
+        let projectile_count_bytes:[u8;2] = message[i..(i+2)].try_into().unwrap();
+        let projectile_count = u16::from_le_bytes(projectile_count_bytes) as usize;
+        i += 2;
+
+        let mut projectiles: Vec<Projectile> = Vec::new();
+
+        for _ in 0..projectile_count {
+            if message.len() < i + 21 {
+                return Err(ProtoError::TooShort);
+            }
+
+            let id_bytes:[u8;4] = message[i..(i+4)].try_into().unwrap();
+            let x_bytes:[u8;4] = message[(i+5)..(i+9)].try_into().unwrap();
+            let y_bytes:[u8;4] = message[(i+9)..(i+13)].try_into().unwrap();
+            let vel_x_bytes:[u8;4] = message[(i+13)..(i+17)].try_into().unwrap();
+            let vel_y_bytes:[u8;4] = message[(i+17)..(i+21)].try_into().unwrap();
+
+            projectiles.push(Projectile {
+                id: u32::from_le_bytes(id_bytes),
+                projectile_type: Proto::parse_type_index(message[i+4], Projectile::PROJECTILE_TYPES.len())?,
+                x: f32::from_le_bytes(x_bytes),
+                y: f32::from_le_bytes(y_bytes),
+                vel_x: f32::from_le_bytes(vel_x_bytes),
+                vel_y: f32::from_le_bytes(vel_y_bytes),
+                // Only the server needs to track projectile owners
+                owner: 0,
+            });
+            i += 21;
+        }
+
+        if message.len() > i {
+            return Err(ProtoError::TrailingBytes);
+        }
+
+        return Ok(ServerMessage::GameState { player_i, players, weapons, projectiles });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(message: ServerMessage) {
+        let decoded = ServerMessage::decode(&message.encode());
+        assert_eq!(decoded, Ok(message));
+    }
+
+    #[test]
+    fn round_trips_every_message() {
+        let mut player = Player::new(String::new());
+        player.x = 120.5;
+        player.y = 300.0;
+        player.angle = 2.5;
+        player.health = 3.25;
+        player.alive = false;
+        player.weapon_type = Some(1);
+        player.ammo = 7;
+        player.time_to_respawn = 1500;
+        player.kills = 4;
+        player.deaths = 2;
+
+        let projectile = Projectile {
+            id: 70000,
+            projectile_type: Projectile::TYPE_GRENADE,
+            x: 10.0,
+            y: 20.0,
+            vel_x: -0.5,
+            vel_y: 0.25,
+            owner: 0,
+        };
+
+        let messages = [
+            ServerMessage::JoinExistingResult {
+                status: Proto::JOIN_EXISTING_RESULT_SUCCESS,
+                resume_token: Some([9; Proto::RESUME_TOKEN_LEN]),
+            },
+            ServerMessage::JoinExistingResult { status: Proto::JOIN_EXISTING_RESULT_FULL, resume_token: None },
+            ServerMessage::CreateNewResult {
+                status: Proto::CREATE_NEW_RESULT_SUCCESS,
+                code: String::from("ABC123"),
+                resume_token: Some([3; Proto::RESUME_TOKEN_LEN]),
+            },
+            ServerMessage::CreateNewResult {
+                status: Proto::CREATE_NEW_RESULT_SERVER_ERROR,
+                code: String::new(),
+                resume_token: None,
+            },
+            ServerMessage::PlayerList { names: vec![String::from("Alice"), String::from("Bøb")] },
+            ServerMessage::PlayerList { names: Vec::new() },
+            ServerMessage::ToggleReady { player_i: 1, ready: true },
+            ServerMessage::StartGame { player_i: 2, x: 100.0, y: 200.0 },
+            ServerMessage::NewPos { player_i: 3, x: -1.5, y: 2.5 },
+            ServerMessage::NewAngle { player_i: 0, angle: 3.1 },
+            ServerMessage::WeaponSpawn { weapon_id: 300, weapon_type: 1, x: 5.0, y: 6.0 },
+            ServerMessage::RemoveWeapon { weapon_id: 300 },
+            ServerMessage::AssignWeapon { player_i: 1, weapon_type: 0 },
+            ServerMessage::NewProjectile {
+                projectile_id: 70000,
+                projectile_type: 0,
+                x: 1.0,
+                y: 2.0,
+                vel_x: 3.0,
+                vel_y: 4.0,
+            },
+            ServerMessage::DestroyProjectile { projectile_id: 70000 },
+            ServerMessage::ProjectileExplosion { projectile_type: 1, x: 7.0, y: 8.0 },
+            ServerMessage::UpdateHealth { health: 4.5 },
+            ServerMessage::RemoveAmmo,
+            ServerMessage::KillPlayer { player_i: 2 },
+            ServerMessage::RespawnPlayer { player_i: 1, x: 50.0, y: 60.0 },
+            ServerMessage::GameOverStats { kills_deaths: vec![(5, 1), (0, 5)] },
+            ServerMessage::ServerShutdown { seconds: 30 },
+            ServerMessage::LobbyList {
+                lobbies: vec![
+                    (String::from("ABC123"), String::from("Alice"), 2),
+                    (String::from("XYZ789"), String::from("Bob"), 1),
+                ],
+            },
+            ServerMessage::ResumeResult { status: Proto::RESUME_RESULT_SUCCESS, player_i: 1 },
+            ServerMessage::GameState {
+                player_i: 0,
+                players: vec![player.clone(), Player::new(String::new())],
+                weapons: vec![Weapon::new(4, 0, 11.0, 12.0)],
+                projectiles: vec![projectile.clone()],
+            },
+            ServerMessage::Terrain { runs: vec![(1, 2, 3), (400, 500, 60)] },
+            ServerMessage::PlayerJoined { player_i: 3, x: 1.0, y: 2.0, name: String::from("Carol") },
+            ServerMessage::LobbyState { host_i: 1, player_i: Proto::SPECTATOR as usize, locked: true },
+            ServerMessage::Kicked { reason: Proto::KICK_REASON_FLOOD },
+            ServerMessage::MatchSettings(MatchSettings::new()),
+            ServerMessage::InputAck { seq: 77, x: 1.0, y: 2.0, vel_y: -0.5, jumps: 2 },
+            ServerMessage::VersionMismatch { protocol_version: Proto::PROTOCOL_VERSION },
+            ServerMessage::MatchError,
+        ];
+
+        for message in messages {
+            round_trip(message);
+        }
+    }
+
+    #[test]
+    fn keeps_the_wire_layout() {
+        let message = ServerMessage::PlayerList { names: vec![String::from("A"), String::from("B")] };
+        assert_eq!(message.encode(), vec![Proto::TCT_PLAYER_LIST, b'A', Proto::SEPARATOR, b'B', Proto::SEPARATOR]);
+
+        let message = ServerMessage::ResumeResult { status: Proto::RESUME_RESULT_EXPIRED, player_i: 2 };
+        assert_eq!(message.encode(), vec![Proto::TCT_RESUME_RESULT, Proto::RESUME_RESULT_EXPIRED, 2]);
+
+        let message = ServerMessage::DestroyProjectile { projectile_id: 0x01020304 };
+        assert_eq!(message.encode(), vec![Proto::TCT_DESTROY_PROJECTILE, 0x04, 0x03, 0x02, 0x01]);
+
+        let message = ServerMessage::VersionMismatch { protocol_version: 0x0102 };
+        assert_eq!(message.encode(), vec![0xA8, 0x02, 0x01]);
+
+        let message = ServerMessage::Terrain { runs: vec![(0x0102, 3, 4)] };
+        assert_eq!(message.encode(), vec![Proto::TCT_TERRAIN, 0x02, 0x01, 3, 0, 4, 0]);
+
+        let message = ServerMessage::GameState {
+            player_i: 0,
+            players: vec![Player::new(String::new())],
+            weapons: Vec::new(),
+            projectiles: Vec::new(),
+        };
+        assert_eq!(message.encode().len(), 3 + 34 + 1 + 2);
+    }
+
+    #[test]
+    fn unbundles_every_message() {
+        let messages = vec![
+            ServerMessage::RemoveAmmo.encode(),
+            ServerMessage::UpdateHealth { health: 50.0 }.encode(),
+            ServerMessage::Terrain { runs: Vec::new() }.encode(),
+        ];
+
+        let bundle = Proto::bundle(&messages);
+        assert_eq!(bundle.len(), 1 + 4 + 1 + 4 + 5 + 4 + 1);
+        assert_eq!(Proto::unbundle(&bundle), Ok(messages));
+
+        assert_eq!(Proto::unbundle(&vec![Proto::TCT_BUNDLE]), Ok(Vec::new()));
+        assert_eq!(Proto::unbundle(&vec![Proto::TCT_BUNDLE, 2, 0, 0]), Err(ProtoError::TooShort));
+        assert_eq!(Proto::unbundle(&vec![Proto::TCT_BUNDLE, 2, 0, 0, 0, 0x94]), Err(ProtoError::TooShort));
+        assert_eq!(Proto::unbundle(&ServerMessage::RemoveAmmo.encode()), Err(ProtoError::UnknownValue));
+    }
+
+    #[test]
+    fn rejects_bad_messages() {
+        assert_eq!(ServerMessage::decode(&vec![]), Err(ProtoError::TooShort));
+        assert_eq!(ServerMessage::decode(&vec![0x00]), Err(ProtoError::UnknownValue));
+        assert_eq!(ServerMessage::decode(&vec![Proto::TCT_UPDATE_HEALTH, 0, 0, 0]), Err(ProtoError::TooShort));
+        assert_eq!(ServerMessage::decode(&vec![Proto::TCT_UPDATE_HEALTH, 0, 0, 0, 0, 0]), Err(ProtoError::TrailingBytes));
+        assert_eq!(ServerMessage::decode(&vec![Proto::TCT_TOGGLE_READY, 0, 0x42]), Err(ProtoError::UnknownValue));
+        assert_eq!(ServerMessage::decode(&vec![Proto::TCT_TERRAIN, 0, 0, 0]), Err(ProtoError::TooShort));
+        assert_eq!(ServerMessage::decode(&vec![Proto::TCT_GAME_OVER_STATS, 0, 0, 0, 0]), Err(ProtoError::TooShort));
+        assert_eq!(ServerMessage::decode(&vec![Proto::TCT_LOBBY_LIST, 1, b'A']), Err(ProtoError::TooShort));
+        assert_eq!(ServerMessage::decode(&vec![Proto::TCT_GAME_STATE, 0, 1, 0]), Err(ProtoError::TooShort));
+        assert_eq!(ServerMessage::decode(&vec![Proto::TCT_PLAYER_LIST, b'A']), Err(ProtoError::TrailingBytes));
+        assert_eq!(
+            ServerMessage::decode(&vec![Proto::TCT_PLAYER_LIST, 0xFF, Proto::SEPARATOR]),
+            Err(ProtoError::BadUtf8),
+        );
+        assert_eq!(ServerMessage::decode(&vec![Proto::TCT_KICKED, 0x42]), Err(ProtoError::UnknownValue));
+        assert_eq!(ServerMessage::decode(&vec![Proto::TCT_ASSIGN_WEAPON, 0, 0x42]), Err(ProtoError::UnknownValue));
+        assert_eq!(ServerMessage::decode(&vec![Proto::TCT_RESUME_RESULT, 0x42, 0]), Err(ProtoError::UnknownValue));
+
+        let mut game_state = ServerMessage::GameState {
+            player_i: 0,
+            players: Vec::new(),
+            weapons: Vec::new(),
+            projectiles: Vec::new(),
+        }.encode();
+        game_state.push(0);
+        assert_eq!(ServerMessage::decode(&game_state), Err(ProtoError::TrailingBytes));
+    }
+}
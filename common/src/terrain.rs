@@ -0,0 +1,46 @@
+#[cfg(target_family = "wasm")]
+use web_sys::CanvasRenderingContext2d;
+
+#[cfg(not(target_family = "wasm"))]
+use image::RgbaImage;
+
+// The map players collide with. The client reads its map canvas and the server its map image,
+// both hand pixels back the same way so movement works out the same on each side
+pub trait Terrain {
+    // RGBA bytes row by row, pixels outside the map are empty
+    fn rgba_rect(&self, x: i32, y: i32, width: i32, height: i32) -> Vec<u8>;
+}
+
+#[cfg(target_family = "wasm")]
+impl Terrain for CanvasRenderingContext2d {
+    fn rgba_rect(&self, x: i32, y: i32, width: i32, height: i32) -> Vec<u8> {
+        return self.get_image_data(x as f64, y as f64, width as f64, height as f64)
+            .expect("Unable to get map data")
+            .data()
+            .0;
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl Terrain for RgbaImage {
+    fn rgba_rect(&self, x: i32, y: i32, width: i32, height: i32) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::new();
+
+        for pixel_y in y..(y + height) {
+            for pixel_x in x..(x + width) {
+                if pixel_x < 0
+                    || pixel_y < 0
+                    || pixel_x as u32 >= self.width()
+                    || pixel_y as u32 >= self.height()
+                {
+                    data.extend_from_slice(&[0, 0, 0, 0]);
+                    continue;
+                }
+
+                data.extend_from_slice(&self.get_pixel(pixel_x as u32, pixel_y as u32).0);
+            }
+        }
+
+        return data;
+    }
+}
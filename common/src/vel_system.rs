@@ -1,4 +1,3 @@
-#[cfg(target_family = "wasm")]
 use crate::player::Player;
 
 use crate::projectile::Projectile;
@@ -8,7 +7,6 @@ pub struct VelSystem {}
 impl VelSystem {
     pub const GRAVITY:f32 = 1.2/1000.0;
 
-    #[cfg(target_family = "wasm")]
     pub fn update_player(player: &mut Player, time_elapsed: f32) {
         // Apply gravity to the average change in velocity
         player.vel_y += Self::GRAVITY*time_elapsed/2.0;
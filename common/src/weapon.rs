@@ -1,7 +1,10 @@
 use crate::projectile::Projectile;
 use crate::server_tick::ServerTick;
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct Weapon {
+    // Handed out by the server, see Proto
+    pub id: u32,
     pub weapon_type: usize,
     pub x: f32,
     pub y: f32,
@@ -37,8 +40,9 @@ impl Weapon {
         },
     ];
 
-    pub fn new(weapon_type: usize, x: f32, y: f32) -> Weapon {
+    pub fn new(id: u32, weapon_type: usize, x: f32, y: f32) -> Weapon {
         return Weapon {
+            id,
             weapon_type,
             x,
             y,
@@ -1,21 +1,210 @@
 use warp::ws::WebSocket;
+use futures::{SinkExt, StreamExt};
 use futures::stream::{SplitSink, SplitStream};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::{TryRecvError, TrySendError};
+use tokio::task::JoinHandle;
 use warp::ws::Message;
 
+use pixel_demolition_common::proto::Proto;
+use pixel_demolition_common::server_message::ServerMessage;
 
+use crate::metrics::METRICS;
+use crate::validation::Validation;
+
+// What the reader task hands over from the socket
+type Incoming = mpsc::Receiver<Result<Message, warp::Error>>;
+
+// The socket is only ever touched by the client's own reader and writer tasks, the engine only
+// talks to them through channels so a slow client can't hold up a tick
 pub struct Client {
-    pub websocket_send: SplitSink<WebSocket, Message>,
-    pub websocket_recv: SplitStream<WebSocket>,
+    // Frames for the writer task, None once the client was closed
+    outgoing: Option<mpsc::Sender<Message>>,
+    // Filled by the reader task, disconnected once the socket closes
+    incoming: Incoming,
+    writer: JoinHandle<()>,
+    reader: JoinHandle<()>,
     pub name: String,
     pub connected: bool,
+    // Lets a new websocket take over this client after a disconnect
+    pub resume_token: [u8; Proto::RESUME_TOKEN_LEN],
+    pub disconnected_ticks: i32,
+    // Set when a new websocket took over so the engine re-sends the full state
+    pub resumed: bool,
+    pub validation: Validation,
+    // Kicked mid-game, the slot stays but can't be resumed
+    pub kicked: bool,
+    // Encoded messages waiting for the end of the tick
+    outbox: Vec<Vec<u8>>,
 }
 
 impl Client {
+    // Hold a disconnected player's slot for 30 seconds
+    pub const RESUME_GRACE: i32 = 30;
+
+    // Ticks worth of frames the writer can fall behind by, a client any further behind is
+    // disconnected and has to resume
+    pub const OUTGOING_FRAMES: usize = 32;
+
+    // The reader stops taking messages off the socket once this many wait on the engine
+    pub const INCOMING_MESSAGES: usize = 64;
+
     pub fn new(
         websocket_send: SplitSink<WebSocket, Message>,
         websocket_recv: SplitStream<WebSocket>,
-        name: String)
+        name: String,
+        resume_token: [u8; Proto::RESUME_TOKEN_LEN])
     -> Client {
-        Client { name, websocket_send, websocket_recv, connected: true }
+        let (outgoing, incoming, writer, reader) = Self::spawn_io(websocket_send, websocket_recv);
+
+        Client {
+            name,
+            outgoing: Some(outgoing),
+            incoming,
+            writer,
+            reader,
+            connected: true,
+            resume_token,
+            disconnected_ticks: 0,
+            resumed: false,
+            validation: Validation::new(),
+            kicked: false,
+            outbox: Vec::new(),
+        }
+    }
+
+    fn spawn_io(
+        websocket_send: SplitSink<WebSocket, Message>,
+        websocket_recv: SplitStream<WebSocket>,
+    ) -> (mpsc::Sender<Message>, Incoming, JoinHandle<()>, JoinHandle<()>) {
+        let (outgoing, mut outgoing_recv) = mpsc::channel::<Message>(Self::OUTGOING_FRAMES);
+        let (incoming_send, incoming) = mpsc::channel(Self::INCOMING_MESSAGES);
+
+        // Sends whatever is left once the client is closed, then closes the socket
+        let writer = tokio::spawn(async move {
+            let mut websocket_send = websocket_send;
+
+            while let Some(frame) = outgoing_recv.recv().await {
+                if websocket_send.send(frame).await.is_err() {
+                    break;
+                }
+            }
+
+            let _ = websocket_send.close().await;
+        });
+
+        let reader = tokio::spawn(async move {
+            let mut websocket_recv = websocket_recv;
+
+            while let Some(message) = websocket_recv.next().await {
+                if incoming_send.send(message).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        return (outgoing, incoming, writer, reader);
+    }
+
+    // Shaped like polling the socket directly. None when nothing is waiting, Some(None) once
+    // the socket closed
+    pub fn next_message(&mut self) -> Option<Option<Result<Message, warp::Error>>> {
+        return match self.incoming.try_recv() {
+            Ok(message) => Some(Some(message)),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(None),
+        };
+    }
+
+    // Only queues the message, nothing goes out until the next flush
+    pub fn send(&mut self, message: &ServerMessage) {
+        self.send_encoded(&message.encode());
+    }
+
+    // For messages going to several clients, so they are only encoded once
+    pub fn send_encoded(&mut self, message: &Vec<u8>) {
+        METRICS.message_sent(message);
+
+        self.outbox.push(message.clone());
+    }
+
+    // Hands everything queued to the writer as one frame. A lone message isn't worth bundling.
+    // Anything queued while disconnected is dropped, resuming sends the full state anyway.
+    // Returns false if the client couldn't keep up and was disconnected
+    pub fn flush(&mut self) -> bool {
+        if self.outbox.len() < 1 {
+            return true;
+        }
+
+        let frame = match self.outbox.len() {
+            1 => self.outbox.pop().unwrap(),
+            _ => Proto::bundle(&self.outbox),
+        };
+
+        self.outbox.clear();
+
+        if !self.connected {
+            return true;
+        }
+
+        let outgoing = match &self.outgoing {
+            Some(outgoing) => outgoing,
+            None => return true,
+        };
+
+        // A closed channel means the writer hit a socket error, the reader sees the socket close
+        if let Err(TrySendError::Full(_)) = outgoing.try_send(Message::binary(frame)) {
+            self.disconnect();
+            return false;
+        }
+
+        return true;
+    }
+
+    // Lets the writer send what is already queued before it closes the socket
+    pub fn close(&mut self) {
+        self.outgoing = None;
+        self.reader.abort();
+    }
+
+    // Drops the socket without waiting on it, the client can still resume
+    fn disconnect(&mut self) {
+        self.outgoing = None;
+        self.writer.abort();
+        self.reader.abort();
+
+        self.connected = false;
+        METRICS.client_disconnected();
+    }
+
+    // The old socket may still look connected if it dropped without closing, so it is
+    // replaced either way
+    pub fn resume(
+        &mut self,
+        websocket_send: SplitSink<WebSocket, Message>,
+        websocket_recv: SplitStream<WebSocket>,
+    ) {
+        self.close();
+
+        if self.connected {
+            METRICS.client_disconnected();
+        }
+
+        // Whatever was waiting for the old socket is stale, the full state follows anyway
+        self.outbox.clear();
+
+        let (outgoing, incoming, writer, reader) = Self::spawn_io(websocket_send, websocket_recv);
+        self.outgoing = Some(outgoing);
+        self.incoming = incoming;
+        self.writer = writer;
+        self.reader = reader;
+        self.connected = true;
+        self.disconnected_ticks = 0;
+        self.resumed = true;
+    }
+
+    // True once a disconnected client has run out of time to resume
+    pub fn expired(&self, grace_ticks: i32) -> bool {
+        return self.kicked || (!self.connected && self.disconnected_ticks > grace_ticks);
     }
 }
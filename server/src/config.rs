@@ -0,0 +1,411 @@
+use std::env;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+use pixel_demolition_common::match_settings::MatchSettings;
+use pixel_demolition_common::server_tick::ServerTick;
+
+use crate::client::Client;
+use crate::engine::Engine;
+use crate::game_matches::GameMatches;
+use crate::logging::Logging;
+use crate::shutdown::Shutdown;
+
+// Settings are applied in order of increasing priority:
+// defaults < TOML config file < environment variables < command line flags
+pub struct Config {
+    pub bind_address: IpAddr,
+    pub port: u16,
+    pub max_matches: usize,
+    // In seconds
+    pub timeout: i32,
+    // Default for new matches, the host can change it in the lobby
+    pub kills_to_win: i32,
+    // In ms
+    pub server_tick: i32,
+    // In seconds
+    pub shutdown_grace: i32,
+    // In seconds
+    pub resume_grace: i32,
+    // Either a level such as "debug" or a filter such as "pixel_demolition_server=debug,warp=warn"
+    pub log_level: String,
+    // "text" or "json"
+    pub log_format: String,
+    // Directory to write match replays to, recording is off when empty
+    pub replay_dir: String,
+}
+
+impl Config {
+    pub const ENV_PREFIX: &'static str = "PIXEL_DEMOLITION_";
+
+    pub const KEYS: [&'static str; 11] = [
+        "bind_address",
+        "port",
+        "max_matches",
+        "timeout",
+        "kills_to_win",
+        "server_tick",
+        "shutdown_grace",
+        "resume_grace",
+        "log_level",
+        "log_format",
+        "replay_dir",
+    ];
+
+    pub fn new() -> Config {
+        Config {
+            bind_address: IpAddr::from([127, 0, 0, 1]),
+            port: 8000,
+            max_matches: GameMatches::MAX_MATCHES,
+            timeout: Engine::TIMEOUT,
+            kills_to_win: MatchSettings::KILLS_TO_WIN,
+            server_tick: ServerTick::SERVER_TICK,
+            shutdown_grace: Shutdown::GRACE_PERIOD,
+            resume_grace: Client::RESUME_GRACE,
+            log_level: String::from("info"),
+            log_format: String::from("text"),
+            replay_dir: String::new(),
+        }
+    }
+
+    pub fn load() -> Result<Config, String> {
+        let args: Vec<String> = env::args().skip(1).collect();
+
+        return Self::from_sources(&args, &|key| env::var(key).ok());
+    }
+
+    // Layers the config file, environment and flags over the defaults, env_var looks up a
+    // single environment variable
+    fn from_sources(args: &Vec<String>, env_var: &dyn Fn(&str) -> Option<String>) -> Result<Config, String> {
+        let mut config = Config::new();
+
+        let flags = Self::parse_args(args)?;
+
+        // The config file location can come from either a flag or the environment
+        let mut config_path = env_var(&format!("{}CONFIG", Self::ENV_PREFIX));
+
+        for (key, value) in &flags {
+            if key == "config" {
+                config_path = Some(value.clone());
+            }
+        }
+
+        if let Some(config_path) = config_path {
+            config.apply_file(&config_path)?;
+        }
+
+        for key in Self::KEYS {
+            let env_key = format!("{}{}", Self::ENV_PREFIX, key.to_uppercase());
+
+            if let Some(value) = env_var(&env_key) {
+                config
+                    .apply(key, &value)
+                    .map_err(|err| format!("{}: {}", env_key, err))?;
+            }
+        }
+
+        for (key, value) in &flags {
+            if key == "config" {
+                continue;
+            }
+
+            config
+                .apply(key, value)
+                .map_err(|err| format!("--{}: {}", key.replace('_', "-"), err))?;
+        }
+
+        config.validate()?;
+
+        return Ok(config);
+    }
+
+    pub fn usage() -> String {
+        let mut usage = String::from("Usage: pixel_demolition_server [OPTIONS]\n\nOptions:\n");
+        usage += "  --config <path>        TOML file to read settings from\n";
+
+        for key in Self::KEYS {
+            usage += &format!(
+                "  --{:<20} (env {}{})\n",
+                key.replace('_', "-"),
+                Self::ENV_PREFIX,
+                key.to_uppercase()
+            );
+        }
+
+        return usage;
+    }
+
+    // Accepts both "--key value" and "--key=value", returning keys in snake_case
+    fn parse_args(args: &Vec<String>) -> Result<Vec<(String, String)>, String> {
+        let mut flags: Vec<(String, String)> = Vec::new();
+
+        let mut i = 0;
+        while i < args.len() {
+            let arg = &args[i];
+
+            if arg == "--help" || arg == "-h" {
+                return Err(Self::usage());
+            }
+
+            let flag = arg.strip_prefix("--");
+
+            if flag.is_none() {
+                return Err(format!("Unexpected argument {}\n\n{}", arg, Self::usage()));
+            }
+
+            let flag = flag.unwrap();
+
+            let (key, value) = match flag.split_once('=') {
+                Some((key, value)) => (key, Some(value.to_string())),
+                None => (flag, None),
+            };
+
+            let key = key.replace('-', "_");
+
+            if key != "config" && !Self::KEYS.contains(&key.as_str()) {
+                return Err(format!("Unknown option {}\n\n{}", arg, Self::usage()));
+            }
+
+            let value = match value {
+                Some(value) => value,
+                None => {
+                    i += 1;
+
+                    if i >= args.len() {
+                        return Err(format!("Missing value for {}", arg));
+                    }
+
+                    args[i].clone()
+                }
+            };
+
+            flags.push((key, value));
+            i += 1;
+        }
+
+        return Ok(flags);
+    }
+
+    fn apply_file(&mut self, path: &String) -> Result<(), String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| format!("Unable to read config file {}: {}", path, err))?;
+
+        let table = contents
+            .parse::<toml::Table>()
+            .map_err(|err| format!("Unable to parse config file {}: {}", path, err))?;
+
+        for (key, value) in table {
+            let value = match value {
+                toml::Value::String(value) => value,
+                toml::Value::Integer(value) => value.to_string(),
+                _ => return Err(format!("{}: {} must be a string or integer", path, key)),
+            };
+
+            self.apply(&key, &value)
+                .map_err(|err| format!("{}: {}: {}", path, key, err))?;
+        }
+
+        return Ok(());
+    }
+
+    fn apply(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "bind_address" => {
+                self.bind_address = value.parse().map_err(|_| "Invalid IP address")?;
+            }
+            "port" => {
+                self.port = value.parse().map_err(|_| "Invalid port")?;
+            }
+            "max_matches" => {
+                self.max_matches = value.parse().map_err(|_| "Invalid match count")?;
+            }
+            "timeout" => {
+                self.timeout = value.parse().map_err(|_| "Invalid timeout")?;
+            }
+            "kills_to_win" => {
+                self.kills_to_win = value.parse().map_err(|_| "Invalid kill count")?;
+            }
+            "server_tick" => {
+                self.server_tick = value.parse().map_err(|_| "Invalid server tick")?;
+            }
+            "shutdown_grace" => {
+                self.shutdown_grace = value.parse().map_err(|_| "Invalid grace period")?;
+            }
+            "resume_grace" => {
+                self.resume_grace = value.parse().map_err(|_| "Invalid grace period")?;
+            }
+            "log_level" => {
+                self.log_level = value.to_string();
+            }
+            "log_format" => {
+                self.log_format = value.to_lowercase();
+            }
+            "replay_dir" => {
+                self.replay_dir = value.to_string();
+            }
+            _ => return Err(format!("Unknown setting {}", key)),
+        }
+
+        return Ok(());
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.max_matches < 1 {
+            return Err(String::from("max_matches must be at least 1"));
+        }
+
+        if self.timeout < 1 {
+            return Err(String::from("timeout must be at least 1 second"));
+        }
+
+        if self.kills_to_win < 1 {
+            return Err(String::from("kills_to_win must be at least 1"));
+        }
+
+        if self.server_tick < 1 || self.server_tick > 1000 {
+            return Err(String::from("server_tick must be between 1 and 1000 ms"));
+        }
+
+        if self.shutdown_grace < 0 {
+            return Err(String::from("shutdown_grace can't be negative"));
+        }
+
+        if self.resume_grace < 0 {
+            return Err(String::from("resume_grace can't be negative"));
+        }
+
+        if !Logging::FORMATS.contains(&self.log_format.as_str()) {
+            return Err(format!("log_format must be one of {}", Logging::FORMATS.join(", ")));
+        }
+
+        if !self.replay_dir.is_empty() && !Path::new(&self.replay_dir).is_dir() {
+            return Err(format!("replay_dir {} is not a directory", self.replay_dir));
+        }
+
+        return Ok(());
+    }
+
+    // Rules a new match starts with before the host changes anything
+    pub fn match_settings(&self) -> MatchSettings {
+        let mut settings = MatchSettings::new();
+        settings.kills_to_win = self.kills_to_win;
+        settings.server_tick = self.server_tick;
+
+        return settings;
+    }
+
+    // Convert a duration in seconds into a number of server ticks
+    pub fn secs_to_ticks(&self, secs: i32) -> i32 {
+        return secs * 1000 / self.server_tick;
+    }
+
+    // Convert a tick count defined against the default server tick into the configured rate
+    pub fn scale_ticks(&self, ticks: i32) -> i32 {
+        return ticks * ServerTick::SERVER_TICK / self.server_tick;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        return args.iter().map(|arg| arg.to_string()).collect();
+    }
+
+    fn load(flags: &[&str], vars: &[(&str, &str)]) -> Result<Config, String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        return Config::from_sources(&args(flags), &|key| vars.get(key).cloned());
+    }
+
+    #[test]
+    fn later_layers_override_earlier_ones() {
+        let path = env::temp_dir().join(format!("pixel_demolition_config_{}.toml", std::process::id()));
+        fs::write(&path, "port = 1000\nkills_to_win = 3\ntimeout = 100\n").unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let config = load(
+            &["--port", "3000"],
+            &[
+                ("PIXEL_DEMOLITION_CONFIG", path.as_str()),
+                ("PIXEL_DEMOLITION_PORT", "2000"),
+                ("PIXEL_DEMOLITION_KILLS_TO_WIN", "4"),
+            ],
+        );
+
+        fs::remove_file(&path).unwrap();
+
+        let config = config.unwrap();
+        assert_eq!(config.port, 3000);
+        assert_eq!(config.kills_to_win, 4);
+        assert_eq!(config.timeout, 100);
+        assert_eq!(config.max_matches, GameMatches::MAX_MATCHES);
+    }
+
+    #[test]
+    fn flags_take_either_form() {
+        let flags = Config::parse_args(&args(&["--max-matches", "3", "--port=8001"])).unwrap();
+
+        assert_eq!(flags, vec![
+            (String::from("max_matches"), String::from("3")),
+            (String::from("port"), String::from("8001")),
+        ]);
+    }
+
+    #[test]
+    fn unknown_flags_are_rejected() {
+        let result = Config::parse_args(&args(&["--ports", "8001"]));
+
+        assert!(result.unwrap_err().starts_with("Unknown option --ports"));
+
+        // Even at the end, where there is no value to go with it
+        let result = Config::parse_args(&args(&["--ports"]));
+
+        assert!(result.unwrap_err().starts_with("Unknown option --ports"));
+    }
+
+    #[test]
+    fn flags_need_a_value() {
+        let result = Config::parse_args(&args(&["--port"]));
+
+        assert_eq!(result.unwrap_err(), "Missing value for --port");
+    }
+
+    #[test]
+    fn invalid_numbers_name_their_source() {
+        let result = load(&["--port", "80a"], &[]);
+        assert_eq!(result.err(), Some(String::from("--port: Invalid port")));
+
+        let result = load(&[], &[("PIXEL_DEMOLITION_SERVER_TICK", "fast")]);
+        assert_eq!(result.err(), Some(String::from("PIXEL_DEMOLITION_SERVER_TICK: Invalid server tick")));
+    }
+
+    #[test]
+    fn ticks_follow_the_server_tick() {
+        let config = load(&["--server-tick", "30"], &[]).unwrap();
+
+        // Clients interpolate at the rate they are told
+        assert_eq!(config.match_settings().server_tick, 30);
+        assert_eq!(config.secs_to_ticks(2), 2 * 1000 / 30);
+        // Twice the ticks at half the tick length, so weapons fire just as often in real time
+        assert_eq!(config.scale_ticks(10), 10 * ServerTick::SERVER_TICK / 30);
+        assert_eq!(config.scale_ticks(10), 20);
+
+        let config = Config::new();
+        assert_eq!(config.scale_ticks(10), 10);
+
+        // A tick that doesn't divide a second still adds up over longer durations
+        let config = load(&["--server-tick", "600"], &[]).unwrap();
+        assert_eq!(config.secs_to_ticks(1), 1);
+        assert_eq!(config.secs_to_ticks(10), 16);
+
+        let config = load(&["--server-tick", "300"], &[]).unwrap();
+        assert_eq!(config.secs_to_ticks(30), 100);
+    }
+}
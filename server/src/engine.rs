@@ -1,73 +1,116 @@
+use std::any::Any;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::sync::RwLock;
 
-use futures::{FutureExt, SinkExt, StreamExt};
 use image::{self, RgbaImage};
-use rand::Rng;
-use tokio::time::{self, Duration};
-use warp::ws::Message;
+use tokio::time::{self, Duration, Instant};
+use tracing::{error, info, warn, Instrument};
 
-use pixel_demolition_common::collision_system::CollisionSystem;
+use pixel_demolition_common::client_message::ClientMessage;
 use pixel_demolition_common::player::Player;
-use pixel_demolition_common::projectile::Projectile;
 use pixel_demolition_common::proto::Proto;
-use pixel_demolition_common::server_tick::ServerTick;
-use pixel_demolition_common::vel_system::VelSystem;
-use pixel_demolition_common::weapon::Weapon;
+use pixel_demolition_common::server_message::ServerMessage;
 
+use crate::config::Config;
 use crate::game_matches::game_match::GameMatch;
+use crate::game_matches::GameMatches;
+use crate::metrics::{Metrics, METRICS};
+use crate::replay_recorder::ReplayRecorder;
+use crate::shutdown::Shutdown;
+use crate::simulation::{Event, PlayerAction, Simulation};
 
 pub struct Engine {}
 
 impl Engine {
-    // Spawn new weapons every 30 seconds
-    pub const WEAPONS_SPAWN_TICKS: i32 = 30 * (1000 as f32 / ServerTick::SERVER_TICK as f32) as i32;
-    pub const SPAWN_WEAPONS_COUNT: usize = 10;
-    pub const PICKUP_RANGE: f32 = 20.0;
-    pub const KILLS_TO_WIN_GAME: i32 = 5;
+    // Timeout after 30 minutes
+    pub const TIMEOUT: i32 = 30*60;
+
+    // Runs the match in a task of its own so a panic can't leave its code taken and its clients
+    // hanging, then takes the match out of the registry however it ended
+    pub async fn supervise(game_matches: Arc<GameMatches>, game_match: Arc<RwLock<GameMatch>>, code: String) {
+        let task = {
+            let game_matches = game_matches.clone();
+            let game_match = game_match.clone();
+
+            tokio::spawn(async move {
+                Self::handle(&game_match, &game_matches.config, &game_matches.shutdown).await;
+            }.in_current_span())
+        };
+
+        if let Err(err) = task.await {
+            let reason = match err.try_into_panic() {
+                Ok(panic) => Self::panic_message(&*panic),
+                Err(_) => String::from("cancelled"),
+            };
 
-    const CLEAR_PIXEL: image::Rgba<u8> = image::Rgba::<u8>([0, 0, 0, 0]);
+            error!("Match task failed: {}", reason);
+            METRICS.match_outcome(Metrics::OUTCOME_CRASHED);
 
-    // Run collision checks for projectiles 10 times per tick
-    const PROJECTILE_INTERP_RATE: i32 = 10;
+            // Whatever the match was in the middle of is lost, tell everyone before letting go
+            game_match.write().await.broadcast(&ServerMessage::MatchError);
+            Self::release_match(&game_match).await;
+        }
 
-    // Timeout after 30 minutes
-    const TIMEOUT: i32 = 30*60*(1000/ServerTick::SERVER_TICK);
+        game_matches.remove(&code);
+    }
 
-    pub async fn handle(game_match: &RwLock<GameMatch>) {
-        let result = Self::lobby(game_match).await;
+    fn panic_message(panic: &(dyn Any + Send)) -> String {
+        if let Some(message) = panic.downcast_ref::<&str>() {
+            return String::from(*message);
+        }
+
+        if let Some(message) = panic.downcast_ref::<String>() {
+            return message.clone();
+        }
+
+        return String::from("unknown panic");
+    }
+
+    pub async fn handle(game_match: &RwLock<GameMatch>, config: &Config, shutdown: &Shutdown) {
+        let result = Self::lobby(game_match, config, shutdown).await;
 
         if let Err(error) = result {
-            println!("Releasing lobby early: {}", error);
+            info!("Releasing lobby early: {}", error);
             Self::release_match(game_match).await;
             return;
         }
 
-        let mut players = result.unwrap();
+        let players = result.unwrap();
 
-        let result = Self::main(game_match, &mut players).await;
+        let result = Self::main(game_match, players, config, shutdown).await;
 
         if let Err(error) = result {
-            println!("Releasing match early: {}", error);
+            info!("Releasing match early: {}", error);
+            Self::save_replay(game_match, config).await;
             Self::release_match(game_match).await;
             return;
         }
 
+        let mut players = result.unwrap();
+
         Self::game_over(game_match, &mut players).await;
+        Self::save_replay(game_match, config).await;
         Self::release_match(game_match).await;
-        println!("Released game match");
+        info!("Released game match");
     }
 
-    pub async fn lobby(game_match: &RwLock<GameMatch>) -> Result<Vec<Player>, &'static str> {
+    pub async fn lobby(game_match: &RwLock<GameMatch>, config: &Config, shutdown: &Shutdown)
+        -> Result<Vec<Player>, &'static str>
+    {
         let mut client_len = 0;
 
         let mut players: Vec<Player> = Vec::new();
 
-        println!("In lobby");
+        info!("In lobby");
 
-        let mut interval = time::interval(Duration::from_millis(ServerTick::SERVER_TICK as u64));
+        let mut interval = time::interval(Duration::from_millis(config.server_tick as u64));
 
         let mut ticks_alive = 0;
 
+        let grace_ticks = config.secs_to_ticks(config.resume_grace);
+
         loop {
             interval.tick().await;
             {
@@ -77,8 +120,39 @@ impl Engine {
                     continue;
                 }
 
+                // Lobbies haven't started playing yet so there is nothing to wait on
+                if shutdown.is_started() {
+                    let shutdown_message = ServerMessage::ServerShutdown { seconds: 0 };
+
+                    game_match.broadcast(&shutdown_message);
+
+                    METRICS.match_outcome(Metrics::OUTCOME_SHUTDOWN);
+                    return Err("Server shutting down");
+                }
+
+                // Set whenever the host, lock or player indices change
+                let mut lobby_changed = false;
+
+                Self::handle_spectators(&mut game_match);
+
+                // Bring new spectators up to date, the lobby state and settings follow below
+                for spectator_i in 0..game_match.spectators.len() {
+                    if !game_match.spectators[spectator_i].resumed {
+                        continue;
+                    }
+
+                    game_match.spectators[spectator_i].resumed = false;
+                    lobby_changed = true;
+
+                    for message in &Self::lobby_state_messages(&game_match, &players) {
+                        game_match.spectators[spectator_i].send(message);
+                    }
+                }
+
                 // Broadcast new players
                 if game_match.clients.len() > client_len {
+                    lobby_changed = true;
+
                     for client_i in client_len..game_match.clients.len() {
                         let new_player = Player::new(game_match.clients[client_i].name.clone());
                         players.push(new_player);
@@ -86,38 +160,62 @@ impl Engine {
 
                     client_len = game_match.clients.len();
 
-                    let mut names: Vec<String> = Vec::new();
+                    let player_list_message = ServerMessage::PlayerList { names: game_match.names() };
+
+                    game_match.broadcast(&player_list_message);
+                }
 
-                    for client in &game_match.clients {
-                        names.push(client.name.clone());
+                // Bring clients that reconnected back up to date
+                for client_i in 0..game_match.clients.len() {
+                    if !game_match.clients[client_i].resumed {
+                        continue;
                     }
 
-                    let player_list_message = Proto::tct_player_list(&names);
+                    game_match.clients[client_i].resumed = false;
+                    lobby_changed = true;
 
-                    for client in &mut game_match.clients {
-                        let websocket_send = &mut client.websocket_send;
-                        let _ = websocket_send
-                            .send(Message::binary(player_list_message.clone()))
-                            .await;
+                    for message in &Self::lobby_state_messages(&game_match, &players) {
+                        game_match.clients[client_i].send(message);
                     }
                 }
 
-                // Handle toggle messages
+                // Clients that left or ran out of time to resume, in ascending order
+                let mut departed: Vec<usize> = Vec::new();
+
+                let mut force_start = false;
+
+                // Handle toggle, leave and host messages
                 for client_i in 0..game_match.clients.len() {
+                    let is_host = client_i == game_match.host;
+
                     let client = &mut game_match.clients[client_i];
                     let player = &mut players[client_i];
 
-                    let message = client.websocket_recv.next().now_or_never();
+                    if !client.connected {
+                        client.disconnected_ticks += 1;
+
+                        if client.expired(grace_ticks) {
+                            info!(player_i = client_i, "Resume grace period expired in lobby");
+                            departed.push(client_i);
+                        }
+
+                        continue;
+                    }
+
+                    let message = client.next_message();
 
-                    // Continue now_or_never() returned None
+                    // Continue if no messages in the queue
                     if message.is_none() {
                         continue;
                     }
 
                     let message = message.unwrap();
 
-                    // Continue if there was no valid websocket message 
+                    // The socket closed, hold the slot in case the client resumes
                     if message.is_none() {
+                        client.connected = false;
+                        METRICS.client_disconnected();
+                        info!(player_i = client_i, "Player disconnected in lobby");
                         continue;
                     }
 
@@ -130,24 +228,102 @@ impl Engine {
 
                     let message = message.unwrap().into_bytes();
 
-                    let message_type = Proto::get_type(&message);
+                    METRICS.message_received(&message);
 
-                    if message_type.is_err() {
+                    let message = ClientMessage::decode(&message);
+
+                    if message.is_err() {
                         continue;
                     }
 
-                    if message_type.unwrap() == Proto::TST_TOGGLE_READY {
-                        player.ready = !player.ready;
-                        let toggle_message = Proto::tct_toggle_ready(client_i, player.ready);
-                        for client in &mut game_match.clients {
-                            let websocket_send = &mut client.websocket_send;
-                            let _ = websocket_send
-                                .send(Message::binary(toggle_message.clone()))
-                                .await;
-                        }
+                    match message.unwrap() {
+                        ClientMessage::ToggleReady => {
+                            player.ready = !player.ready;
+                            let toggle_message = ServerMessage::ToggleReady { player_i: client_i, ready: player.ready };
+                            game_match.broadcast(&toggle_message);
+                        },
+                        ClientMessage::LeaveMatch => {
+                            info!(player_i = client_i, "Player left lobby");
+                            departed.push(client_i);
+                        },
+                        ClientMessage::KickPlayer { player_i: kick_i } if is_host => {
+                            if kick_i == client_i || kick_i >= game_match.clients.len() {
+                                continue;
+                            }
+
+                            info!(player_i = kick_i, "Host kicked player");
+
+                            let kicked_message = ServerMessage::Kicked { reason: Proto::KICK_REASON_HOST };
+                            game_match.clients[kick_i].send(&kicked_message);
+
+                            departed.push(kick_i);
+                        },
+                        ClientMessage::ForceStart if is_host && players.len() >= GameMatch::MIN_PLAYERS => {
+                            info!(player_i = client_i, "Host forced the game to start");
+                            force_start = true;
+                        },
+                        ClientMessage::LockLobby if is_host => {
+                            game_match.locked = !game_match.locked;
+                            info!(player_i = client_i, locked = game_match.locked, "Host toggled lobby lock");
+                            lobby_changed = true;
+                        },
+                        ClientMessage::UpdateSettings(mut settings) if is_host => {
+                            // The tick is the server's to decide
+                            settings.server_tick = game_match.settings.server_tick;
+
+                            if let Err(error) = settings.validate() {
+                                warn!(player_i = client_i, "Rejected match settings: {}", error);
+                                continue;
+                            }
+
+                            game_match.settings = settings;
+                            lobby_changed = true;
+                        },
+                        _ => (),
+                    }
+                }
+
+                // Kicks can name a player out of order or one who is also leaving
+                departed.sort();
+                departed.dedup();
+
+                if departed.len() > 0 {
+                    lobby_changed = true;
+
+                    let host_left = departed.contains(&game_match.host);
+
+                    // Remove from the back so the remaining indices stay valid
+                    for client_i in departed.into_iter().rev() {
+                        players.remove(client_i);
+                        game_match.remove_client(client_i);
+                    }
+
+                    client_len = game_match.clients.len();
+
+                    if game_match.clients.len() < 1 {
+                        METRICS.match_outcome(Metrics::OUTCOME_ABANDONED);
+                        return Err("All clients left");
+                    }
+
+                    if host_left {
+                        info!(player_i = game_match.host, "Host reassigned");
+                    }
+
+                    // Everyone after a removed player moved up, so resend the whole lobby
+                    for message in &Self::lobby_state_messages(&game_match, &players) {
+                        game_match.broadcast(message);
                     }
                 }
 
+                if lobby_changed {
+                    game_match.broadcast_lobby_state();
+
+                    let match_settings_message = ServerMessage::MatchSettings(game_match.settings.clone());
+                    game_match.broadcast(&match_settings_message);
+                }
+
+                game_match.flush();
+
                 // Check to see if all players are ready
                 let mut ready = true;
                 for player in &players {
@@ -156,13 +332,18 @@ impl Engine {
                     }
                 }
 
-                if ready {
+                if ready || force_start {
+                    // Switch states while still holding the lock so nobody joins a running game
+                    game_match.state = GameMatch::GAME;
+                    METRICS.match_state_changed(GameMatch::LOBBY, GameMatch::GAME);
+
                     return Ok(players);
                 }
 
             }
 
-            if ticks_alive > Self::TIMEOUT {
+            if ticks_alive > config.secs_to_ticks(config.timeout) {
+                METRICS.match_outcome(Metrics::OUTCOME_LOBBY_TIMEOUT);
                 return Err("Lobby timeout");
             }
             ticks_alive += 1;
@@ -170,59 +351,159 @@ impl Engine {
         }
     }
 
-    pub async fn main(game_match: &RwLock<GameMatch>, players: &mut Vec<Player>)
-        -> Result<(), &'static str>
+    pub async fn main(
+        game_match: &RwLock<GameMatch>,
+        players: Vec<Player>,
+        config: &Config,
+        shutdown: &Shutdown,
+    ) -> Result<Vec<Player>, &'static str>
     {
-        // Once the match starts we can permanently lock it since clients won't be added and
-        // InitWSHandler can still read() it for checking the status
-        let game_match = &mut game_match.write().await;
-        game_match.state = GameMatch::GAME;
-
         // Create a fresh copy of the map
         let map_bytes = include_bytes!("../static/map.png");
         let dynamic_map_image = image::load_from_memory(map_bytes);
-        let mut map = dynamic_map_image.unwrap().into_rgba8();
+        let map = dynamic_map_image.unwrap().into_rgba8();
 
-        let mut projectiles: Vec<Projectile> = Vec::new();
-        let mut ground_weapons: Vec<Weapon> = Vec::new();
-        let mut ticks_since_weapon_spawn: i32 = -1;
+        // Kept untouched to work out which pixels were destroyed when a client resumes
+        let original_map = map.clone();
+
+        let grace_ticks = config.secs_to_ticks(config.resume_grace);
 
         let mut messages: Vec<Vec<u8>> = Vec::new();
 
-        for client_i in 0..game_match.clients.len() {
-            let (x, y) = Self::get_rand_pos(&map);
-            players[client_i].x = x;
-            players[client_i].y = y;
+        // Settings can only be changed in the lobby so they are fixed from here on
+        let settings = game_match.read().await.settings.clone();
+
+        let mut simulation = Simulation::new(players, map, settings);
 
-            let start_message = Proto::tct_start_game(client_i, x, y);
+        {
+            let game_match = &mut game_match.write().await;
 
-            let client = &mut game_match.clients[client_i];
-            let websocket_send = &mut client.websocket_send;
+            if !config.replay_dir.is_empty() {
+                let mut names = game_match.names();
+                names.truncate(simulation.players.len());
 
-            let _ = websocket_send
-                .send(Message::binary(start_message.clone()))
-                .await;
+                game_match.replay = Some(ReplayRecorder::new(
+                    config.server_tick,
+                    &names,
+                    &simulation.settings,
+                    map_bytes,
+                ));
+            }
+
+            // Anyone who joined after the lobby finished is picked up as a late join
+            for client_i in 0..simulation.players.len() {
+                let player = &simulation.players[client_i];
+
+                let start_message = ServerMessage::StartGame { player_i: client_i, x: player.x, y: player.y };
+
+                game_match.send_to(client_i, &start_message);
+            }
+
+            // Spectators have no start message, they switch over once they get the game state
+            for spectator in &mut game_match.spectators {
+                spectator.resumed = true;
+            }
+
+            game_match.flush();
         }
 
         let mut ticks_alive = 0;
 
-        let mut interval = time::interval(Duration::from_millis(ServerTick::SERVER_TICK as u64));
+        // Set once the server starts shutting down, the match ends when it runs out
+        let mut shutdown_ticks_left: Option<i32> = None;
+
+        let mut interval = time::interval(Duration::from_millis(config.server_tick as u64));
 
         'game_loop: loop {
             interval.tick().await;
 
-            for player_i in 0..players.len() {
+            // Only lock for the tick so resuming clients can get at the match in between
+            let game_match = &mut game_match.write().await;
+
+            let tick_start = Instant::now();
+
+            if let Some(replay) = &mut game_match.replay {
+                replay.set_tick(ticks_alive as u32);
+            }
+
+            for client_i in simulation.players.len()..game_match.clients.len() {
+                Self::handle_late_join(game_match, &mut simulation, client_i, &original_map);
+            }
+
+            Self::handle_spectators(game_match);
+
+            for spectator_i in 0..game_match.spectators.len() {
+                if !game_match.spectators[spectator_i].resumed {
+                    continue;
+                }
+
+                game_match.spectators[spectator_i].resumed = false;
+
+                let player_list_message = ServerMessage::PlayerList { names: game_match.names() };
+
+                let mut state_messages = Self::game_state_messages(
+                    game_match,
+                    &simulation,
+                    Proto::SPECTATOR as usize,
+                    &original_map,
+                );
+                state_messages.insert(0, player_list_message);
+
+                for message in &state_messages {
+                    game_match.spectators[spectator_i].send(message);
+                }
+            }
+
+            if shutdown_ticks_left.is_none() && shutdown.is_started() {
+                info!(grace = config.shutdown_grace, "Server shutting down, ending match");
+
+                let shutdown_message = ServerMessage::ServerShutdown { seconds: config.shutdown_grace as u32 };
+
+                game_match.broadcast(&shutdown_message);
+
+                shutdown_ticks_left = Some(config.secs_to_ticks(config.shutdown_grace));
+            }
+
+            if let Some(ticks_left) = shutdown_ticks_left {
+                if ticks_left <= 0 {
+                    METRICS.match_outcome(Metrics::OUTCOME_SHUTDOWN);
+                    break 'game_loop;
+                }
+
+                shutdown_ticks_left = Some(ticks_left - 1);
+            }
+
+            for player_i in 0..simulation.players.len() {
+                if game_match.clients[player_i].resumed {
+                    game_match.clients[player_i].resumed = false;
+
+                    Self::send_game_state(game_match, &simulation, player_i, &original_map);
+                }
+
+                if game_match.clients[player_i].connected {
+                    continue;
+                }
+
+                game_match.clients[player_i].disconnected_ticks += 1;
+
+                // Take the player out of play once their slot can no longer be resumed
+                if game_match.clients[player_i].expired(grace_ticks) && simulation.retire_player(player_i) {
+                    info!(player_i, "Resume grace period expired");
+                }
+            }
+
+            let mut actions: Vec<Vec<PlayerAction>> = Vec::new();
+
+            for player_i in 0..simulation.players.len() {
                 messages.clear();
+                actions.push(Vec::new());
 
                 if !game_match.clients[player_i].connected {
                     continue;
                 }
 
                 loop {
-                    let message = game_match.clients[player_i]
-                        .websocket_recv
-                        .next()
-                        .now_or_never();
+                    let message = game_match.clients[player_i].next_message();
 
                     // Break if no messages in the queue
                     if message.is_none() {
@@ -234,6 +515,8 @@ impl Engine {
                     // Break if the socket closed
                     if message.is_none() {
                         game_match.clients[player_i].connected = false;
+                        METRICS.client_disconnected();
+                        info!(player_i, "Player disconnected");
                         break;
                     }
 
@@ -241,112 +524,98 @@ impl Engine {
 
                     // Continue if there was a websocket error
                     if message.is_err() {
-                        println!("{}", message.unwrap_err());
+                        warn!(player_i, "{}", message.unwrap_err());
                         continue;
                     }
 
                     let message = message.unwrap().into_bytes();
 
+                    METRICS.message_received(&message);
+
+                    // Still read off the socket so a flood can't pile up, but not handled
+                    if !game_match.clients[player_i].validation.allow_message() {
+                        continue;
+                    }
+
                     messages.push(message);
                 }
 
-                let mut set_angle = false;
-                let mut set_pos = false;
+                for message in &messages {
+                    let message = ClientMessage::decode(message);
 
-                // Iterate over the newest messages first
-                for message in (&messages).into_iter().rev() {
-                    let message_type = Proto::get_type(&message);
-
-                    if message_type.is_err() {
+                    if message.is_err() {
+                        warn!(player_i, "{}", message.unwrap_err());
+                        game_match.clients[player_i].validation
+                            .flag(Proto::KICK_REASON_INVALID_MESSAGE);
                         continue;
                     }
 
-                    match message_type.unwrap() {
-                        Proto::TST_NEW_POS => {
-                            // If the player position has already been updated with a newer message,
-                            // ignore this one
-                            if set_pos {
-                                continue;
-                            }
+                    let action = match message.unwrap() {
+                        ClientMessage::Input(input) => PlayerAction::Input(input),
+                        ClientMessage::TakeWeapon => PlayerAction::TakeWeapon,
+                        ClientMessage::TriggerPulled => PlayerAction::TriggerPulled,
+                        ClientMessage::TriggerReleased => PlayerAction::TriggerReleased,
+                        _ => continue,
+                    };
 
-                            Self::handle_pos_update(&message, game_match, players, player_i, &map)
-                                .await;
+                    actions[player_i].push(action);
+                }
+            }
 
-                            set_pos = true;
-                        }
+            let events = simulation.tick(&actions, config);
 
-                        Proto::TST_NEW_ANGLE => {
-                            // If the player angle has already been updated with a newer message,
-                            // ignore this one
-                            if set_angle {
-                                continue;
-                            }
+            Self::send_events(game_match, &events);
 
-                            Self::handle_angle_update(&message, game_match, players, player_i)
-                                .await;
+            // Rejected inputs only count against the client once the simulation has run
+            for player_i in 0..simulation.players.len() {
+                if !game_match.clients[player_i].connected {
+                    continue;
+                }
 
-                            set_angle = true;
-                        }
-                        Proto::TST_TAKE_WEAPON => {
-                            let player = &mut players[player_i];
-                            Self::handle_take_weapon(
-                                game_match,
-                                player,
-                                player_i,
-                                &mut ground_weapons,
-                            )
-                            .await;
-                        }
-                        Proto::TST_TRIGGER_PULLED => {
-                            players[player_i].trigger_pulled = true;
-                        }
-                        Proto::TST_TRIGGER_RELEASED => {
-                            players[player_i].trigger_pulled = false;
-                        }
-                        _ => (),
-                    }
+                if let Some(reason) = game_match.clients[player_i].validation.end_tick(config) {
+                    warn!(player_i, reason, "Kicked for abusive messages");
+                    game_match.kick_client(player_i, reason);
                 }
             }
 
-            Self::handle_weapons(game_match, players, &mut projectiles).await;
-            Self::handle_projectiles(game_match, players, &mut projectiles, &mut map).await;
+            // Everything this tick produced goes out to each client in a single frame
+            game_match.flush();
 
-            Self::handle_player_respawns(game_match, players, &mut map).await;
+            METRICS.record_tick(
+                &game_match.code,
+                tick_start.elapsed(),
+                config.server_tick,
+                simulation.projectiles.len(),
+            );
 
-            if ticks_since_weapon_spawn < 0 || ticks_since_weapon_spawn >= Self::WEAPONS_SPAWN_TICKS
-            {
-                Self::handle_weapon_spawns(game_match, &mut ground_weapons, &map).await;
-                ticks_since_weapon_spawn = 0;
-            } else {
-                ticks_since_weapon_spawn += 1;
-            }
-
-            for player_i in 0..players.len() {
-                if players[player_i].kills >= Self::KILLS_TO_WIN_GAME {
-                    break 'game_loop;
-                }
+            if events.iter().any(|event| matches!(event, Event::GameWon { .. })) {
+                METRICS.match_outcome(Metrics::OUTCOME_COMPLETED);
+                break 'game_loop;
             }
 
             let mut any_connected = false;
             for client in &game_match.clients {
-                if client.connected {
+                // Clients that can still resume count as connected
+                if !client.expired(grace_ticks) {
                     any_connected = true;
                     break;
                 }
             }
 
             if !any_connected {
+                METRICS.match_outcome(Metrics::OUTCOME_ABANDONED);
                 return Err("All clients disconnected");
             }
 
-            if ticks_alive > Self::TIMEOUT {
+            if ticks_alive > config.secs_to_ticks(config.timeout) {
+                METRICS.match_outcome(Metrics::OUTCOME_GAME_TIMEOUT);
                 return Err("Game timeout");
 
             } 
             ticks_alive += 1;
         }
 
-        return Ok(());
+        return Ok(simulation.players);
     }
 
     pub async fn game_over(game_match: &RwLock<GameMatch>, players: &mut Vec<Player>) {
@@ -358,487 +627,329 @@ impl Engine {
             kills_deaths.push((player.kills, player.deaths));
         }
 
-        let game_over_stats_message = Proto::tct_game_over_stats(&kills_deaths);
-
-        for client in &mut game_match.clients {
-            let websocket_send = &mut client.websocket_send;
-            let _ = websocket_send
-                .send(Message::binary(game_over_stats_message.clone()))
-                .await;
-        }
-    }
-
-    pub async fn release_match(game_match: &RwLock<GameMatch>) {
-        let game_match = &mut game_match.write().await;
-
-        for client in &mut game_match.clients {
-            let websocket_send = &mut client.websocket_send;
-            let _ = websocket_send.close().await;
-        }
-
-        game_match.clients.clear();
+        let game_over_stats_message = ServerMessage::GameOverStats { kills_deaths };
 
-        game_match.state = GameMatch::UNUSED;
+        game_match.broadcast(&game_over_stats_message);
     }
 
-    pub async fn handle_pos_update(
-        message: &Vec<u8>,
-        game_match: &mut GameMatch,
-        players: &mut Vec<Player>,
-        player_i: usize,
-        map: &RgbaImage,
-    ) {
-        let message = Proto::parse_tst_new_pos(&message);
+    // Only touches the disk once the match is over so recording never holds up a tick
+    pub async fn save_replay(game_match: &RwLock<GameMatch>, config: &Config) {
+        let (replay, code) = {
+            let game_match = &mut game_match.write().await;
+            (game_match.replay.take(), game_match.code.clone())
+        };
 
-        if message.is_err() {
-            println!("{}", message.unwrap_err());
+        if replay.is_none() {
             return;
         }
 
-        let (x, y) = message.unwrap();
-
-        players[player_i].x = x;
-        players[player_i].y = y;
-
-        let player_pos_message = Proto::tct_new_pos(player_i, x, y);
-
-        for client_i in 0..game_match.clients.len() {
-            // Don't send the player's position back to them
-            if client_i == player_i {
-                continue;
-            }
-
-            let websocket_send = &mut game_match.clients[client_i].websocket_send;
-
-            let _ = websocket_send
-                .send(Message::binary(player_pos_message.clone()))
-                .await;
-        }
-
-        if CollisionSystem::player_oob(&players[player_i], map) {
-            Self::handle_player_death(game_match, players, player_i, None).await;
-        }
-    }
-
-    pub async fn handle_angle_update(
-        message: &Vec<u8>,
-        game_match: &mut GameMatch,
-        players: &mut Vec<Player>,
-        player_i: usize,
-    ) {
-        let angle = Proto::parse_tst_new_angle(&message);
+        let replay = replay.unwrap();
 
-        if angle.is_err() {
-            println!("{}", angle.unwrap_err());
-            return;
+        if replay.truncated {
+            warn!("Replay hit the size limit and was cut short");
         }
 
-        let angle = angle.unwrap();
-
-        players[player_i].angle = angle;
-
-        let player_angle_message = Proto::tct_new_angle(player_i, angle);
+        let finished = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
 
-        for client_i in 0..game_match.clients.len() {
-            // Don't send the player's angle back to them
-            if client_i == player_i {
-                continue;
-            }
+        let file_name = format!("{}-{}.{}", code, finished, ReplayRecorder::EXTENSION);
+        let path = Path::new(&config.replay_dir).join(file_name);
 
-            let websocket_send = &mut game_match.clients[client_i].websocket_send;
+        let data = replay.finish();
 
-            let _ = websocket_send
-                .send(Message::binary(player_angle_message.clone()))
-                .await;
+        match tokio::fs::write(&path, &data).await {
+            Ok(()) => info!(path = %path.display(), bytes = data.len(), "Saved replay"),
+            Err(err) => warn!(path = %path.display(), "Unable to save replay: {}", err),
         }
     }
 
-    pub async fn handle_take_weapon(
-        game_match: &mut GameMatch,
-        player: &mut Player,
-        player_i: usize,
-        ground_weapons: &mut Vec<Weapon>,
-    ) {
-        for weapon_i in 0..ground_weapons.len() {
-            if (player.x - ground_weapons[weapon_i].x).abs() < Self::PICKUP_RANGE
-                && (player.y - ground_weapons[weapon_i].y).abs() < Self::PICKUP_RANGE
-            {
-                let weapon_type = ground_weapons[weapon_i].weapon_type;
-
-                ground_weapons.remove(weapon_i as usize);
-
-                let remove_weapon_message = Proto::tct_remove_weapon(weapon_i);
-                let assign_weapon_message = Proto::tct_assign_weapon(player_i, weapon_type);
-
-                player.assign_weapon(weapon_type);
+    pub async fn release_match(game_match: &RwLock<GameMatch>) {
+        let game_match = &mut game_match.write().await;
 
-                for client in &mut game_match.clients {
-                    let websocket_send = &mut client.websocket_send;
-                    let _ = websocket_send
-                        .send(Message::binary(remove_weapon_message.clone()))
-                        .await;
+        // Anything sent since the last tick, like the game over stats, still has to go out
+        game_match.flush();
 
-                    let _ = websocket_send
-                        .send(Message::binary(assign_weapon_message.clone()))
-                        .await;
-                }
+        for client in &mut game_match.clients {
+            client.close();
 
-                break;
+            if client.connected {
+                METRICS.client_disconnected();
             }
         }
-    }
 
-    pub async fn handle_weapons(
-        game_match: &mut GameMatch,
-        players: &mut Vec<Player>,
-        projectiles: &mut Vec<Projectile>,
-    ) {
-        for player_i in 0..players.len() {
-            let player = &mut players[player_i];
-
-            if player.weapon_type.is_none() {
-                continue;
-            }
-
-            let weapon_type = player.weapon_type.unwrap();
-
-            let ticks_per_fire = Weapon::WEAPON_TYPES[weapon_type].ticks_per_fire;
-
-            if player.ticks_since_last_fire > -1 && player.ticks_since_last_fire <= ticks_per_fire {
-                player.ticks_since_last_fire += 1;
-                continue;
-            }
+        game_match.clients.clear();
 
-            if player.trigger_pulled == false {
-                continue;
-            }
+        for spectator in &mut game_match.spectators {
+            spectator.close();
 
-            if player.ammo <= 0 {
-                continue;
+            if spectator.connected {
+                METRICS.client_disconnected();
             }
+        }
 
-            let projectile_type = Weapon::WEAPON_TYPES[weapon_type].projectile_type;
-
-            let init_vel = Projectile::PROJECTILE_TYPES[projectile_type].init_vel;
-
-            let vel_x = -player.angle.cos() * init_vel;
-            let vel_y = -player.angle.sin() * init_vel;
-
-            let offset_x = -player.angle.cos() * (Weapon::WEAPON_WIDTH) as f32;
-            let offset_y = -player.angle.sin() * (Weapon::WEAPON_WIDTH) as f32;
-
-            let init_x = player.x + offset_x;
-            let init_y = player.y + offset_y;
-
-            let new_projectile = Projectile {
-                projectile_type,
-                x: init_x as f32,
-                y: init_y as f32,
-                vel_x,
-                vel_y,
-                owner: player_i,
-            };
-
-            let projectile_message = Proto::tct_new_projectile(&new_projectile);
+        game_match.spectators.clear();
 
-            projectiles.push(new_projectile);
+        game_match.replay = None;
 
-            for client_i in 0..game_match.clients.len() {
-                let client = &mut game_match.clients[client_i];
-                let websocket_send = &mut client.websocket_send;
-                let _ = websocket_send
-                    .send(Message::binary(projectile_message.clone()))
-                    .await;
+        METRICS.remove_match(&game_match.code);
+        METRICS.match_state_changed(game_match.state, GameMatch::UNUSED);
 
-                if client_i == player_i {
-                    let remove_ammo_message = Proto::tct_remove_ammo();
+        // The registry drops the match after this, anyone who already looked it up gets turned
+        // away by its state
+        game_match.state = GameMatch::UNUSED;
+    }
 
-                    let _ = websocket_send
-                        .send(Message::binary(remove_ammo_message.clone()))
-                        .await;
-                }
+    // Turns what happened in the simulation into messages for the clients
+    fn send_events(game_match: &mut GameMatch, events: &Vec<Event>) {
+        for event in events {
+            match event {
+                Event::InputApplied { player_i, seq, x, y, vel_y, jumps } => {
+                    let input_ack_message = ServerMessage::InputAck {
+                        seq: *seq,
+                        x: *x,
+                        y: *y,
+                        vel_y: *vel_y,
+                        jumps: *jumps,
+                    };
+                    game_match.send_to(*player_i, &input_ack_message);
+                },
+                Event::Aimed { player_i, angle } => {
+                    let player_angle_message = ServerMessage::NewAngle { player_i: *player_i, angle: *angle };
+                    game_match.broadcast_except(*player_i, &player_angle_message);
+                },
+                Event::Moved { player_i, x, y } => {
+                    let player_pos_message = ServerMessage::NewPos { player_i: *player_i, x: *x, y: *y };
+                    game_match.broadcast_except(*player_i, &player_pos_message);
+                },
+                Event::InputRejected { player_i, reason } => {
+                    game_match.clients[*player_i].validation.flag(*reason);
+                },
+                Event::WeaponTaken { player_i, weapon_id, weapon_type } => {
+                    let remove_weapon_message = ServerMessage::RemoveWeapon { weapon_id: *weapon_id };
+                    let assign_weapon_message = ServerMessage::AssignWeapon {
+                        player_i: *player_i,
+                        weapon_type: *weapon_type,
+                    };
+
+                    game_match.broadcast(&remove_weapon_message);
+                    game_match.broadcast(&assign_weapon_message);
+                },
+                Event::ProjectileFired { player_i, projectile } => {
+                    let projectile_message = ServerMessage::NewProjectile {
+                        projectile_id: projectile.id,
+                        projectile_type: projectile.projectile_type,
+                        x: projectile.x,
+                        y: projectile.y,
+                        vel_x: projectile.vel_x,
+                        vel_y: projectile.vel_y,
+                    };
+
+                    game_match.broadcast(&projectile_message);
+
+                    let remove_ammo_message = ServerMessage::RemoveAmmo;
+                    game_match.send_to(*player_i, &remove_ammo_message);
+                },
+                Event::ProjectileRemoved { projectile_id } => {
+                    let destroy_projectile_message = ServerMessage::DestroyProjectile { projectile_id: *projectile_id };
+                    game_match.broadcast(&destroy_projectile_message);
+                },
+                Event::ProjectileExploded { projectile } => {
+                    let projectile_explosion_message = ServerMessage::ProjectileExplosion {
+                        projectile_type: projectile.projectile_type,
+                        x: projectile.x,
+                        y: projectile.y,
+                    };
+                    let destroy_projectile_message = ServerMessage::DestroyProjectile { projectile_id: projectile.id };
+
+                    game_match.broadcast(&projectile_explosion_message);
+                    game_match.broadcast(&destroy_projectile_message);
+                },
+                Event::HealthChanged { player_i, health } => {
+                    let update_health_message = ServerMessage::UpdateHealth { health: *health };
+                    game_match.send_to(*player_i, &update_health_message);
+                },
+                Event::PlayerKilled { player_i, killer_i } => {
+                    info!(player_i, killer_i, "Killing player");
+
+                    let player_kill_message = ServerMessage::KillPlayer { player_i: *player_i };
+                    game_match.broadcast(&player_kill_message);
+                },
+                Event::PlayerRespawned { player_i, x, y } => {
+                    let respawn_player_message = ServerMessage::RespawnPlayer { player_i: *player_i, x: *x, y: *y };
+                    game_match.broadcast(&respawn_player_message);
+                },
+                Event::WeaponSpawned { weapon } => {
+                    let weapon_spawn_message = ServerMessage::WeaponSpawn {
+                        weapon_id: weapon.id,
+                        weapon_type: weapon.weapon_type,
+                        x: weapon.x,
+                        y: weapon.y,
+                    };
+                    game_match.broadcast(&weapon_spawn_message);
+                },
+                // Nothing to send, the match ends and the game over message goes out after
+                Event::GameWon { .. } => (),
             }
-
-            player.ticks_since_last_fire = 0;
-
-            player.ammo -= 1;
         }
     }
 
-    pub async fn handle_projectiles(
+    pub fn handle_late_join(
         game_match: &mut GameMatch,
-        players: &mut Vec<Player>,
-        projectiles: &mut Vec<Projectile>,
-        map: &mut RgbaImage,
+        simulation: &mut Simulation,
+        client_i: usize,
+        original_map: &RgbaImage,
     ) {
-        // Track destroyed projectiles as we iterate
-        let mut destroyed_projectiles: usize = 0;
-
-        'per_projectile: for projectile_i in 0..projectiles.len() {
-            // We will be popping projectiles off the Vec as we go so adjust based on that
-            let projectile_i = projectile_i - destroyed_projectiles;
-
-            if projectile_i >= projectiles.len() {
-                break;
-            }
+        let name = game_match.clients[client_i].name.clone();
 
-            // Too much time passes during each tick to do good collisions, so interpolate
-            for _ in 0..(Self::PROJECTILE_INTERP_RATE as usize) {
-                VelSystem::update_projectile(
-                    &mut projectiles[projectile_i],
-                    (ServerTick::SERVER_TICK / Self::PROJECTILE_INTERP_RATE) as f32,
-                );
+        let player_i = simulation.add_player(name.clone());
+        let (x, y) = (simulation.players[player_i].x, simulation.players[player_i].y);
 
-                // If the projectile fell off the screen destroy it without an explosion
-                if CollisionSystem::projectile_oob(&projectiles[projectile_i], map) {
-                    let destroy_projectile_message = Proto::tct_destroy_projectile(projectile_i);
+        info!(player_i = client_i, "Player joined mid-game");
 
-                    for client in &mut game_match.clients {
-                        let websocket_send = &mut client.websocket_send;
-                        let _ = websocket_send
-                            .send(Message::binary(destroy_projectile_message.clone()))
-                            .await;
-                    }
+        // The newcomer needs everyone's names before the game state makes sense. Clients that
+        // joined after it are announced with their own player joined message
+        let mut names = game_match.names();
+        names.truncate(client_i + 1);
 
-                    println!("Projectile out of bounds");
+        let player_list_message = ServerMessage::PlayerList { names };
+        game_match.send_to(client_i, &player_list_message);
 
-                    projectiles.remove(projectile_i);
-                    destroyed_projectiles += 1;
-
-                    continue 'per_projectile;
-                }
+        Self::send_game_state(game_match, simulation, client_i, original_map);
 
-                if CollisionSystem::projectile_collide_map(&projectiles[projectile_i], map) {
-                    Self::handle_projectile_damage(
-                        game_match,
-                        players,
-                        &mut projectiles[projectile_i],
-                        projectile_i,
-                        map,
-                    )
-                    .await;
-
-                    projectiles.remove(projectile_i);
-                    destroyed_projectiles += 1;
-                    continue 'per_projectile;
-                }
+        let player_joined_message = ServerMessage::PlayerJoined { player_i: client_i, x, y, name };
 
-                for player_i in 0..players.len() {
-                    if CollisionSystem::point_collide_player(
-                        projectiles[projectile_i].x,
-                        projectiles[projectile_i].y,
-                        &players[player_i],
-                    ) {
-                        Self::handle_projectile_damage(
-                            game_match,
-                            players,
-                            &mut projectiles[projectile_i],
-                            projectile_i,
-                            map,
-                        )
-                        .await;
-
-                        projectiles.remove(projectile_i);
-                        destroyed_projectiles += 1;
-                        continue 'per_projectile;
-                    }
-                }
-            }
-        }
+        game_match.broadcast_except(client_i, &player_joined_message);
     }
 
-    pub async fn handle_projectile_damage(
+    pub fn send_game_state(
         game_match: &mut GameMatch,
-        players: &mut Vec<Player>,
-        projectile: &mut Projectile,
-        projectile_i: usize,
-        map: &mut RgbaImage,
+        simulation: &Simulation,
+        player_i: usize,
+        original_map: &RgbaImage,
     ) {
-        let destroy_pixels = projectile.draw_explosion();
-
-        // Keep track of which players were damaged so we can tell them their new health
-        let mut players_health_affected: Vec<usize> = Vec::new();
-
-        for destroy_pixel in destroy_pixels {
-            let (destroy_pixel_x, destroy_pixel_y) = destroy_pixel;
-
-            for player_i in 0..players.len() {
-                if CollisionSystem::point_collide_player(
-                    destroy_pixel_x as f32,
-                    destroy_pixel_y as f32,
-                    &players[player_i],
-                ) {
-                    if !players[player_i].alive {
-                        continue;
-                    }
-
-                    let projectile_type = projectile.projectile_type;
-                    let projectile_damage = Projectile::PROJECTILE_TYPES[projectile_type].damage;
-
-                    players[player_i].health -= projectile_damage;
-
-                    if !players_health_affected.contains(&player_i) {
-                        players_health_affected.push(player_i);
-                    }
-
-                    if players[player_i].health < 0.0 {
-                        Self::handle_player_death(
-                            game_match,
-                            players,
-                            player_i,
-                            Some(projectile.owner),
-                        )
-                        .await;
-                        continue;
-                    }
-                }
-            }
-
-            if map.get_pixel(destroy_pixel_x as u32, destroy_pixel_y as u32)[3] > 0 {
-                if destroy_pixel_x < 0 || destroy_pixel_x >= map.width() as i32 {
-                    continue;
-                } else if destroy_pixel_y < 0 || destroy_pixel_y >= map.height() as i32 {
-                    continue;
-                }
+        let state_messages = Self::game_state_messages(game_match, simulation, player_i, original_map);
 
-                map.put_pixel(
-                    destroy_pixel_x as u32,
-                    destroy_pixel_y as u32,
-                    Self::CLEAR_PIXEL.clone(),
-                );
-            }
-        }
-
-        let projectile_explosion_message = Proto::tct_projectile_explosion(&projectile);
-        let destroy_projectile_message = Proto::tct_destroy_projectile(projectile_i);
-
-        for client_i in 0..game_match.clients.len() {
-            let websocket_send = &mut game_match.clients[client_i].websocket_send;
-            let _ = websocket_send
-                .send(Message::binary(projectile_explosion_message.clone()))
-                .await;
-            let _ = websocket_send
-                .send(Message::binary(destroy_projectile_message.clone()))
-                .await;
-
-            if players_health_affected.contains(&client_i) {
-                let update_health_message = Proto::tct_update_health(players[client_i].health);
-
-                let _ = websocket_send
-                    .send(Message::binary(update_health_message.clone()))
-                    .await;
-            }
+        for message in &state_messages {
+            game_match.send_to(player_i, message);
         }
     }
 
-    pub async fn handle_player_death(
-        game_match: &mut GameMatch,
-        players: &mut Vec<Player>,
-        killed_player_i: usize,
-        projectile_owner: Option<usize>,
-    ) {
-        println!("Killing player {}", killed_player_i);
+    // Who is in the lobby and who is ready, for anyone whose view of it is out of date
+    fn lobby_state_messages(game_match: &GameMatch, players: &Vec<Player>) -> Vec<ServerMessage> {
+        let mut messages = vec![ServerMessage::PlayerList { names: game_match.names() }];
 
-        if projectile_owner.is_some() && projectile_owner.unwrap() != killed_player_i {
-            players[projectile_owner.unwrap()].kills += 1;
+        for player_i in 0..players.len() {
+            messages.push(ServerMessage::ToggleReady { player_i, ready: players[player_i].ready });
         }
 
-        players[killed_player_i].kill();
-
-        let player_kill_message = Proto::tct_kill_player(killed_player_i);
-
-        for client in &mut game_match.clients {
-            let websocket_send = &mut client.websocket_send;
-            let _ = websocket_send
-                .send(Message::binary(player_kill_message.clone()))
-                .await;
-        }
+        return messages;
     }
 
-    pub async fn handle_player_respawns(
-        game_match: &mut GameMatch,
-        players: &mut Vec<Player>,
-        map: &mut RgbaImage,
-    ) {
-        for player_i in 0..players.len() {
-            let player = &mut players[player_i];
-
-            if player.alive {
-                continue;
-            }
+    // Everything a client needs to pick up a game in progress, player_i is Proto::SPECTATOR
+    // for spectators
+    fn game_state_messages(
+        game_match: &GameMatch,
+        simulation: &Simulation,
+        player_i: usize,
+        original_map: &RgbaImage,
+    ) -> Vec<ServerMessage> {
+        // Clients that missed the lobby don't know the rules yet
+        let match_settings_message = ServerMessage::MatchSettings(game_match.settings.clone());
+        let game_state_message = ServerMessage::GameState {
+            player_i,
+            players: simulation.players.clone(),
+            weapons: simulation.ground_weapons.clone(),
+            projectiles: simulation.projectiles.clone(),
+        };
+        let terrain_message = ServerMessage::Terrain {
+            runs: Self::destroyed_terrain(&simulation.map, original_map),
+        };
+
+        return vec![match_settings_message, game_state_message, terrain_message];
+    }
 
-            player.time_to_respawn -= ServerTick::SERVER_TICK;
+    // Spectators can only leave, anything else they send is dropped
+    pub fn handle_spectators(game_match: &mut GameMatch) {
+        let mut departed: Vec<usize> = Vec::new();
 
-            if player.time_to_respawn > 0 {
+        for spectator_i in 0..game_match.spectators.len() {
+            // Spectators have nothing to resume, one that fell too far behind is gone for good
+            if !game_match.spectators[spectator_i].connected {
+                info!(spectator_i, "Spectator fell behind");
+                departed.push(spectator_i);
                 continue;
             }
 
-            let (x, y) = Self::get_rand_pos(&map);
-            player.respawn(x, y);
-
-            let respawn_player_message = Proto::tct_respawn_player(player_i, x, y);
+            loop {
+                let message = game_match.spectators[spectator_i].next_message();
 
-            for client in &mut game_match.clients {
-                let websocket_send = &mut client.websocket_send;
-                let _ = websocket_send
-                    .send(Message::binary(respawn_player_message.clone()))
-                    .await;
-            }
-        }
-    }
+                // Break if no messages in the queue
+                if message.is_none() {
+                    break;
+                }
 
-    pub async fn handle_weapon_spawns(
-        game_match: &mut GameMatch,
-        ground_weapons: &mut Vec<Weapon>,
-        map: &RgbaImage,
-    ) {
-        for _ in 0..Self::SPAWN_WEAPONS_COUNT {
-            if ground_weapons.len() > 255 {
-                return;
-            }
+                let message = message.unwrap();
 
-            let (x, y) = Self::get_rand_pos(&map);
-            let weapon_type = rand::thread_rng().gen_range(0..Weapon::WEAPON_TYPES.len());
+                // Break if the socket closed
+                if message.is_none() {
+                    info!(spectator_i, "Spectator disconnected");
+                    departed.push(spectator_i);
+                    break;
+                }
 
-            let weapon_type = weapon_type as usize;
+                let message = message.unwrap();
 
-            let new_weapon = Weapon::new(weapon_type, x, y);
+                // Continue if there was a websocket error
+                if message.is_err() {
+                    warn!(spectator_i, "{}", message.unwrap_err());
+                    continue;
+                }
 
-            ground_weapons.push(new_weapon);
+                let message = message.unwrap().into_bytes();
 
-            let weapon_spawn_message = Proto::tct_weapon_spawn(weapon_type, x, y);
+                METRICS.message_received(&message);
 
-            for client in &mut game_match.clients {
-                let websocket_send = &mut client.websocket_send;
-                let _ = websocket_send
-                    .send(Message::binary(weapon_spawn_message.clone()))
-                    .await;
+                if ClientMessage::decode(&message) == Ok(ClientMessage::LeaveMatch) {
+                    info!(spectator_i, "Spectator left");
+                    departed.push(spectator_i);
+                    break;
+                }
             }
         }
+
+        // Remove from the back so the remaining indices stay valid
+        for spectator_i in departed.into_iter().rev() {
+            game_match.remove_spectator(spectator_i);
+        }
     }
 
-    fn get_rand_pos(map: &RgbaImage) -> (f32, f32) {
-        let mut rng = rand::thread_rng();
-        loop {
-            let x = rng.gen_range(0..map.width() as usize);
-            let y = rng.gen_range(0..map.height() as usize);
-            let x = x as u32;
-            let mut y = y as u32;
-            while y < map.height() {
-                // Find the ground
-                if map.get_pixel(x, y)[3] > 0 {
-                    // Move up to find the space right above the ground
-                    while map.get_pixel(x, y)[3] > 0 {
-                        y -= 1;
+    // Returns (x, y, length) for each horizontal run of pixels that have been blown up
+    fn destroyed_terrain(map: &RgbaImage, original_map: &RgbaImage) -> Vec<(i32, i32, i32)> {
+        let mut runs: Vec<(i32, i32, i32)> = Vec::new();
+
+        for y in 0..map.height() {
+            let mut run_start: Option<u32> = None;
+
+            for x in 0..map.width() {
+                let destroyed = original_map.get_pixel(x, y)[3] > 0 && map.get_pixel(x, y)[3] == 0;
+
+                match (destroyed, run_start) {
+                    (true, None) => run_start = Some(x),
+                    (false, Some(start)) => {
+                        runs.push((start as i32, y as i32, (x - start) as i32));
+                        run_start = None;
                     }
-                    // Move it up to half the character's height
-                    y -= Player::PLAYER_HEIGHT / 2;
-                    return (x as f32, y as f32);
+                    _ => (),
                 }
-                y += 1;
             }
-            // If a location wasn't found that means the coordinates didn't have any ground below
-            // them, so start over with new coordinates
+
+            if let Some(start) = run_start {
+                runs.push((start as i32, y as i32, (map.width() - start) as i32));
+            }
         }
+
+        return runs;
     }
 }
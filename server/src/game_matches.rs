@@ -1,103 +1,147 @@
 pub mod game_match;
 
 use rand::{distributions::Alphanumeric, Rng};
-use futures::stream::{SplitSink, SplitStream};
+use std::collections::HashMap;
+use std::sync::Arc;
 use tokio::sync::RwLock;
-use warp::ws::{WebSocket, Message};
 use tokio::time::{sleep, Duration};
 
+use pixel_demolition_common::proto::Proto;
+
 use game_match::GameMatch;
+use crate::config::Config;
+use crate::engine::Engine;
+use crate::metrics::METRICS;
+use crate::shutdown::Shutdown;
 
 pub struct GameMatches {
-    pub c: Vec<RwLock<GameMatch>>,
+    // Keyed by match code. Only held long enough to look a match up or add and remove one, never
+    // while a match itself is locked
+    c: std::sync::RwLock<HashMap<String, Arc<RwLock<GameMatch>>>>,
+    // Which match each resume token was handed out by, so resuming only locks that match
+    resume_codes: std::sync::RwLock<HashMap<[u8; Proto::RESUME_TOKEN_LEN], String>>,
+    pub config: Config,
+    pub shutdown: Shutdown,
 }
 
 impl GameMatches {
     pub const MAX_MATCHES: usize = 100;
 
-    pub fn new() -> GameMatches {
-        let mut c:Vec<RwLock<GameMatch>> = Vec::new();
-
-        for _ in 0..Self::MAX_MATCHES {
-            let new_game_match = GameMatch::new();
-            let new_game_match = RwLock::new(new_game_match);
-
-            c.push(new_game_match);
-        }
+    pub fn new(config: Config) -> GameMatches {
+        // Matches are created on demand, the unused count is how many more can be
+        METRICS.add_matches(config.max_matches);
 
         GameMatches {
-            c
+            c: std::sync::RwLock::new(HashMap::new()),
+            resume_codes: std::sync::RwLock::new(HashMap::new()),
+            config,
+            shutdown: Shutdown::new(),
         }
     }
 
-    pub async fn index_by_code(
-        &self,
-        code: &String, 
-    ) -> Result<usize, &'static str> {
-        for i in 0..Self::MAX_MATCHES {
-            let game_match = self.c[i].read().await;
-            if code == &game_match.code {
-                return Ok(i as usize);
-            }
-        }
-        return Err("No match");
+    pub fn get(&self, code: &String) -> Option<Arc<RwLock<GameMatch>>> {
+        return self.c.read().unwrap().get(code).cloned();
     }
 
-    pub async fn push_client(
-        &self,
-        game_match_i: usize,
-        websocket_send: SplitSink<WebSocket, Message>,
-        websocket_recv: SplitStream<WebSocket>,
-        name: String,
-    ) {
-        let mut game_match = self.c[game_match_i].write().await;
-        game_match.push_client(websocket_send, websocket_recv, name);
+    // Handed out so callers can go through the matches without holding up the registry
+    pub fn all(&self) -> Vec<Arc<RwLock<GameMatch>>> {
+        return self.c.read().unwrap().values().cloned().collect();
     }
 
-    pub async fn activate(&self) -> Result<usize, &'static str> {
-        // Find unused match in vector
-        let mut reserved = Self::MAX_MATCHES + 1;
-
-        for i in 0..Self::MAX_MATCHES {
-            let mut game_match = self.c[i].write().await;
+    // Returns the new match's code along with the match, which starts out in its lobby
+    pub fn activate(&self, public: bool) -> Result<(String, Arc<RwLock<GameMatch>>), &'static str> {
+        let mut c = self.c.write().unwrap();
 
-            if game_match.state == GameMatch::UNUSED {
-                game_match.state = GameMatch::LOBBY;
-                reserved = i;
-                break;
-            }
-        }
-
-        if reserved > Self::MAX_MATCHES {
+        if c.len() >= self.config.max_matches {
             return Err("Match limit reached");
         }
 
-        loop {
+        let code = loop {
             let new_code: String = rand::thread_rng()
                 .sample_iter(&Alphanumeric)
-                .take(GameMatch::GAME_CODE_LEN as usize)
+                .take(Proto::GAME_CODE_LEN)
                 .map(char::from)
                 .collect();
 
             let new_code = new_code.to_uppercase();
 
-            let mut existing_found = false;
-
-            for i in 0..Self::MAX_MATCHES {
-                if new_code == self.c[i].read().await.code {
-                    existing_found = true;
-                    break;
-                }
+            if !c.contains_key(&new_code) {
+                break new_code;
             }
-            if !existing_found {
-                let mut game_match = self.c[reserved].write().await;
-                game_match.code = new_code;
+        };
+
+        let mut game_match = GameMatch::new();
+        game_match.state = GameMatch::LOBBY;
+        game_match.code = code.clone();
+        game_match.public = public;
+        game_match.settings = self.config.match_settings();
+        METRICS.match_state_changed(GameMatch::UNUSED, GameMatch::LOBBY);
 
-                return Ok(reserved);
+        let game_match = Arc::new(RwLock::new(game_match));
+        c.insert(code.clone(), game_match.clone());
+
+        return Ok((code, game_match));
+    }
+
+    // Called once the engine has released the match
+    pub fn remove(&self, code: &String) {
+        self.c.write().unwrap().remove(code);
+        self.resume_codes.write().unwrap().retain(|_, token_code| token_code != code);
+    }
+
+    pub fn add_resume_token(&self, resume_token: [u8; Proto::RESUME_TOKEN_LEN], code: &String) {
+        self.resume_codes.write().unwrap().insert(resume_token, code.clone());
+    }
+
+    // Tokens of players that have since left stay until their match is removed, the match
+    // itself has the final say on whether a token can still resume
+    pub fn resume_code(&self, resume_token: &[u8; Proto::RESUME_TOKEN_LEN]) -> Option<String> {
+        return self.resume_codes.read().unwrap().get(resume_token).cloned();
+    }
+
+    // Tell every match the server is going down, wait for them to wrap up and then release
+    // anything that is still holding on
+    pub async fn drain(&self) {
+        self.shutdown.start();
+
+        // Matches get the grace period plus a few seconds to send their final stats
+        let deadline = self.config.shutdown_grace + 5;
+
+        for _ in 0..(deadline * 10) {
+            if self.all_unused().await {
+                break;
             }
 
-            sleep(Duration::from_millis(30)).await;
+            sleep(Duration::from_millis(100)).await;
+        }
+
+        for game_match in self.all() {
+            let result = tokio::time::timeout(
+                Duration::from_secs(1),
+                Engine::release_match(&game_match),
+            ).await;
+
+            if result.is_err() {
+                tracing::warn!("Unable to release match during shutdown");
+            }
         }
     }
 
+    // Matches only hold their lock while a tick runs, so the state is what says whether one is
+    // still in use. One that can't be read in time is treated as busy so a stuck match can't
+    // hold up the drain past its deadline
+    async fn all_unused(&self) -> bool {
+        for game_match in self.all() {
+            match tokio::time::timeout(Duration::from_millis(100), game_match.read()).await {
+                Ok(game_match) => {
+                    if game_match.state != GameMatch::UNUSED {
+                        return false;
+                    }
+                },
+                Err(_) => return false,
+            }
+        }
+
+        return true;
+    }
 }
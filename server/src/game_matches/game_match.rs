@@ -1,17 +1,42 @@
 use warp::ws::WebSocket;
 use futures::stream::{SplitSink, SplitStream};
+use tracing::warn;
 use warp::ws::Message;
 
+use pixel_demolition_common::match_settings::MatchSettings;
+use pixel_demolition_common::proto::Proto;
+use pixel_demolition_common::replay::Replay;
+use pixel_demolition_common::server_message::ServerMessage;
+
 use crate::client::Client;
+use crate::metrics::METRICS;
+use crate::replay_recorder::ReplayRecorder;
 
 pub struct GameMatch {
     pub state: u8,
     pub code: String,
     pub clients: Vec<Client>,
+    // Watch the match and get every broadcast but have no player
+    pub spectators: Vec<Client>,
+    // Public matches are listed by the match browser, private ones can only be joined by code
+    pub public: bool,
+    // Index of the client running the lobby, starts as the creator
+    pub host: usize,
+    // Set by the host to stop new players joining
+    pub locked: bool,
+    pub settings: MatchSettings,
+    // Only set while a game is being recorded
+    pub replay: Option<ReplayRecorder>,
 }
 
 impl GameMatch {
-    pub const GAME_CODE_LEN:i32 = 6;
+    // The client only has colors for 4 players
+    pub const MAX_PLAYERS: usize = 4;
+
+    // The host can't force a game to start with fewer players
+    pub const MIN_PLAYERS: usize = 2;
+
+    pub const MAX_SPECTATORS: usize = 8;
 
     pub const UNUSED:u8 = 0;
     pub const LOBBY:u8 = 1;
@@ -22,6 +47,12 @@ impl GameMatch {
             state: Self::UNUSED,
             code: String::new(),
             clients: Vec::new(),
+            spectators: Vec::new(),
+            public: false,
+            host: 0,
+            locked: false,
+            settings: MatchSettings::new(),
+            replay: None,
         }
     }
 
@@ -29,10 +60,189 @@ impl GameMatch {
         &mut self,
         websocket_send: SplitSink<WebSocket, Message>,
         websocket_recv: SplitStream<WebSocket>,
-        name: String
+        name: String,
+        resume_token: [u8; Proto::RESUME_TOKEN_LEN],
     ) {
-        let new_client = Client::new(websocket_send, websocket_recv, name);
+        let new_client = Client::new(websocket_send, websocket_recv, name, resume_token);
 
         self.clients.push(new_client);
     }
+
+    pub fn push_spectator(
+        &mut self,
+        websocket_send: SplitSink<WebSocket, Message>,
+        websocket_recv: SplitStream<WebSocket>,
+        name: String,
+    ) {
+        let mut new_spectator = Client::new(
+            websocket_send,
+            websocket_recv,
+            name,
+            [0; Proto::RESUME_TOKEN_LEN],
+        );
+
+        // Picked up by the engine like a resumed client so it gets sent the current state
+        new_spectator.resumed = true;
+
+        self.spectators.push(new_spectator);
+    }
+
+    pub fn remove_spectator(&mut self, spectator_i: usize) {
+        let mut spectator = self.spectators.remove(spectator_i);
+
+        spectator.flush();
+        spectator.close();
+
+        if spectator.connected {
+            METRICS.client_disconnected();
+        }
+    }
+
+    // Closes the client's socket and keeps the host pointing at the same client, or hands it
+    // to the longest waiting client if the host was the one removed
+    pub fn remove_client(&mut self, client_i: usize) {
+        let mut client = self.clients.remove(client_i);
+
+        client.flush();
+        client.close();
+
+        if client.connected {
+            METRICS.client_disconnected();
+        }
+
+        if client_i < self.host {
+            self.host -= 1;
+        } else if client_i == self.host {
+            self.host = 0;
+        }
+    }
+
+    // Players can't be removed mid-game without moving everyone's index, so the client is only
+    // disconnected and the engine takes the player out of play once it sees they expired
+    pub fn kick_client(&mut self, client_i: usize, reason: u8) {
+        let kicked_message = ServerMessage::Kicked { reason };
+
+        let client = &mut self.clients[client_i];
+        client.send(&kicked_message);
+        client.flush();
+        client.close();
+
+        if client.connected {
+            METRICS.client_disconnected();
+        }
+
+        client.connected = false;
+        client.kicked = true;
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+
+        for client in &self.clients {
+            names.push(client.name.clone());
+        }
+
+        return names;
+    }
+
+    pub fn find_resumable(
+        &self,
+        resume_token: &[u8; Proto::RESUME_TOKEN_LEN],
+        grace_ticks: i32,
+    ) -> Option<usize> {
+        for client_i in 0..self.clients.len() {
+            let client = &self.clients[client_i];
+
+            if &client.resume_token == resume_token && !client.expired(grace_ticks) {
+                return Some(client_i);
+            }
+        }
+
+        return None;
+    }
+
+    pub fn send_to(&mut self, client_i: usize, message: &ServerMessage) {
+        let message = message.encode();
+
+        if let Some(replay) = &mut self.replay {
+            replay.record(client_i as u8, &message);
+        }
+
+        self.clients[client_i].send_encoded(&message);
+    }
+
+    // Spectator only messages aren't recorded, they repeat state that is already in the replay
+    pub fn broadcast(&mut self, message: &ServerMessage) {
+        let message = message.encode();
+
+        if let Some(replay) = &mut self.replay {
+            replay.record(Replay::ALL, &message);
+        }
+
+        for client in &mut self.clients {
+            client.send_encoded(&message);
+        }
+
+        for spectator in &mut self.spectators {
+            spectator.send_encoded(&message);
+        }
+    }
+
+    // Skips the player an update came from since they already applied it locally. Recorded as
+    // going to everyone so playback has every player's updates
+    pub fn broadcast_except(&mut self, player_i: usize, message: &ServerMessage) {
+        let message = message.encode();
+
+        if let Some(replay) = &mut self.replay {
+            replay.record(Replay::ALL, &message);
+        }
+
+        for client_i in 0..self.clients.len() {
+            if client_i == player_i {
+                continue;
+            }
+
+            self.clients[client_i].send_encoded(&message);
+        }
+
+        for spectator in &mut self.spectators {
+            spectator.send_encoded(&message);
+        }
+    }
+
+    // Every client gets their own index so they know whether they are the host
+    pub fn broadcast_lobby_state(&mut self) {
+        let host = self.host;
+        let locked = self.locked;
+
+        for client_i in 0..self.clients.len() {
+            let lobby_state_message = ServerMessage::LobbyState { host_i: host, player_i: client_i, locked };
+            self.clients[client_i].send(&lobby_state_message);
+        }
+
+        let lobby_state_message = ServerMessage::LobbyState {
+            host_i: host,
+            player_i: Proto::SPECTATOR as usize,
+            locked,
+        };
+
+        for spectator in &mut self.spectators {
+            spectator.send(&lobby_state_message);
+        }
+    }
+
+    // Called once at the end of every tick
+    pub fn flush(&mut self) {
+        for player_i in 0..self.clients.len() {
+            if !self.clients[player_i].flush() {
+                warn!(player_i, "Client fell too far behind, disconnecting");
+            }
+        }
+
+        for spectator_i in 0..self.spectators.len() {
+            if !self.spectators[spectator_i].flush() {
+                warn!(spectator_i, "Spectator fell too far behind, disconnecting");
+            }
+        }
+    }
 }
@@ -1,12 +1,20 @@
 use futures::{SinkExt, StreamExt};
+use futures::stream::{SplitSink, SplitStream};
 use std::sync::Arc;
+use tracing::{debug, info, info_span, warn, Instrument};
+use uuid::Uuid;
+use warp::http::StatusCode;
 use warp::ws::{Message, WebSocket, Ws};
 
+use pixel_demolition_common::client_message::ClientMessage;
 use pixel_demolition_common::proto::Proto;
+use pixel_demolition_common::server_message::ServerMessage;
 
 use crate::game_matches::game_match::GameMatch;
 use crate::game_matches::GameMatches;
 use crate::engine::Engine;
+use crate::match_browser::MatchBrowser;
+use crate::metrics::METRICS;
 
 pub struct InitWSHandler {}
 
@@ -14,133 +22,304 @@ impl InitWSHandler {
     pub async fn handle(
         ws: Ws,
         game_matches: Arc<GameMatches>,
-    ) -> Result<impl warp::Reply, std::convert::Infallible> {
-        println!("Upgrading websocket");
-        Ok(ws.on_upgrade(move |socket| Self::handle_upgraded(socket, game_matches)))
+    ) -> Result<Box<dyn warp::Reply>, std::convert::Infallible> {
+        if game_matches.shutdown.is_started() {
+            info!("Refusing websocket during shutdown");
+            return Ok(Box::new(StatusCode::SERVICE_UNAVAILABLE));
+        }
+
+        debug!("Upgrading websocket");
+        Ok(Box::new(ws.on_upgrade(move |socket| Self::handle_upgraded(socket, game_matches))))
     }
 
     pub async fn handle_upgraded(websocket: WebSocket, game_matches: Arc<GameMatches>) {
         let (mut websocket_send, mut websocket_recv) = websocket.split();
 
+        METRICS.client_connected();
+
+        if !Self::handshake(&mut websocket_send, &mut websocket_recv).await {
+            METRICS.client_disconnected();
+            return;
+        }
+
         // Loop until user joins an existing match or creates a new one
         while let Some(message) = websocket_recv.next().await {
-            println!("Received message");
             if message.is_err() {
-                eprintln!("{}", message.unwrap_err());
+                warn!("{}", message.unwrap_err());
                 continue;
             }
 
             let message = message.unwrap().into_bytes();
 
-            let message_type = Proto::get_type(&message);
+            METRICS.message_received(&message);
 
-            if message_type.is_err() {
+            let message = ClientMessage::decode(&message);
+
+            if message.is_err() {
+                warn!("{}", message.unwrap_err());
                 continue;
             }
 
-            match message_type.unwrap() {
-                Proto::TST_JOIN_EXISTING => {
-                    let join_message =
-                        Proto::parse_tst_join_existing(&message, GameMatch::GAME_CODE_LEN);
+            let message = message.unwrap();
+            let spectate = matches!(message, ClientMessage::SpectateExisting { .. });
 
-                    if join_message.is_err() {
-                        eprintln!("{}", join_message.unwrap_err());
+            match message {
+                ClientMessage::JoinExisting { code, name }
+                | ClientMessage::SpectateExisting { code, name } => {
+                    if game_matches.shutdown.is_started() {
+                        let join_result_message = ServerMessage::JoinExistingResult {
+                            status: Proto::JOIN_EXISTING_RESULT_SERVER_ERROR,
+                            resume_token: None,
+                        };
+
+                        Self::send(&mut websocket_send, join_result_message).await;
                         continue;
                     }
 
-                    let (code, name) = join_message.unwrap();
+                    debug!(code = %code, "Looking for match");
 
-                    println!("Looking for match {}", code);
+                    match game_matches.get(&code) {
+                        Some(game_match) => {
+                            // Hold the lock from the checks until the client is pushed so the
+                            // match can't be released or filled in between. Matches that are
+                            // already playing pick the client up on their next tick. Nothing
+                            // waits on the socket while the lock is held
+                            let mut game_match = game_match.write().await;
 
-                    let game_match_i = game_matches.index_by_code(&code).await;
+                            let status = match game_match.state {
+                                GameMatch::UNUSED => Some(Proto::JOIN_EXISTING_RESULT_BAD_CODE),
+                                _ if spectate => {
+                                    match game_match.spectators.len() >= GameMatch::MAX_SPECTATORS {
+                                        true => Some(Proto::JOIN_EXISTING_RESULT_FULL),
+                                        false => None,
+                                    }
+                                }
+                                _ if game_match.clients.len() >= GameMatch::MAX_PLAYERS => {
+                                    Some(Proto::JOIN_EXISTING_RESULT_FULL)
+                                }
+                                GameMatch::LOBBY if game_match.locked => {
+                                    Some(Proto::JOIN_EXISTING_RESULT_LOCKED)
+                                }
+                                _ => None,
+                            };
 
-                    match game_match_i {
-                        Ok(game_match_i) => {
-                            println!("Successfully joined match {}", &code);
-                            let join_result_message = Proto::tct_join_existing_result(
-                                Proto::JOIN_EXISTING_RESULT_SUCCESS,
-                            );
-                            
-                            let _ = websocket_send.send(Message::binary(join_result_message)).await;
+                            if let Some(status) = status {
+                                drop(game_match);
+
+                                let join_result_message = ServerMessage::JoinExistingResult {
+                                    status,
+                                    resume_token: None,
+                                };
+
+                                Self::send(&mut websocket_send, join_result_message).await;
+                                continue;
+                            }
+
+                            // From here the reply goes through the client's writer, queued ahead
+                            // of anything the engine sends it
+                            if spectate {
+                                let join_result_message = ServerMessage::JoinExistingResult {
+                                    status: Proto::JOIN_EXISTING_RESULT_SPECTATING,
+                                    resume_token: None,
+                                };
+
+                                game_match.push_spectator(websocket_send, websocket_recv, name);
+
+                                let spectator = game_match.spectators.last_mut().unwrap();
+                                spectator.send(&join_result_message);
+                                spectator.flush();
+
+                                info!(code = %code, "Spectating match");
+
+                                return;
+                            }
+
+                            let resume_token = Uuid::new_v4().into_bytes();
 
-                            game_matches.push_client(
-                                game_match_i,
+                            let join_result_message = ServerMessage::JoinExistingResult {
+                                status: Proto::JOIN_EXISTING_RESULT_SUCCESS,
+                                resume_token: Some(resume_token),
+                            };
+
+                            game_match.push_client(
                                 websocket_send,
                                 websocket_recv,
                                 name,
-                            ).await;
+                                resume_token,
+                            );
+
+                            let player_i = game_match.clients.len() - 1;
+
+                            game_match.clients[player_i].send(&join_result_message);
+                            game_match.clients[player_i].flush();
+
+                            game_matches.add_resume_token(resume_token, &code);
+
+                            info!(code = %code, player_i, "Joined match");
 
                             return;
                         }
-                        Err(err) => {
-                            println!("Unable to find match {}", err);
-                            let join_result_message = Proto::tct_join_existing_result(
-                                Proto::JOIN_EXISTING_RESULT_BAD_CODE,
-                            );
+                        None => {
+                            info!(code = %code, "Unable to find match");
+                            let join_result_message = ServerMessage::JoinExistingResult {
+                                status: Proto::JOIN_EXISTING_RESULT_BAD_CODE,
+                                resume_token: None,
+                            };
                             
-                            let _ = websocket_send.send(Message::binary(join_result_message)).await;
+                            Self::send(&mut websocket_send, join_result_message).await;
                         }
                     }
                 },
-                Proto::TST_CREATE_NEW => {
-                    println!("Received request to create new server");
-
-                    let name = Proto::parse_tst_create_new(&message);
+                ClientMessage::CreateNew { public, name } => {
+                    let result = match game_matches.shutdown.is_started() {
+                        true => Err("Server shutting down"),
+                        false => game_matches.activate(public),
+                    };
 
-                    if name.is_err() {
-                        eprintln!("{}", name.unwrap_err());
-                        continue;
-                    }
+                    match result {
+                        Ok((code, game_match)) => {
+                            let resume_token = Uuid::new_v4().into_bytes();
 
-                    let name = name.unwrap();
+                            let create_result_message = ServerMessage::CreateNewResult {
+                                status: Proto::CREATE_NEW_RESULT_SUCCESS,
+                                code: code.clone(),
+                                resume_token: Some(resume_token),
+                            };
 
-                    let result = game_matches.activate().await;
+                            Self::send(&mut websocket_send, create_result_message).await;
 
-                    match result {
-                        Ok(game_match_i) => {
-                            let code = &game_matches.c[game_match_i].read().await.code.clone();
-                            let create_result_message = Proto::tct_create_new_result(
-                                Proto::CREATE_NEW_RESULT_SUCCESS,
-                                code,
-                            );
+                            let player_i = {
+                                let mut game_match = game_match.write().await;
+                                game_match.push_client(websocket_send, websocket_recv, name, resume_token);
 
-                            println!("Created new server with code {}", code);
-                            
-                            let _ = websocket_send.send(Message::binary(create_result_message)).await;
+                                game_match.clients.len() - 1
+                            };
 
-                            println!("Sent message");
+                            game_matches.add_resume_token(resume_token, &code);
 
-                            game_matches.push_client(
-                                game_match_i,
-                                websocket_send,
-                                websocket_recv,
-                                name,
-                            ).await;
+                            info!(code = %code, player_i, public, "Created match");
 
-                            let game_matches_cloned = game_matches.clone();
+                            // Everything the engine logs is tagged with the match it belongs to
+                            let span = info_span!("match", code = %code);
 
-                            tokio::spawn(async move {
-                                println!("Retrieving match");
-                                let game_match = &game_matches_cloned.c[game_match_i];
-                                Engine::handle(game_match).await;
-                            });
-                            
-                            println!("Spawned thread");
+                            tokio::spawn(
+                                Engine::supervise(game_matches.clone(), game_match, code.clone())
+                                    .instrument(span),
+                            );
 
                             return;
                         },
                         Err(err) => {
-                            println!("Unable to create server {}", err);
-                            let create_result_message = Proto::tct_create_new_result(
-                                Proto::CREATE_NEW_RESULT_SERVER_ERROR,
-                                &String::new()
-                            );
-                            let _ = websocket_send.send(Message::binary(create_result_message)).await;
+                            warn!("Unable to create match: {}", err);
+                            let create_result_message = ServerMessage::CreateNewResult {
+                                status: Proto::CREATE_NEW_RESULT_SERVER_ERROR,
+                                code: String::new(),
+                                resume_token: None,
+                            };
+                            Self::send(&mut websocket_send, create_result_message).await;
+                        }
+                    }
+                },
+                ClientMessage::Resume { resume_token } => {
+                    let config = &game_matches.config;
+                    let grace_ticks = config.secs_to_ticks(config.resume_grace);
+
+                    let game_match = game_matches
+                        .resume_code(&resume_token)
+                        .and_then(|code| game_matches.get(&code));
+
+                    if let Some(game_match) = game_match {
+                        let mut game_match = game_match.write().await;
+
+                        let player_i = match game_match.state {
+                            GameMatch::UNUSED => None,
+                            _ => game_match.find_resumable(&resume_token, grace_ticks),
+                        };
+
+                        if let Some(player_i) = player_i {
+                            let resume_result_message = ServerMessage::ResumeResult {
+                                status: Proto::RESUME_RESULT_SUCCESS,
+                                player_i,
+                            };
+
+                            // Goes out through the new writer ahead of the state the engine
+                            // re-sends on its next tick
+                            let client = &mut game_match.clients[player_i];
+                            client.resume(websocket_send, websocket_recv);
+                            client.send(&resume_result_message);
+                            client.flush();
+
+                            info!(code = %game_match.code, player_i, "Resumed session");
+
+                            return;
                         }
                     }
+
+                    let resume_result_message = ServerMessage::ResumeResult {
+                        status: Proto::RESUME_RESULT_EXPIRED,
+                        player_i: 0,
+                    };
+
+                    Self::send(&mut websocket_send, resume_result_message).await;
+                },
+                ClientMessage::ListLobbies => {
+                    let mut lobbies: Vec<(String, String, usize)> = Vec::new();
+
+                    for listing in MatchBrowser::list(&game_matches) {
+                        lobbies.push((listing.code, listing.host, listing.players));
+                    }
+
+                    let lobby_list_message = ServerMessage::LobbyList { lobbies };
+
+                    Self::send(&mut websocket_send, lobby_list_message).await;
                 },
                 _ => (),
             }
         }
+
+        // The socket closed before it was handed to a match
+        METRICS.client_disconnected();
+    }
+
+    // Every connection has to start with a hello carrying the client's protocol version. Anything
+    // else, including clients from before the handshake existed, is told to reload and dropped
+    async fn handshake(
+        websocket_send: &mut SplitSink<WebSocket, Message>,
+        websocket_recv: &mut SplitStream<WebSocket>,
+    ) -> bool {
+        let message = match websocket_recv.next().await {
+            Some(Ok(message)) => message.into_bytes(),
+            _ => return false,
+        };
+
+        METRICS.message_received(&message);
+
+        let client_version = match ClientMessage::decode(&message) {
+            Ok(ClientMessage::Hello { protocol_version }) => Some(protocol_version),
+            _ => None,
+        };
+
+        if client_version == Some(Proto::PROTOCOL_VERSION) {
+            return true;
+        }
+
+        info!(client_version = ?client_version, "Rejecting client with a different protocol version");
+
+        let version_mismatch_message = ServerMessage::VersionMismatch {
+            protocol_version: Proto::PROTOCOL_VERSION,
+        };
+
+        Self::send(websocket_send, version_mismatch_message).await;
+        let _ = websocket_send.close().await;
+
+        return false;
+    }
+
+    async fn send(websocket_send: &mut SplitSink<WebSocket, Message>, message: ServerMessage) {
+        let message = message.encode();
+
+        METRICS.message_sent(&message);
+
+        let _ = websocket_send.send(Message::binary(message)).await;
     }
 }
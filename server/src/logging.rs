@@ -0,0 +1,25 @@
+use tracing_subscriber::EnvFilter;
+
+use crate::config::Config;
+
+pub struct Logging {}
+
+impl Logging {
+    pub const FORMATS: [&'static str; 2] = ["text", "json"];
+
+    // Matches run inside a "match" span carrying their code, so a single match can be filtered
+    // out of the log by it. Lines about one player carry player_i, or spectator_i for spectators
+    pub fn init(config: &Config) -> Result<(), String> {
+        let filter = EnvFilter::try_new(&config.log_level)
+            .map_err(|err| format!("Invalid log level {}: {}", config.log_level, err))?;
+
+        let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+        match config.log_format.as_str() {
+            "json" => builder.json().init(),
+            _ => builder.init(),
+        }
+
+        return Ok(());
+    }
+}
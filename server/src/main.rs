@@ -4,6 +4,15 @@ mod engine;
 mod game_matches; 
 mod init_ws_handler;
 mod etag;
+mod config;
+mod shutdown;
+mod match_browser;
+mod metrics;
+mod logging;
+mod replay_recorder;
+mod replay_files;
+mod validation;
+mod simulation;
 
 use warp;
 use warp::Filter;
@@ -13,27 +22,83 @@ use static_assets::StaticAssets;
 use game_matches::GameMatches;
 use init_ws_handler::InitWSHandler;
 use etag::Etag;
+use config::Config;
+use shutdown::Shutdown;
+use match_browser::MatchBrowser;
+use metrics::Metrics;
+use logging::Logging;
+use replay_files::ReplayFiles;
 
 
 #[tokio::main]
 async fn main() {
-    let game_matches = GameMatches::new();
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(err) = Logging::init(&config) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+
+    let bind_address = (config.bind_address, config.port);
+
+    let game_matches = GameMatches::new(config);
     let game_matches = Arc::new(game_matches);
 
-    
+    let ws_game_matches = game_matches.clone();
     let ws_route = warp::path("ws")
         .and(warp::ws())
-        .and(warp::any().map(move || game_matches.clone()))
+        .and(warp::any().map(move || ws_game_matches.clone()))
         .and_then(InitWSHandler::handle);
 
+    let match_browser_game_matches = game_matches.clone();
+    let match_browser_route = warp::path!("api" / "matches")
+        .and(warp::get())
+        .and(warp::any().map(move || match_browser_game_matches.clone()))
+        .and_then(MatchBrowser::handle);
+
+    let metrics_game_matches = game_matches.clone();
+    let metrics_route = warp::path!("metrics")
+        .and(warp::get())
+        .and(warp::any().map(move || metrics_game_matches.clone()))
+        .and_then(Metrics::handle);
+
+    let replay_files_game_matches = game_matches.clone();
+    let replay_files_route = warp::path!("replays" / String)
+        .and(warp::get())
+        .and(warp::any().map(move || replay_files_game_matches.clone()))
+        .and_then(ReplayFiles::handle);
+
     let static_assets_route = warp::any()
         .and(warp::path::full())
         .and(warp::header::headers_cloned())
         .and(warp::any().map(move || Etag::get()))
         .and_then(StaticAssets::handle);
 
-    let routes = ws_route.or(static_assets_route);
+    let routes = ws_route
+        .or(match_browser_route)
+        .or(metrics_route)
+        .or(replay_files_route)
+        .or(static_assets_route);
+
+    tracing::info!(address = %bind_address.0, port = bind_address.1, "Listening");
+
+    let shutdown_game_matches = game_matches.clone();
+    let (_, server) = warp::serve(routes).bind_with_graceful_shutdown(bind_address, async move {
+        Shutdown::wait_for_signal().await;
+        // Stop handing out new matches before the listener goes away
+        shutdown_game_matches.shutdown.start();
+    });
+
+    server.await;
 
-    warp::serve(routes).run(([127, 0, 0, 1], 8000)).await;
+    tracing::info!("Draining matches");
+    game_matches.drain().await;
+    tracing::info!("Shutdown complete");
 }
 
@@ -0,0 +1,88 @@
+use serde::Serialize;
+use std::sync::Arc;
+
+use pixel_demolition_common::match_settings::MatchSettings;
+
+use crate::game_matches::game_match::GameMatch;
+use crate::game_matches::GameMatches;
+
+#[derive(Serialize)]
+pub struct MatchListing {
+    pub code: String,
+    pub host: String,
+    pub players: usize,
+    pub settings: MatchListingSettings,
+}
+
+// Mirrors MatchSettings, which lives in common and has no serde
+#[derive(Serialize)]
+pub struct MatchListingSettings {
+    pub kills_to_win: i32,
+    // In ms
+    pub time_to_respawn: i32,
+    pub max_health: f32,
+    pub air_jumps: i32,
+    // In seconds
+    pub weapon_spawn_interval: i32,
+    pub weapon_spawn_count: usize,
+    // In ms
+    pub server_tick: i32,
+}
+
+impl MatchListingSettings {
+    pub fn new(settings: &MatchSettings) -> MatchListingSettings {
+        MatchListingSettings {
+            kills_to_win: settings.kills_to_win,
+            time_to_respawn: settings.time_to_respawn,
+            max_health: settings.max_health,
+            air_jumps: settings.air_jumps,
+            weapon_spawn_interval: settings.weapon_spawn_interval,
+            weapon_spawn_count: settings.weapon_spawn_count,
+            server_tick: settings.server_tick,
+        }
+    }
+}
+
+pub struct MatchBrowser {}
+
+impl MatchBrowser {
+    pub async fn handle(
+        game_matches: Arc<GameMatches>,
+    ) -> Result<impl warp::Reply, std::convert::Infallible> {
+        let listings = Self::list(&game_matches);
+
+        Ok(warp::reply::json(&listings))
+    }
+
+    // Returns every public match that is still waiting in its lobby
+    pub fn list(game_matches: &GameMatches) -> Vec<MatchListing> {
+        let mut listings: Vec<MatchListing> = Vec::new();
+
+        for game_match in game_matches.all() {
+            // Matches only hold their lock while a tick runs, so a lobby that is busy is only
+            // missing from this listing. Whether a match is a lobby comes from its state below
+            let game_match = match game_match.try_read() {
+                Ok(game_match) => game_match,
+                Err(_) => continue,
+            };
+
+            if game_match.state != GameMatch::LOBBY || !game_match.public || game_match.locked {
+                continue;
+            }
+
+            // The creator's client hasn't been pushed yet
+            if game_match.clients.len() < 1 {
+                continue;
+            }
+
+            listings.push(MatchListing {
+                code: game_match.code.clone(),
+                host: game_match.clients[game_match.host].name.clone(),
+                players: game_match.clients.len(),
+                settings: MatchListingSettings::new(&game_match.settings),
+            });
+        }
+
+        return listings;
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use warp::http::Response;
+
+use pixel_demolition_common::proto::Proto;
+
+use crate::game_matches::game_match::GameMatch;
+use crate::game_matches::GameMatches;
+
+pub static METRICS: Metrics = Metrics::new();
+
+struct MatchStats {
+    // In seconds
+    last_tick_duration: f64,
+    tick_overruns: u64,
+    projectiles: usize,
+}
+
+// Counters and gauges exposed in the Prometheus text format on /metrics
+pub struct Metrics {
+    matches: [AtomicI64; 3],
+    clients_connected: AtomicI64,
+    messages_received: [AtomicU64; 256],
+    messages_sent: [AtomicU64; 256],
+    outcomes: [AtomicU64; 6],
+    match_stats: Mutex<BTreeMap<String, MatchStats>>,
+}
+
+impl Metrics {
+    pub const OUTCOME_COMPLETED: usize = 0;
+    pub const OUTCOME_ABANDONED: usize = 1;
+    pub const OUTCOME_LOBBY_TIMEOUT: usize = 2;
+    pub const OUTCOME_GAME_TIMEOUT: usize = 3;
+    pub const OUTCOME_SHUTDOWN: usize = 4;
+    pub const OUTCOME_CRASHED: usize = 5;
+
+    const OUTCOME_NAMES: [&'static str; 6] = [
+        "completed",
+        "abandoned",
+        "lobby_timeout",
+        "game_timeout",
+        "shutdown",
+        "crashed",
+    ];
+
+    // Indexed by GameMatch state
+    const STATE_NAMES: [&'static str; 3] = ["unused", "lobby", "game"];
+
+    const fn new() -> Metrics {
+        // Atomics aren't Copy, so each array element gets its own const block
+        Metrics {
+            matches: [const { AtomicI64::new(0) }; 3],
+            clients_connected: AtomicI64::new(0),
+            messages_received: [const { AtomicU64::new(0) }; 256],
+            messages_sent: [const { AtomicU64::new(0) }; 256],
+            outcomes: [const { AtomicU64::new(0) }; 6],
+            match_stats: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub async fn handle(
+        game_matches: Arc<GameMatches>,
+    ) -> Result<impl warp::Reply, std::convert::Infallible> {
+        let body = METRICS.render(game_matches.config.server_tick);
+
+        Ok(Response::builder()
+            .status(200)
+            .header("Content-Type", "text/plain; version=0.0.4")
+            .body(body)
+            .unwrap())
+    }
+
+    pub fn add_matches(&self, count: usize) {
+        self.matches[GameMatch::UNUSED as usize].fetch_add(count as i64, Ordering::Relaxed);
+    }
+
+    pub fn match_state_changed(&self, old_state: u8, new_state: u8) {
+        if old_state == new_state {
+            return;
+        }
+
+        self.matches[old_state as usize].fetch_sub(1, Ordering::Relaxed);
+        self.matches[new_state as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn client_connected(&self) {
+        self.clients_connected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn client_disconnected(&self) {
+        self.clients_connected.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn message_received(&self, message: &Vec<u8>) {
+        if let Ok(message_type) = Proto::get_type(message) {
+            self.messages_received[message_type as usize].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn message_sent(&self, message: &Vec<u8>) {
+        if let Ok(message_type) = Proto::get_type(message) {
+            self.messages_sent[message_type as usize].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn match_outcome(&self, outcome: usize) {
+        self.outcomes[outcome].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_tick(&self, code: &String, duration: Duration, server_tick: i32, projectiles: usize) {
+        let mut match_stats = self.match_stats.lock().unwrap();
+
+        let stats = match_stats.entry(code.clone()).or_insert(MatchStats {
+            last_tick_duration: 0.0,
+            tick_overruns: 0,
+            projectiles: 0,
+        });
+
+        stats.last_tick_duration = duration.as_secs_f64();
+        stats.projectiles = projectiles;
+
+        if duration.as_millis() > server_tick as u128 {
+            stats.tick_overruns += 1;
+        }
+    }
+
+    pub fn remove_match(&self, code: &String) {
+        self.match_stats.lock().unwrap().remove(code);
+    }
+
+    pub fn render(&self, server_tick: i32) -> String {
+        let mut body = String::new();
+
+        let _ = writeln!(body, "# HELP pixel_demolition_matches Match slots by state");
+        let _ = writeln!(body, "# TYPE pixel_demolition_matches gauge");
+        for state in 0..Self::STATE_NAMES.len() {
+            let _ = writeln!(
+                body,
+                "pixel_demolition_matches{{state=\"{}\"}} {}",
+                Self::STATE_NAMES[state],
+                self.matches[state].load(Ordering::Relaxed),
+            );
+        }
+
+        let _ = writeln!(body, "# HELP pixel_demolition_clients_connected Open websockets");
+        let _ = writeln!(body, "# TYPE pixel_demolition_clients_connected gauge");
+        let _ = writeln!(
+            body,
+            "pixel_demolition_clients_connected {}",
+            self.clients_connected.load(Ordering::Relaxed),
+        );
+
+        for (name, help, counters) in [
+            ("messages_received_total", "Messages received by type", &self.messages_received),
+            ("messages_sent_total", "Messages sent by type", &self.messages_sent),
+        ] {
+            let _ = writeln!(body, "# HELP pixel_demolition_{} {}", name, help);
+            let _ = writeln!(body, "# TYPE pixel_demolition_{} counter", name);
+
+            for message_type in 0..counters.len() {
+                let count = counters[message_type].load(Ordering::Relaxed);
+
+                if count == 0 {
+                    continue;
+                }
+
+                let _ = writeln!(
+                    body,
+                    "pixel_demolition_{}{{type=\"{}\"}} {}",
+                    name,
+                    Proto::type_name(message_type as u8),
+                    count,
+                );
+            }
+        }
+
+        let _ = writeln!(body, "# HELP pixel_demolition_server_tick_seconds Target tick duration");
+        let _ = writeln!(body, "# TYPE pixel_demolition_server_tick_seconds gauge");
+        let _ = writeln!(
+            body,
+            "pixel_demolition_server_tick_seconds {}",
+            server_tick as f64 / 1000.0,
+        );
+
+        let match_stats = self.match_stats.lock().unwrap();
+
+        let _ = writeln!(
+            body,
+            "# HELP pixel_demolition_match_tick_duration_seconds Duration of the last tick",
+        );
+        let _ = writeln!(body, "# TYPE pixel_demolition_match_tick_duration_seconds gauge");
+        for (code, stats) in match_stats.iter() {
+            let _ = writeln!(
+                body,
+                "pixel_demolition_match_tick_duration_seconds{{match=\"{}\"}} {}",
+                code,
+                stats.last_tick_duration,
+            );
+        }
+
+        let _ = writeln!(
+            body,
+            "# HELP pixel_demolition_match_tick_overruns_total Ticks that took longer than the target",
+        );
+        let _ = writeln!(body, "# TYPE pixel_demolition_match_tick_overruns_total counter");
+        for (code, stats) in match_stats.iter() {
+            let _ = writeln!(
+                body,
+                "pixel_demolition_match_tick_overruns_total{{match=\"{}\"}} {}",
+                code,
+                stats.tick_overruns,
+            );
+        }
+
+        let _ = writeln!(body, "# HELP pixel_demolition_projectiles Projectiles alive");
+        let _ = writeln!(body, "# TYPE pixel_demolition_projectiles gauge");
+        // Per match only, sum() gives the total
+        for (code, stats) in match_stats.iter() {
+            let _ = writeln!(
+                body,
+                "pixel_demolition_projectiles{{match=\"{}\"}} {}",
+                code,
+                stats.projectiles,
+            );
+        }
+
+        let _ = writeln!(body, "# HELP pixel_demolition_match_outcomes_total How matches ended");
+        let _ = writeln!(body, "# TYPE pixel_demolition_match_outcomes_total counter");
+        for outcome in 0..Self::OUTCOME_NAMES.len() {
+            let _ = writeln!(
+                body,
+                "pixel_demolition_match_outcomes_total{{outcome=\"{}\"}} {}",
+                Self::OUTCOME_NAMES[outcome],
+                self.outcomes[outcome].load(Ordering::Relaxed),
+            );
+        }
+
+        return body;
+    }
+}
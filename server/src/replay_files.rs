@@ -0,0 +1,45 @@
+use std::path::Path;
+use std::sync::Arc;
+use warp::http::{Response, StatusCode};
+
+use crate::game_matches::GameMatches;
+use crate::replay_recorder::ReplayRecorder;
+
+pub struct ReplayFiles {}
+
+impl ReplayFiles {
+    // Serves recorded matches to the client's replay viewer
+    pub async fn handle(
+        name: String,
+        game_matches: Arc<GameMatches>,
+    ) -> Result<Box<dyn warp::Reply>, std::convert::Infallible> {
+        let replay_dir = &game_matches.config.replay_dir;
+
+        if replay_dir.is_empty() || !Self::valid_name(&name) {
+            return Ok(Box::new(StatusCode::NOT_FOUND));
+        }
+
+        match tokio::fs::read(Path::new(replay_dir).join(&name)).await {
+            Ok(data) => {
+                Ok(Box::new(Response::builder()
+                    .status(200)
+                    .header("Content-Type", "application/octet-stream")
+                    .body(data)
+                    .unwrap()))
+            },
+            Err(_) => Ok(Box::new(StatusCode::NOT_FOUND)),
+        }
+    }
+
+    // Only names the engine could have written, so requests can't leave the replay directory
+    fn valid_name(name: &String) -> bool {
+        let stem = name.strip_suffix(&format!(".{}", ReplayRecorder::EXTENSION));
+
+        return match stem {
+            Some(stem) => {
+                !stem.is_empty() && stem.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            },
+            None => false,
+        };
+    }
+}
@@ -0,0 +1,114 @@
+use pixel_demolition_common::match_settings::MatchSettings;
+use pixel_demolition_common::replay::Replay;
+
+// Collects a match's outbound messages in memory, the engine writes them out once the match ends
+pub struct ReplayRecorder {
+    data: Vec<u8>,
+    tick: u32,
+    // Tick of the last tick record, so ticks where nothing was sent take no space
+    recorded_tick: Option<u32>,
+    // Set once the size limit is hit, anything after that is dropped
+    pub truncated: bool,
+}
+
+impl ReplayRecorder {
+    pub const EXTENSION: &'static str = "pdreplay";
+
+    // Stop recording a runaway match at 64 MB
+    pub const MAX_LEN: usize = 64*1024*1024;
+
+    pub fn new(
+        server_tick: i32,
+        names: &Vec<String>,
+        settings: &MatchSettings,
+        map_png: &[u8],
+    ) -> ReplayRecorder {
+        ReplayRecorder {
+            data: Replay::header(server_tick, names, settings, map_png),
+            tick: 0,
+            recorded_tick: None,
+            truncated: false,
+        }
+    }
+
+    pub fn set_tick(&mut self, tick: u32) {
+        self.tick = tick;
+    }
+
+    pub fn record(&mut self, recipient: u8, message: &Vec<u8>) {
+        if self.truncated {
+            return;
+        }
+
+        if self.data.len() + message.len() > Self::MAX_LEN {
+            self.truncated = true;
+            return;
+        }
+
+        if self.recorded_tick != Some(self.tick) {
+            Replay::push_tick(&mut self.data, self.tick);
+            self.recorded_tick = Some(self.tick);
+        }
+
+        Replay::push_message(&mut self.data, recipient, message);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        return self.data;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pixel_demolition_common::server_message::ServerMessage;
+
+    fn recording() -> Vec<u8> {
+        let names = vec![String::from("A"), String::from("B")];
+        let mut recorder = ReplayRecorder::new(30, &names, &MatchSettings::new(), b"not really a png");
+
+        recorder.record(Replay::ALL, &vec![0x83, 1, 2]);
+        recorder.record(0, &vec![0x84]);
+
+        // Nothing sent in tick 1, so it has no tick record
+        recorder.set_tick(1);
+        recorder.set_tick(2);
+        // Long enough to need a second length byte
+        recorder.record(1, &vec![0x86; 300]);
+
+        return recorder.finish();
+    }
+
+    #[test]
+    fn recordings_parse_back() {
+        let (server_tick, player_list_message, settings_message, records) = Replay::parse(&recording()).unwrap();
+
+        let names = vec![String::from("A"), String::from("B")];
+
+        assert_eq!(server_tick, 30);
+        assert_eq!(player_list_message, ServerMessage::PlayerList { names }.encode());
+        assert_eq!(settings_message, ServerMessage::MatchSettings(MatchSettings::new()).encode());
+        assert_eq!(records, vec![
+            (0, Replay::ALL, vec![0x83, 1, 2]),
+            (0, 0, vec![0x84]),
+            (2, 1, vec![0x86; 300]),
+        ]);
+    }
+
+    #[test]
+    fn damaged_recordings_are_rejected() {
+        let data = recording();
+
+        assert_eq!(Replay::parse(&data[..data.len() - 1]).err(), Some("Replay message cut short"));
+        assert_eq!(Replay::parse(&data[..20]).err(), Some("Replay header cut short"));
+        assert_eq!(Replay::parse(&data[..3]).err(), Some("Not a replay file"));
+
+        let mut bad_magic = data.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(Replay::parse(&bad_magic).err(), Some("Not a replay file"));
+
+        let mut bad_version = data.clone();
+        bad_version[4] = Replay::VERSION + 1;
+        assert_eq!(Replay::parse(&bad_version).err(), Some("Unsupported replay version"));
+    }
+}
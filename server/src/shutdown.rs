@@ -0,0 +1,47 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+
+pub struct Shutdown {
+    started: AtomicBool,
+}
+
+impl Shutdown {
+    // Give running matches 60 seconds to finish before forcing them to end
+    pub const GRACE_PERIOD: i32 = 60;
+
+    pub fn new() -> Shutdown {
+        Shutdown {
+            started: AtomicBool::new(false),
+        }
+    }
+
+    pub fn start(&self) {
+        self.started.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_started(&self) -> bool {
+        return self.started.load(Ordering::SeqCst);
+    }
+
+    // Resolves once the process receives SIGINT or SIGTERM
+    pub async fn wait_for_signal() {
+        #[cfg(unix)]
+        {
+            let mut terminate = signal(SignalKind::terminate())
+                .expect("Unable to listen for SIGTERM");
+
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => tracing::info!("Received SIGINT"),
+                _ = terminate.recv() => tracing::info!("Received SIGTERM"),
+            }
+        }
+
+        #[cfg(not(unix))]
+        {
+            let _ = tokio::signal::ctrl_c().await;
+            tracing::info!("Received SIGINT");
+        }
+    }
+}
@@ -0,0 +1,764 @@
+use image::{self, RgbaImage};
+use rand::Rng;
+use tracing::debug;
+
+use pixel_demolition_common::collision_system::CollisionSystem;
+use pixel_demolition_common::match_settings::MatchSettings;
+use pixel_demolition_common::movement_system::MovementSystem;
+use pixel_demolition_common::player::Player;
+use pixel_demolition_common::player_input::PlayerInput;
+use pixel_demolition_common::projectile::Projectile;
+use pixel_demolition_common::proto::Proto;
+use pixel_demolition_common::vel_system::VelSystem;
+use pixel_demolition_common::weapon::Weapon;
+
+use crate::config::Config;
+
+// What a player asked to do during a tick, the engine decodes these from their messages
+#[derive(Clone, Debug, PartialEq)]
+pub enum PlayerAction {
+    Input(PlayerInput),
+    TakeWeapon,
+    TriggerPulled,
+    TriggerReleased,
+}
+
+// Everything that happened during a tick, in the order it happened
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    // Only the player who moved needs to know which of their inputs were applied
+    InputApplied { player_i: usize, seq: u32, x: f32, y: f32, vel_y: f32, jumps: i32 },
+    Aimed { player_i: usize, angle: f32 },
+    Moved { player_i: usize, x: f32, y: f32 },
+    // The player broke the rules, reason is the kick reason it counts towards
+    InputRejected { player_i: usize, reason: u8 },
+    WeaponTaken { player_i: usize, weapon_id: u32, weapon_type: usize },
+    ProjectileFired { player_i: usize, projectile: Projectile },
+    // Fell off the map without exploding
+    ProjectileRemoved { projectile_id: u32 },
+    ProjectileExploded { projectile: Projectile },
+    HealthChanged { player_i: usize, health: f32 },
+    PlayerKilled { player_i: usize, killer_i: Option<usize> },
+    PlayerRespawned { player_i: usize, x: f32, y: f32 },
+    WeaponSpawned { weapon: Weapon },
+    GameWon { player_i: usize },
+}
+
+// The game rules for one match. Knows nothing about clients or sockets, so it can be run
+// without a server
+pub struct Simulation {
+    pub players: Vec<Player>,
+    pub projectiles: Vec<Projectile>,
+    pub ground_weapons: Vec<Weapon>,
+    pub map: RgbaImage,
+    pub settings: MatchSettings,
+    // In ms, how much movement each player can still send. Topped up every tick so nobody can
+    // move faster than real time
+    input_budgets: Vec<f32>,
+    // In ms, movement dropped for going over budget during the current speed window
+    overruns: Vec<f32>,
+    speed_window_ticks: i32,
+    // Players that left for good stay dead for the rest of the match
+    retired: Vec<bool>,
+    ticks_since_weapon_spawn: i32,
+    // Shared by projectiles and ground weapons, ids are never reused within a match
+    next_entity_id: u32,
+    events: Vec<Event>,
+}
+
+impl Simulation {
+    pub const PICKUP_RANGE: f32 = 20.0;
+
+    const CLEAR_PIXEL: image::Rgba<u8> = image::Rgba::<u8>([0, 0, 0, 0]);
+
+    // Run collision checks for projectiles 10 times per tick
+    const PROJECTILE_INTERP_RATE: i32 = 10;
+
+    // Clients can bank up to a second of movement, enough to catch up after a lag spike
+    const INPUT_BUDGET: f32 = 1000.0;
+
+    // Dropped movement only counts against a client once it adds up to more than 2 seconds
+    // within 5, which a lag spike alone can't do
+    const SPEED_WINDOW: i32 = 5;
+    const SPEED_TOLERANCE: f32 = 2000.0;
+
+    // Players start out somewhere random on the map
+    pub fn new(players: Vec<Player>, map: RgbaImage, settings: MatchSettings) -> Simulation {
+        let mut players = players;
+
+        for player in players.iter_mut() {
+            player.apply_settings(&settings);
+
+            let (x, y) = Self::get_rand_pos(&map);
+            player.x = x;
+            player.y = y;
+        }
+
+        let player_count = players.len();
+
+        return Simulation {
+            players,
+            projectiles: Vec::new(),
+            ground_weapons: Vec::new(),
+            map,
+            settings,
+            input_budgets: vec![0.0; player_count],
+            overruns: vec![0.0; player_count],
+            speed_window_ticks: 0,
+            retired: vec![false; player_count],
+            ticks_since_weapon_spawn: -1,
+            next_entity_id: 0,
+            events: Vec::new(),
+        };
+    }
+
+    // Players joining mid-game skip the wait and spawn straight away, returns their index
+    pub fn add_player(&mut self, name: String) -> usize {
+        let (x, y) = Self::get_rand_pos(&self.map);
+
+        let mut new_player = Player::new(name);
+        new_player.apply_settings(&self.settings);
+        new_player.respawn(x, y, &self.settings);
+
+        self.players.push(new_player);
+        self.input_budgets.push(0.0);
+        self.overruns.push(0.0);
+        self.retired.push(false);
+
+        return self.players.len() - 1;
+    }
+
+    // Takes the player out of play for the rest of the match. Returns false if they already were
+    pub fn retire_player(&mut self, player_i: usize) -> bool {
+        if self.retired[player_i] {
+            return false;
+        }
+
+        self.retired[player_i] = true;
+
+        let player = &mut self.players[player_i];
+
+        if player.alive {
+            player.alive = false;
+            player.weapon_type = None;
+            player.ammo = 0;
+
+            self.events.push(Event::PlayerKilled { player_i, killer_i: None });
+        }
+
+        return true;
+    }
+
+    // actions has an entry for every player, in the order they were sent
+    pub fn tick(&mut self, actions: &Vec<Vec<PlayerAction>>, config: &Config) -> Vec<Event> {
+        self.speed_window_ticks += 1;
+
+        if self.speed_window_ticks >= config.secs_to_ticks(Self::SPEED_WINDOW) {
+            self.speed_window_ticks = 0;
+
+            for player_i in 0..self.players.len() {
+                if self.overruns[player_i] > Self::SPEED_TOLERANCE {
+                    self.events.push(Event::InputRejected { player_i, reason: Proto::KICK_REASON_SPEED });
+                }
+
+                self.overruns[player_i] = 0.0;
+            }
+        }
+
+        for player_i in 0..self.players.len() {
+            self.input_budgets[player_i] = (self.input_budgets[player_i] + config.server_tick as f32)
+                .min(Self::INPUT_BUDGET);
+
+            let player = &self.players[player_i];
+            let (last_x, last_y, last_angle, last_input_seq)
+                = (player.x, player.y, player.angle, player.input_seq);
+
+            for action in &actions[player_i] {
+                match action {
+                    PlayerAction::Input(input) => self.apply_input(player_i, input),
+                    PlayerAction::TakeWeapon => self.take_weapon(player_i),
+                    PlayerAction::TriggerPulled => self.players[player_i].trigger_pulled = true,
+                    PlayerAction::TriggerReleased => self.players[player_i].trigger_pulled = false,
+                }
+            }
+
+            let player = &self.players[player_i];
+
+            if player.input_seq != last_input_seq {
+                self.events.push(Event::InputApplied {
+                    player_i,
+                    seq: player.input_seq,
+                    x: player.x,
+                    y: player.y,
+                    vel_y: player.vel_y,
+                    jumps: player.jumps,
+                });
+            }
+
+            if player.angle != last_angle {
+                self.events.push(Event::Aimed { player_i, angle: player.angle });
+            }
+
+            if player.x != last_x || player.y != last_y {
+                self.events.push(Event::Moved { player_i, x: player.x, y: player.y });
+
+                if CollisionSystem::player_oob(player, &self.map) {
+                    self.kill_player(player_i, None);
+                }
+            }
+        }
+
+        self.update_weapons(config);
+        self.update_projectiles(config);
+        self.update_respawns(config);
+
+        if self.ticks_since_weapon_spawn < 0
+            || self.ticks_since_weapon_spawn >= config.secs_to_ticks(self.settings.weapon_spawn_interval)
+        {
+            self.spawn_weapons();
+            self.ticks_since_weapon_spawn = 0;
+        } else {
+            self.ticks_since_weapon_spawn += 1;
+        }
+
+        for player_i in 0..self.players.len() {
+            if self.players[player_i].kills >= self.settings.kills_to_win {
+                self.events.push(Event::GameWon { player_i });
+                break;
+            }
+        }
+
+        return std::mem::take(&mut self.events);
+    }
+
+    // Inputs sent faster than real time are skipped but still count as applied, so the client
+    // falls back to the server's position when it gets the ack
+    fn apply_input(&mut self, player_i: usize, input: &PlayerInput) {
+        let player = &mut self.players[player_i];
+
+        // Repeated or out of order
+        if input.seq <= player.input_seq {
+            return;
+        }
+
+        player.input_seq = input.seq;
+
+        if !player.alive {
+            return;
+        }
+
+        // The client clamps its frame time the same way before sending
+        if input.time_elapsed < 0.0 || input.time_elapsed > PlayerInput::MAX_TIME_ELAPSED {
+            self.events.push(Event::InputRejected { player_i, reason: Proto::KICK_REASON_INVALID_MESSAGE });
+            return;
+        }
+
+        // Movement is simulated here so it can't go faster than MOVE_SPEED and JUMP_VEL allow,
+        // the only way to speed up is claiming more time than has passed
+        if input.time_elapsed > self.input_budgets[player_i] {
+            debug!(player_i, seq = input.seq, "Input over budget");
+            self.overruns[player_i] += input.time_elapsed;
+            return;
+        }
+
+        self.input_budgets[player_i] -= input.time_elapsed;
+
+        let (last_x, last_y) = (player.x, player.y);
+
+        MovementSystem::apply_input(player, input, &self.map, &self.settings);
+
+        // A long fall can carry the player through a thin ledge before collisions catch it.
+        // Not the client's fault, so no strike, it just lands from where it was
+        if CollisionSystem::player_in_terrain(player, &self.map) {
+            debug!(player_i, seq = input.seq, "Input moved into terrain");
+            player.x = last_x;
+            player.y = last_y;
+            player.vel_y = 0.0;
+        }
+    }
+
+    fn take_weapon(&mut self, player_i: usize) {
+        let player = &mut self.players[player_i];
+
+        for weapon_i in 0..self.ground_weapons.len() {
+            if (player.x - self.ground_weapons[weapon_i].x).abs() < Self::PICKUP_RANGE
+                && (player.y - self.ground_weapons[weapon_i].y).abs() < Self::PICKUP_RANGE
+            {
+                let weapon = self.ground_weapons.remove(weapon_i);
+                let weapon_type = weapon.weapon_type;
+
+                player.assign_weapon(weapon_type);
+
+                self.events.push(Event::WeaponTaken { player_i, weapon_id: weapon.id, weapon_type });
+
+                break;
+            }
+        }
+    }
+
+    fn update_weapons(&mut self, config: &Config) {
+        for player_i in 0..self.players.len() {
+            let player = &mut self.players[player_i];
+
+            if player.weapon_type.is_none() {
+                continue;
+            }
+
+            let weapon_type = player.weapon_type.unwrap();
+
+            let ticks_per_fire = config.scale_ticks(Weapon::WEAPON_TYPES[weapon_type].ticks_per_fire);
+
+            if player.ticks_since_last_fire > -1 && player.ticks_since_last_fire <= ticks_per_fire {
+                player.ticks_since_last_fire += 1;
+                continue;
+            }
+
+            if !player.trigger_pulled {
+                continue;
+            }
+
+            if player.ammo <= 0 {
+                continue;
+            }
+
+            let projectile_type = Weapon::WEAPON_TYPES[weapon_type].projectile_type;
+
+            let init_vel = Projectile::PROJECTILE_TYPES[projectile_type].init_vel;
+
+            let vel_x = -player.angle.cos() * init_vel;
+            let vel_y = -player.angle.sin() * init_vel;
+
+            let offset_x = -player.angle.cos() * (Weapon::WEAPON_WIDTH) as f32;
+            let offset_y = -player.angle.sin() * (Weapon::WEAPON_WIDTH) as f32;
+
+            let init_x = player.x + offset_x;
+            let init_y = player.y + offset_y;
+
+            let new_projectile = Projectile {
+                id: self.next_entity_id,
+                projectile_type,
+                x: init_x,
+                y: init_y,
+                vel_x,
+                vel_y,
+                owner: player_i,
+            };
+
+            self.next_entity_id += 1;
+
+            self.events.push(Event::ProjectileFired { player_i, projectile: new_projectile.clone() });
+
+            self.projectiles.push(new_projectile);
+
+            player.ticks_since_last_fire = 0;
+
+            player.ammo -= 1;
+        }
+    }
+
+    fn update_projectiles(&mut self, config: &Config) {
+        // Track destroyed projectiles as we iterate
+        let mut destroyed_projectiles: usize = 0;
+
+        'per_projectile: for projectile_i in 0..self.projectiles.len() {
+            // We will be popping projectiles off the Vec as we go so adjust based on that
+            let projectile_i = projectile_i - destroyed_projectiles;
+
+            if projectile_i >= self.projectiles.len() {
+                break;
+            }
+
+            // Too much time passes during each tick to do good collisions, so interpolate
+            for _ in 0..(Self::PROJECTILE_INTERP_RATE as usize) {
+                VelSystem::update_projectile(
+                    &mut self.projectiles[projectile_i],
+                    (config.server_tick / Self::PROJECTILE_INTERP_RATE) as f32,
+                );
+
+                // If the projectile fell off the screen destroy it without an explosion
+                if CollisionSystem::projectile_oob(&self.projectiles[projectile_i], &self.map) {
+                    let projectile = self.projectiles.remove(projectile_i);
+                    destroyed_projectiles += 1;
+
+                    debug!(projectile_id = projectile.id, "Projectile out of bounds");
+
+                    self.events.push(Event::ProjectileRemoved { projectile_id: projectile.id });
+
+                    continue 'per_projectile;
+                }
+
+                if CollisionSystem::projectile_collide_map(&self.projectiles[projectile_i], &mut self.map) {
+                    let projectile = self.projectiles.remove(projectile_i);
+                    destroyed_projectiles += 1;
+
+                    self.explode(projectile);
+
+                    continue 'per_projectile;
+                }
+
+                for player_i in 0..self.players.len() {
+                    if !self.players[player_i].alive {
+                        continue;
+                    }
+
+                    if CollisionSystem::point_collide_player(
+                        self.projectiles[projectile_i].x,
+                        self.projectiles[projectile_i].y,
+                        &self.players[player_i],
+                    ) {
+                        let projectile = self.projectiles.remove(projectile_i);
+                        destroyed_projectiles += 1;
+
+                        self.explode(projectile);
+
+                        continue 'per_projectile;
+                    }
+                }
+            }
+        }
+    }
+
+    fn explode(&mut self, projectile: Projectile) {
+        let destroy_pixels = projectile.draw_explosion();
+
+        // Keep track of which players were damaged so we can tell them their new health
+        let mut players_health_affected: Vec<usize> = Vec::new();
+
+        for destroy_pixel in destroy_pixels {
+            let (destroy_pixel_x, destroy_pixel_y) = destroy_pixel;
+
+            for player_i in 0..self.players.len() {
+                if CollisionSystem::point_collide_player(
+                    destroy_pixel_x as f32,
+                    destroy_pixel_y as f32,
+                    &self.players[player_i],
+                ) {
+                    if !self.players[player_i].alive {
+                        continue;
+                    }
+
+                    let projectile_type = projectile.projectile_type;
+                    let projectile_damage = Projectile::PROJECTILE_TYPES[projectile_type].damage;
+
+                    self.players[player_i].health -= projectile_damage;
+
+                    if !players_health_affected.contains(&player_i) {
+                        players_health_affected.push(player_i);
+                    }
+
+                    if self.players[player_i].health < 0.0 {
+                        self.kill_player(player_i, Some(projectile.owner));
+                        continue;
+                    }
+                }
+            }
+
+            // Explosions can reach past the edge of the map
+            if destroy_pixel_x < 0
+                || destroy_pixel_x >= self.map.width() as i32
+                || destroy_pixel_y < 0
+                || destroy_pixel_y >= self.map.height() as i32
+            {
+                continue;
+            }
+
+            if self.map.get_pixel(destroy_pixel_x as u32, destroy_pixel_y as u32)[3] > 0 {
+                self.map.put_pixel(destroy_pixel_x as u32, destroy_pixel_y as u32, Self::CLEAR_PIXEL);
+            }
+        }
+
+        self.events.push(Event::ProjectileExploded { projectile });
+
+        for player_i in players_health_affected {
+            self.events.push(Event::HealthChanged { player_i, health: self.players[player_i].health });
+        }
+    }
+
+    fn kill_player(&mut self, killed_player_i: usize, killer_i: Option<usize>) {
+        if let Some(killer_i) = killer_i {
+            if killer_i != killed_player_i {
+                self.players[killer_i].kills += 1;
+            }
+        }
+
+        self.players[killed_player_i].kill(&self.settings);
+
+        self.events.push(Event::PlayerKilled { player_i: killed_player_i, killer_i });
+    }
+
+    fn update_respawns(&mut self, config: &Config) {
+        for player_i in 0..self.players.len() {
+            let player = &mut self.players[player_i];
+
+            if player.alive || self.retired[player_i] {
+                continue;
+            }
+
+            player.time_to_respawn -= config.server_tick;
+
+            if player.time_to_respawn > 0 {
+                continue;
+            }
+
+            let (x, y) = Self::get_rand_pos(&self.map);
+            player.respawn(x, y, &self.settings);
+
+            self.events.push(Event::PlayerRespawned { player_i, x, y });
+        }
+    }
+
+    fn spawn_weapons(&mut self) {
+        for _ in 0..self.settings.weapon_spawn_count {
+            // The game state only has a byte for the weapon count
+            if self.ground_weapons.len() >= u8::MAX as usize {
+                return;
+            }
+
+            let (x, y) = Self::get_rand_pos(&self.map);
+            let weapon_type = rand::thread_rng().gen_range(0..Weapon::WEAPON_TYPES.len());
+
+            let weapon_type = weapon_type as usize;
+
+            let new_weapon = Weapon::new(self.next_entity_id, weapon_type, x, y);
+            self.next_entity_id += 1;
+
+            self.events.push(Event::WeaponSpawned { weapon: new_weapon.clone() });
+
+            self.ground_weapons.push(new_weapon);
+        }
+    }
+
+    fn get_rand_pos(map: &RgbaImage) -> (f32, f32) {
+        let mut rng = rand::thread_rng();
+        loop {
+            let x = rng.gen_range(0..map.width() as usize);
+            let y = rng.gen_range(0..map.height() as usize);
+            let x = x as u32;
+            let mut y = y as u32;
+            while y < map.height() {
+                // Find the ground
+                if map.get_pixel(x, y)[3] > 0 {
+                    // Move up to find the space right above the ground
+                    while map.get_pixel(x, y)[3] > 0 {
+                        y -= 1;
+                    }
+                    // Move it up to half the character's height
+                    y -= Player::PLAYER_HEIGHT / 2;
+                    return (x as f32, y as f32);
+                }
+                y += 1;
+            }
+            // If a location wasn't found that means the coordinates didn't have any ground below
+            // them, so start over with new coordinates
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Flat ground along the bottom so there is always somewhere to spawn
+    fn test_map() -> RgbaImage {
+        let mut map = RgbaImage::new(400, 300);
+
+        for y in 250..300 {
+            for x in 0..400 {
+                map.put_pixel(x, y, image::Rgba::<u8>([100, 100, 100, 255]));
+            }
+        }
+
+        return map;
+    }
+
+    // Two players standing apart with nothing else going on
+    fn test_simulation() -> Simulation {
+        let players = vec![Player::new(String::from("A")), Player::new(String::from("B"))];
+
+        let mut simulation = Simulation::new(players, test_map(), MatchSettings::new());
+        simulation.players[0].x = 100.0;
+        simulation.players[0].y = 200.0;
+        simulation.players[1].x = 300.0;
+        simulation.players[1].y = 200.0;
+
+        return simulation;
+    }
+
+    // A bullet from player_i sitting right on top of the target
+    fn shoot(simulation: &mut Simulation, player_i: usize, target_i: usize) {
+        let target = &simulation.players[target_i];
+
+        simulation.projectiles.push(Projectile {
+            id: 1000,
+            projectile_type: Projectile::TYPE_BULLET,
+            x: target.x,
+            y: target.y,
+            vel_x: 0.0,
+            vel_y: 0.0,
+            owner: player_i,
+        });
+    }
+
+    fn no_actions() -> Vec<Vec<PlayerAction>> {
+        return vec![Vec::new(), Vec::new()];
+    }
+
+    fn input(seq: u32, time_elapsed: f32) -> PlayerAction {
+        return PlayerAction::Input(PlayerInput {
+            seq,
+            left: false,
+            right: false,
+            jump: false,
+            angle: 0.0,
+            time_elapsed,
+        });
+    }
+
+    fn speed_strikes(events: &Vec<Event>) -> usize {
+        return events
+            .iter()
+            .filter(|event| **event == Event::InputRejected { player_i: 0, reason: Proto::KICK_REASON_SPEED })
+            .count();
+    }
+
+    #[test]
+    fn lag_spikes_are_caught_up_without_a_strike() {
+        let config = Config::new();
+        let mut simulation = test_simulation();
+        let mut strikes = 0;
+
+        for _ in 0..20 {
+            strikes += speed_strikes(&simulation.tick(&no_actions(), &config));
+        }
+
+        // A second's worth of inputs arriving at once
+        let burst = (1..=10).map(|seq| input(seq, 100.0)).collect();
+        strikes += speed_strikes(&simulation.tick(&vec![burst, Vec::new()], &config));
+
+        let budget_left = simulation.input_budgets[0];
+
+        for seq in 11..200 {
+            let actions = vec![vec![input(seq, config.server_tick as f32)], Vec::new()];
+            strikes += speed_strikes(&simulation.tick(&actions, &config));
+        }
+
+        assert_eq!(budget_left, 0.0);
+        assert_eq!(simulation.players[0].input_seq, 199);
+        assert_eq!(strikes, 0);
+    }
+
+    #[test]
+    fn sustained_speeding_gets_a_strike() {
+        let config = Config::new();
+        let mut simulation = test_simulation();
+        let mut strikes = 0;
+
+        // Claiming three times as much time as has passed
+        for tick in 0..(config.secs_to_ticks(Simulation::SPEED_WINDOW) as u32) {
+            let actions = vec![vec![input(tick * 2 + 1, 90.0), input(tick * 2 + 2, 90.0)], Vec::new()];
+            strikes += speed_strikes(&simulation.tick(&actions, &config));
+        }
+
+        assert_eq!(strikes, 1);
+    }
+
+    #[test]
+    fn projectiles_damage_players_they_hit() {
+        let config = Config::new();
+        let mut simulation = test_simulation();
+
+        shoot(&mut simulation, 0, 1);
+        let events = simulation.tick(&no_actions(), &config);
+
+        let health = MatchSettings::new().max_health - Projectile::PROJECTILE_TYPES[Projectile::TYPE_BULLET].damage;
+
+        assert_eq!(simulation.players[1].health, health);
+        assert_eq!(simulation.players[0].health, MatchSettings::new().max_health);
+        assert!(simulation.projectiles.is_empty());
+        assert!(events.contains(&Event::HealthChanged { player_i: 1, health }));
+        assert!(events.iter().any(|event| matches!(event, Event::ProjectileExploded { .. })));
+    }
+
+    #[test]
+    fn kills_are_credited_to_the_shooter() {
+        let config = Config::new();
+        let mut simulation = test_simulation();
+
+        simulation.players[1].health = 0.1;
+        shoot(&mut simulation, 0, 1);
+        let events = simulation.tick(&no_actions(), &config);
+
+        assert!(!simulation.players[1].alive);
+        assert_eq!(simulation.players[1].deaths, 1);
+        assert_eq!(simulation.players[0].kills, 1);
+        assert!(events.contains(&Event::PlayerKilled { player_i: 1, killer_i: Some(0) }));
+
+        // Dead players can't be hit again
+        shoot(&mut simulation, 0, 1);
+        let events = simulation.tick(&no_actions(), &config);
+
+        assert_eq!(simulation.players[0].kills, 1);
+        assert!(!events.iter().any(|event| matches!(event, Event::PlayerKilled { .. })));
+    }
+
+    #[test]
+    fn players_respawn_once_their_time_is_up() {
+        let config = Config::new();
+        let mut simulation = test_simulation();
+
+        simulation.players[1].kill(&simulation.settings);
+
+        // Rounded up, a partial tick still has to pass
+        let respawn_ticks = (simulation.settings.time_to_respawn + config.server_tick - 1) / config.server_tick;
+
+        for _ in 1..respawn_ticks {
+            let events = simulation.tick(&no_actions(), &config);
+
+            assert!(!simulation.players[1].alive);
+            assert!(!events.iter().any(|event| matches!(event, Event::PlayerRespawned { .. })));
+        }
+
+        let events = simulation.tick(&no_actions(), &config);
+
+        assert!(simulation.players[1].alive);
+        assert_eq!(simulation.players[1].health, simulation.settings.max_health);
+
+        let (x, y) = (simulation.players[1].x, simulation.players[1].y);
+        assert!(events.contains(&Event::PlayerRespawned { player_i: 1, x, y }));
+    }
+
+    #[test]
+    fn retired_players_never_respawn() {
+        let config = Config::new();
+        let mut simulation = test_simulation();
+
+        assert!(simulation.retire_player(1));
+        assert!(!simulation.retire_player(1));
+
+        let events = simulation.tick(&no_actions(), &config);
+        assert!(events.contains(&Event::PlayerKilled { player_i: 1, killer_i: None }));
+
+        for _ in 0..=(simulation.settings.time_to_respawn / config.server_tick + 1) {
+            simulation.tick(&no_actions(), &config);
+        }
+
+        assert!(!simulation.players[1].alive);
+    }
+
+    #[test]
+    fn the_game_is_won_at_the_kill_limit() {
+        let config = Config::new();
+        let mut simulation = test_simulation();
+
+        simulation.players[0].kills = simulation.settings.kills_to_win - 1;
+
+        let events = simulation.tick(&no_actions(), &config);
+        assert!(!events.iter().any(|event| matches!(event, Event::GameWon { .. })));
+
+        simulation.players[1].health = 0.1;
+        shoot(&mut simulation, 0, 1);
+        let events = simulation.tick(&no_actions(), &config);
+
+        assert_eq!(events.last(), Some(&Event::GameWon { player_i: 0 }));
+    }
+}
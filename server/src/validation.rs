@@ -0,0 +1,178 @@
+use pixel_demolition_common::proto::Proto;
+
+use crate::config::Config;
+
+// Keeps track of how a client has misbehaved. Each kind of abuse counts once a tick at most so a
+// single lag spike can't get an honest player kicked, and strikes wear off over time
+pub struct Validation {
+    strikes: i32,
+    // Kinds of abuse seen this tick, as kick reasons
+    violations: Vec<u8>,
+    ticks_since_strike: i32,
+    // Messages are counted over one second windows
+    window_ticks: i32,
+    window_messages: i32,
+}
+
+impl Validation {
+    pub const MAX_STRIKES: i32 = 5;
+
+    // Clients send an input every frame, so this leaves room for high refresh rate screens
+    pub const MAX_MESSAGES_PER_SEC: i32 = 300;
+
+    // Forgive a strike for every 10 seconds without one
+    pub const STRIKE_DECAY: i32 = 10;
+
+    pub fn new() -> Validation {
+        Validation {
+            strikes: 0,
+            violations: Vec::new(),
+            ticks_since_strike: 0,
+            window_ticks: 0,
+            window_messages: 0,
+        }
+    }
+
+    // Returns false once the client sent more than it is allowed this second, the message
+    // should then be dropped
+    pub fn allow_message(&mut self) -> bool {
+        self.window_messages += 1;
+
+        if self.window_messages > Self::MAX_MESSAGES_PER_SEC {
+            self.flag(Proto::KICK_REASON_FLOOD);
+            return false;
+        }
+
+        return true;
+    }
+
+    pub fn flag(&mut self, reason: u8) {
+        if !self.violations.contains(&reason) {
+            self.violations.push(reason);
+        }
+    }
+
+    // Called once a tick, returns the reason to kick the client with once it has run out of
+    // strikes
+    pub fn end_tick(&mut self, config: &Config) -> Option<u8> {
+        self.window_ticks += 1;
+
+        if self.window_ticks >= config.secs_to_ticks(1) {
+            self.window_ticks = 0;
+            self.window_messages = 0;
+        }
+
+        if self.violations.len() < 1 {
+            self.ticks_since_strike += 1;
+
+            if self.strikes > 0 && self.ticks_since_strike >= config.secs_to_ticks(Self::STRIKE_DECAY) {
+                self.strikes -= 1;
+                self.ticks_since_strike = 0;
+            }
+
+            return None;
+        }
+
+        self.strikes += self.violations.len() as i32;
+        self.ticks_since_strike = 0;
+
+        let reason = self.violations.pop().unwrap();
+        self.violations.clear();
+
+        if self.strikes >= Self::MAX_STRIKES {
+            return Some(reason);
+        }
+
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ticks where the client did nothing wrong
+    fn clean_ticks(validation: &mut Validation, config: &Config, ticks: i32) {
+        for _ in 0..ticks {
+            assert_eq!(validation.end_tick(config), None);
+        }
+    }
+
+    #[test]
+    fn messages_over_the_rate_limit_are_dropped() {
+        let config = Config::new();
+        let mut validation = Validation::new();
+
+        for _ in 0..Validation::MAX_MESSAGES_PER_SEC {
+            assert!(validation.allow_message());
+        }
+
+        assert!(!validation.allow_message());
+        assert_eq!(validation.end_tick(&config), None);
+        assert_eq!(validation.strikes, 1);
+
+        // The count starts over with the next second
+        clean_ticks(&mut validation, &config, config.secs_to_ticks(1));
+        assert!(validation.allow_message());
+    }
+
+    #[test]
+    fn clients_under_the_limit_never_get_a_strike() {
+        let config = Config::new();
+        let mut validation = Validation::new();
+
+        let per_tick = Validation::MAX_MESSAGES_PER_SEC / config.secs_to_ticks(1);
+
+        for _ in 0..config.secs_to_ticks(60) {
+            for _ in 0..per_tick {
+                assert!(validation.allow_message());
+            }
+
+            assert_eq!(validation.end_tick(&config), None);
+        }
+
+        assert_eq!(validation.strikes, 0);
+    }
+
+    #[test]
+    fn strikes_add_up_to_a_kick() {
+        let config = Config::new();
+        let mut validation = Validation::new();
+
+        for _ in 1..Validation::MAX_STRIKES {
+            // The same abuse twice in a tick is only one strike
+            validation.flag(Proto::KICK_REASON_SPEED);
+            validation.flag(Proto::KICK_REASON_SPEED);
+
+            assert_eq!(validation.end_tick(&config), None);
+        }
+
+        validation.flag(Proto::KICK_REASON_INVALID_MESSAGE);
+
+        assert_eq!(validation.end_tick(&config), Some(Proto::KICK_REASON_INVALID_MESSAGE));
+    }
+
+    #[test]
+    fn strikes_wear_off() {
+        let config = Config::new();
+        let mut validation = Validation::new();
+
+        for _ in 1..Validation::MAX_STRIKES {
+            validation.flag(Proto::KICK_REASON_SPEED);
+            assert_eq!(validation.end_tick(&config), None);
+        }
+
+        clean_ticks(&mut validation, &config, config.secs_to_ticks(Validation::STRIKE_DECAY) - 1);
+        assert_eq!(validation.strikes, Validation::MAX_STRIKES - 1);
+
+        clean_ticks(&mut validation, &config, 1);
+        assert_eq!(validation.strikes, Validation::MAX_STRIKES - 2);
+
+        // One strike was forgiven, so it takes two more for a kick
+        validation.flag(Proto::KICK_REASON_SPEED);
+        assert_eq!(validation.end_tick(&config), None);
+
+        validation.flag(Proto::KICK_REASON_SPEED);
+        assert_eq!(validation.end_tick(&config), Some(Proto::KICK_REASON_SPEED));
+    }
+}